@@ -1,187 +1,1585 @@
+// The `into_*` CPI helpers borrow the accounts struct rather than consuming it.
+#![allow(clippy::wrong_self_convention)]
+
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Mint, Token, TokenAccount, Transfer};
+use anchor_lang::solana_program::program_pack::Pack;
+use anchor_lang::solana_program::sysvar::instructions as sysvar_instructions;
+use anchor_lang::Discriminator;
+use anchor_spl::token::{self, Burn, CloseAccount, Mint, MintTo, Token, TokenAccount, Transfer};
+
+pub mod math;
 
 declare_id!("Hng6hDtW2VtYjJwx5RUH7zyuKpQFZMBhmkj17bNTVT18");
 
+// Seeds for the pool PDA (keyed by its swap state and two mints) and its vault token accounts.
+pub const POOL_SEED: &[u8] = b"pool";
+pub const VAULT_SEED: &[u8] = b"vault";
+// Seed for the PDA that owns a pool's token vaults and signs every payout.
+pub const VAULT_AUTHORITY_SEED: &[u8] = b"vault_authority";
+// Seed for the pool's LP mint; its mint authority is the vault authority.
+pub const LP_MINT_SEED: &[u8] = b"lp_mint";
+// Seed for an order's escrow token account, keyed by the order. The vault authority
+// owns it, so the same signer pays out of vaults and escrows.
+pub const ESCROW_SEED: &[u8] = b"escrow";
+// Seeds for limit orders, keyed by pool, owner and the owner's next nonce, and for the
+// per-user account that hands out nonces and lists the owner's open orders.
+pub const ORDER_SEED: &[u8] = b"order";
+pub const USER_ORDER_BOOK_SEED: &[u8] = b"user_order_book";
+pub const MAX_OPEN_ORDERS: usize = 32;
+// Seed for a pool's order book of resting limit orders, and the orders each side holds.
+pub const ORDER_BOOK_SEED: &[u8] = b"order_book";
+pub const ORDER_BOOK_DEPTH: usize = 64;
+// Seed for DCA orders, keyed like limit orders and sharing their nonces and index.
+pub const DCA_SEED: &[u8] = b"dca";
+// Seed for TWAMM long-term orders, keyed like limit orders and sharing their nonces and index.
+pub const LONG_TERM_ORDER_SEED: &[u8] = b"long_term_order";
+// Long-term orders end on multiples of TWAMM_INTERVAL seconds so that orders ending
+// together share one expiry entry; a pool tracks at most MAX_STREAM_EXPIRIES of them.
+pub const TWAMM_INTERVAL: i64 = 3_600;
+pub const MAX_STREAM_EXPIRIES: usize = 32;
+
+pub const LP_DECIMALS: u8 = 6;
+// LP tokens withheld from the first depositor so the share price can never be reset to zero.
+pub const MINIMUM_LIQUIDITY: u64 = 1_000;
+
+// Operation groups for `SwapState::paused`. Pool creation, admin instructions and
+// anything that only returns funds to their owner are never paused.
+pub const PAUSE_SWAPS: u8 = 1 << 0;
+pub const PAUSE_DEPOSITS: u8 = 1 << 1;
+pub const PAUSE_WITHDRAWALS: u8 = 1 << 2;
+pub const PAUSE_LIMIT_ORDERS: u8 = 1 << 3;
+pub const PAUSE_FLASH_SWAPS: u8 = 1 << 4;
+pub const PAUSE_ALL: u8 = PAUSE_SWAPS | PAUSE_DEPOSITS | PAUSE_WITHDRAWALS | PAUSE_LIMIT_ORDERS | PAUSE_FLASH_SWAPS;
+
+// Flash loan fee a new pool starts with, in basis points, and the most an admin can set.
+// The fee is added to the reserves, so it accrues to LPs.
+pub const DEFAULT_FLASH_FEE_BPS: u16 = 9;
+pub const MAX_FLASH_FEE_BPS: u16 = 1_000;
+// Anchor discriminator of `on_flash_swap(amount: u64, fee: u64)`, the callback a flash
+// swap borrower program must implement. The args follow as `FlashSwapCallback`.
+pub const FLASH_SWAP_CALLBACK_DISCRIMINATOR: [u8; 8] = [231, 227, 105, 50, 75, 86, 102, 148];
+
 #[program]
 mod comprehensive_token_swap {
     use super::*;
 
     pub fn initialize(ctx: Context<Initialize>, fee_rate: u64) -> Result<()> {
+        require!(fee_rate <= math::FEE_DENOMINATOR, ErrorCode::InvalidFeeRate);
         let swap = &mut ctx.accounts.swap;
+        swap.admin = ctx.accounts.user.key();
         swap.fee_rate = fee_rate;
-        swap.paused = false;
+        swap.paused = 0;
+        Ok(())
+    }
+
+    // Circuit breaker: `operations` is a mask of PAUSE_* flags, so e.g. swaps can be
+    // halted while withdrawals stay open for LPs.
+    pub fn pause(ctx: Context<AdminCtx>, operations: u8) -> Result<()> {
+        let swap = &mut ctx.accounts.swap;
+        swap.paused |= operations & PAUSE_ALL;
+
+        emit!(PauseEvent {
+            admin: swap.admin,
+            paused: swap.paused,
+        });
+
+        Ok(())
+    }
+
+    pub fn unpause(ctx: Context<AdminCtx>, operations: u8) -> Result<()> {
+        let swap = &mut ctx.accounts.swap;
+        swap.paused &= !operations;
+
+        emit!(PauseEvent {
+            admin: swap.admin,
+            paused: swap.paused,
+        });
+
+        Ok(())
+    }
+
+    pub fn initialize_pool(ctx: Context<InitializePoolCtx>) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        pool.swap = ctx.accounts.swap.key();
+        pool.token_a_mint = ctx.accounts.token_a_mint.key();
+        pool.token_b_mint = ctx.accounts.token_b_mint.key();
+        pool.token_a_vault = ctx.accounts.pool_token_a_account.key();
+        pool.token_b_vault = ctx.accounts.pool_token_b_account.key();
+        pool.lp_mint = ctx.accounts.lp_mint.key();
+        pool.bump = ctx.bumps.pool;
+        pool.vault_authority_bump = ctx.bumps.pool_authority;
+        pool.token_a_reserve = 0;
+        pool.token_b_reserve = 0;
+        pool.lp_supply = 0;
+        pool.flash_fee_bps = DEFAULT_FLASH_FEE_BPS;
+        pool.twamm.last_virtual_order_time = Clock::get()?.unix_timestamp;
+
+        emit!(InitializePoolEvent {
+            pool: pool.key(),
+            swap: pool.swap,
+            token_a_mint: pool.token_a_mint,
+            token_b_mint: pool.token_b_mint,
+        });
+
         Ok(())
     }
 
-    pub fn add_liquidity(ctx: Context<AddLiquidityCtx>, amount_a: u64, amount_b: u64) -> Result<()> {
-        let transfer_ctx_a = ctx.accounts.into_transfer_to_pool_context_a();
-        let transfer_ctx_b = ctx.accounts.into_transfer_to_pool_context_b();
+    // `amount_a` and `amount_b` are upper bounds: after the first deposit only the
+    // balanced portion is pulled from the user, so any excess never leaves their wallet.
+    pub fn add_liquidity(
+        ctx: Context<AddLiquidityCtx>,
+        amount_a: u64,
+        amount_b: u64,
+        min_lp_out: u64,
+    ) -> Result<()> {
+        ctx.accounts.swap.require_active(PAUSE_DEPOSITS)?;
+        ctx.accounts.pool.require_unlocked()?;
+        ctx.accounts.pool.execute_virtual_orders(&ctx.accounts.swap, Clock::get()?.unix_timestamp)?;
+
+        let pool = &ctx.accounts.pool;
+
+        let (deposit_a, deposit_b, lp_amount, locked) = if pool.lp_supply == 0 {
+            let liquidity = math::integer_sqrt(amount_a as u128 * amount_b as u128) as u64;
+            require!(liquidity > MINIMUM_LIQUIDITY, ErrorCode::InsufficientLiquidityMinted);
+            (amount_a, amount_b, liquidity - MINIMUM_LIQUIDITY, MINIMUM_LIQUIDITY)
+        } else {
+            let (deposit_a, deposit_b) =
+                math::balanced_deposit(amount_a, amount_b, pool.token_a_reserve, pool.token_b_reserve)?;
+            let lp_amount = std::cmp::min(
+                math::mul_div_floor(deposit_a, pool.lp_supply, pool.token_a_reserve)?,
+                math::mul_div_floor(deposit_b, pool.lp_supply, pool.token_b_reserve)?,
+            );
+            (deposit_a, deposit_b, lp_amount, 0)
+        };
 
-        token::transfer(transfer_ctx_a, amount_a)?;
-        token::transfer(transfer_ctx_b, amount_b)?;
+        require!(lp_amount > 0, ErrorCode::InsufficientLiquidityMinted);
+        require!(lp_amount >= min_lp_out, ErrorCode::SlippageExceeded);
+
+        let pool_key = pool.key();
+        let authority_seeds = &[VAULT_AUTHORITY_SEED, pool_key.as_ref(), &[pool.vault_authority_bump]];
+        let signer = &[&authority_seeds[..]];
+        token::transfer(ctx.accounts.into_transfer_to_pool_context_a(), deposit_a)?;
+        token::transfer(ctx.accounts.into_transfer_to_pool_context_b(), deposit_b)?;
+        token::mint_to(ctx.accounts.into_mint_lp_context(signer), lp_amount)?;
 
         let pool = &mut ctx.accounts.pool;
-        pool.token_a_reserve += amount_a;
-        pool.token_b_reserve += amount_b;
+        pool.token_a_reserve += deposit_a;
+        pool.token_b_reserve += deposit_b;
+        pool.lp_supply += lp_amount + locked;
 
         emit!(AddLiquidityEvent {
             user: *ctx.accounts.user.to_account_info().key,
+            amount_a: deposit_a,
+            amount_b: deposit_b,
+            lp_amount,
+            token_a_reserve: pool.token_a_reserve,
+            token_b_reserve: pool.token_b_reserve,
+        });
+
+        Ok(())
+    }
+
+    pub fn remove_liquidity(
+        ctx: Context<RemoveLiquidityCtx>,
+        lp_amount: u64,
+        min_amount_a: u64,
+        min_amount_b: u64,
+    ) -> Result<()> {
+        ctx.accounts.swap.require_active(PAUSE_WITHDRAWALS)?;
+        ctx.accounts.pool.require_unlocked()?;
+        ctx.accounts.pool.execute_virtual_orders(&ctx.accounts.swap, Clock::get()?.unix_timestamp)?;
+
+        let pool = &ctx.accounts.pool;
+
+        let amount_a = math::mul_div_floor(lp_amount, pool.token_a_reserve, pool.lp_supply)?;
+        let amount_b = math::mul_div_floor(lp_amount, pool.token_b_reserve, pool.lp_supply)?;
+        require!(amount_a > 0 || amount_b > 0, ErrorCode::InsufficientLiquidityBurned);
+        require!(amount_a >= min_amount_a, ErrorCode::SlippageExceeded);
+        require!(amount_b >= min_amount_b, ErrorCode::SlippageExceeded);
+
+        let pool_key = pool.key();
+        let authority_seeds = &[VAULT_AUTHORITY_SEED, pool_key.as_ref(), &[pool.vault_authority_bump]];
+        let signer = &[&authority_seeds[..]];
+        token::burn(ctx.accounts.into_burn_lp_context(), lp_amount)?;
+        token::transfer(ctx.accounts.into_transfer_to_user_context_a(signer), amount_a)?;
+        token::transfer(ctx.accounts.into_transfer_to_user_context_b(signer), amount_b)?;
+
+        let pool = &mut ctx.accounts.pool;
+        pool.token_a_reserve -= amount_a;
+        pool.token_b_reserve -= amount_b;
+        pool.lp_supply -= lp_amount;
+
+        emit!(RemoveLiquidityEvent {
+            user: *ctx.accounts.user.to_account_info().key,
+            lp_amount,
             amount_a,
             amount_b,
+            token_a_reserve: pool.token_a_reserve,
+            token_b_reserve: pool.token_b_reserve,
         });
 
         Ok(())
     }
 
-   pub fn simple_swap(ctx: Context<SimpleSwapCtx>, amount_a: u64, min_amount_b: u64) -> Result<()> {
-             // Extract the necessary values from the context
-              let transfer_ctx_to_pool = ctx.accounts.into_transfer_to_pool_context();
-              let transfer_ctx_to_user = ctx.accounts.into_transfer_to_user_context();
-              let transfer_ctx_fee = ctx.accounts.into_transfer_fee_context();
+    // Single-sided deposit: the optimal share of `amount_in` is swapped along `direction`,
+    // paying the curve and protocol fees of any swap, and the remainder plus the swap
+    // output are added as one balanced deposit.
+    pub fn add_liquidity_single(
+        ctx: Context<AddLiquiditySingleCtx>,
+        direction: SwapDirection,
+        amount_in: u64,
+        min_lp_out: u64,
+    ) -> Result<()> {
+        ctx.accounts.swap.require_active(PAUSE_DEPOSITS)?;
+        ctx.accounts.pool.require_unlocked()?;
+        require!(!ctx.accounts.pool.breaker_tripped, ErrorCode::CircuitBreakerActive);
+        ctx.accounts.pool.execute_virtual_orders(&ctx.accounts.swap, Clock::get()?.unix_timestamp)?;
+
+        let pool = &ctx.accounts.pool;
+        let fee_rate = ctx.accounts.swap.fee_rate;
+        let (reserve_in, reserve_out) = pool.reserves(direction);
+
+        let swap_in = math::zap_swap_amount(amount_in, reserve_in, fee_rate)?;
+        let (swap_out, _) = pool.quote_exact_in(direction, swap_in, fee_rate)?;
+        let lp_amount = std::cmp::min(
+            math::mul_div_floor(amount_in - swap_in, pool.lp_supply, reserve_in + swap_in)?,
+            math::mul_div_floor(swap_out, pool.lp_supply, reserve_out - swap_out)?,
+        );
+        require!(lp_amount > 0, ErrorCode::InsufficientLiquidityMinted);
+        require!(lp_amount >= min_lp_out, ErrorCode::SlippageExceeded);
+
+        let pool_key = pool.key();
+        let authority_seeds = &[VAULT_AUTHORITY_SEED, pool_key.as_ref(), &[pool.vault_authority_bump]];
+        let signer = &[&authority_seeds[..]];
+        // The swap leg meets the breaker like any other swap, once the input is held.
+        token::transfer(ctx.accounts.into_transfer_to_pool_context(), amount_in)?;
+        if ctx.accounts.pool.settle_swap(pool_key, direction, swap_in, swap_out)? {
+            token::transfer(ctx.accounts.into_refund_context(signer), amount_in)?;
+            return Ok(());
+        }
+        token::mint_to(ctx.accounts.into_mint_lp_context(signer), lp_amount)?;
+
+        // The swapped output is deposited straight back, so only the input side grows.
+        let pool = &mut ctx.accounts.pool;
+        pool.set_reserves(direction, reserve_in + amount_in, reserve_out);
+        pool.lp_supply += lp_amount;
 
-              // Mutate before transfer to calculate the amount and fee
-              let pool = &ctx.accounts.pool; // Immutable borrow to calculate
-              let swap = &ctx.accounts.swap; // Immutable borrow to calculate
-    
-             let amount_b = get_swap_amount(amount_a, pool.token_a_reserve, pool.token_b_reserve)?;
-             let fee = calculate_fee(amount_b, swap.fee_rate);
+        let (amount_a, amount_b) = match direction {
+            SwapDirection::AToB => (amount_in, 0),
+            SwapDirection::BToA => (0, amount_in),
+        };
+        emit!(AddLiquidityEvent {
+            user: *ctx.accounts.user.to_account_info().key,
+            amount_a,
+            amount_b,
+            lp_amount,
+            token_a_reserve: pool.token_a_reserve,
+            token_b_reserve: pool.token_b_reserve,
+        });
 
-            require!(amount_b >= min_amount_b, ErrorCode::SlippageExceeded);
+        Ok(())
+    }
 
-              // Perform all transfers
-             token::transfer(transfer_ctx_to_pool, amount_a)?;
-             token::transfer(transfer_ctx_to_user, amount_b - fee)?;
-             token::transfer(transfer_ctx_fee, fee)?;
+    // Single-sided withdrawal: the pro-rata share of the input side of `direction` is
+    // swapped, paying the curve and protocol fees, against the remaining reserves and
+    // paid out together with the output side. A share too small to buy anything stays in
+    // the pool.
+    pub fn remove_liquidity_single(
+        ctx: Context<RemoveLiquiditySingleCtx>,
+        direction: SwapDirection,
+        lp_amount: u64,
+        min_amount_out: u64,
+    ) -> Result<()> {
+        ctx.accounts.swap.require_active(PAUSE_WITHDRAWALS)?;
+        ctx.accounts.pool.require_unlocked()?;
+        require!(!ctx.accounts.pool.breaker_tripped, ErrorCode::CircuitBreakerActive);
+        ctx.accounts.pool.execute_virtual_orders(&ctx.accounts.swap, Clock::get()?.unix_timestamp)?;
 
-    // Now perform mutable borrow after transfers
-    let pool = &mut ctx.accounts.pool;
-    pool.token_a_reserve += amount_a;
-    pool.token_b_reserve -= amount_b;
+        let pool = &ctx.accounts.pool;
+        let (reserve_in, reserve_out) = pool.reserves(direction);
+
+        let withdrawn_in = math::mul_div_floor(lp_amount, reserve_in, pool.lp_supply)?;
+        let withdrawn_out = math::mul_div_floor(lp_amount, reserve_out, pool.lp_supply)?;
+        let fee_rate = ctx.accounts.swap.fee_rate;
+        let swap_out = match math::quote_exact_in(withdrawn_in, reserve_in - withdrawn_in, reserve_out - withdrawn_out, fee_rate) {
+            Err(math::MathError::ZeroOutput) => 0,
+            quote => quote?.0,
+        };
+        let amount_out = withdrawn_out + swap_out;
+        require!(amount_out > 0, ErrorCode::InsufficientLiquidityBurned);
+        require!(amount_out >= min_amount_out, ErrorCode::SlippageExceeded);
 
-    emit!(SimpleSwapEvent {
-        user: *ctx.accounts.user.to_account_info().key,
-        amount_a,
-        amount_b,
-        fee,
-    });
+        let pool_key = pool.key();
+        let authority_seeds = &[VAULT_AUTHORITY_SEED, pool_key.as_ref(), &[pool.vault_authority_bump]];
+        let signer = &[&authority_seeds[..]];
+        token::burn(ctx.accounts.into_burn_lp_context(), lp_amount)?;
 
-    Ok(())
-}
+        // The withdrawn input side is swapped straight back against what is left, through
+        // the breaker, so only the output side shrinks. A trip hands the burned LP back.
+        let pool = &mut ctx.accounts.pool;
+        pool.set_reserves(direction, reserve_in - withdrawn_in, reserve_out - withdrawn_out);
+        if pool.settle_swap(pool_key, direction, withdrawn_in, swap_out)? {
+            pool.set_reserves(direction, reserve_in, reserve_out);
+            token::mint_to(ctx.accounts.into_mint_lp_context(signer), lp_amount)?;
+            return Ok(());
+        }
+        token::transfer(ctx.accounts.into_transfer_to_user_context(signer), amount_out)?;
 
+        let pool = &mut ctx.accounts.pool;
+        pool.lp_supply -= lp_amount;
+
+        let (amount_a, amount_b) = match direction {
+            SwapDirection::AToB => (0, amount_out),
+            SwapDirection::BToA => (amount_out, 0),
+        };
+        emit!(RemoveLiquidityEvent {
+            user: *ctx.accounts.user.to_account_info().key,
+            lp_amount,
+            amount_a,
+            amount_b,
+            token_a_reserve: pool.token_a_reserve,
+            token_b_reserve: pool.token_b_reserve,
+        });
+
+        Ok(())
+    }
+
+    // Zero thresholds disable a check; a zero window compares every trade against the
+    // price before it. With `auto_pause` a tripping trade pauses the pool instead of failing.
+    pub fn configure_circuit_breaker(
+        ctx: Context<PoolAdminCtx>,
+        max_trade_move_bps: u16,
+        max_window_move_bps: u16,
+        window_slots: u64,
+        auto_pause: bool,
+    ) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        pool.max_trade_move_bps = max_trade_move_bps;
+        pool.max_window_move_bps = max_window_move_bps;
+        pool.breaker_window_slots = window_slots;
+        pool.breaker_auto_pause = auto_pause;
+        Ok(())
+    }
+
+    pub fn reset_circuit_breaker(ctx: Context<PoolAdminCtx>) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        pool.breaker_tripped = false;
+        pool.reference_price = None;
+        Ok(())
+    }
+
+    // Refused while a flash loan is outstanding so the fee owed cannot change mid-loan.
+    pub fn set_flash_fee(ctx: Context<PoolAdminCtx>, flash_fee_bps: u16) -> Result<()> {
+        ctx.accounts.pool.require_unlocked()?;
+        require!(flash_fee_bps <= MAX_FLASH_FEE_BPS, ErrorCode::InvalidFlashFee);
+        ctx.accounts.pool.flash_fee_bps = flash_fee_bps;
+        Ok(())
+    }
+
+    pub fn simple_swap(ctx: Context<SimpleSwapCtx>, amount_a: u64, min_amount_b: u64) -> Result<()> {
+        swap(ctx, SwapDirection::AToB, SwapMode::ExactIn, amount_a, min_amount_b)
+    }
+
+    // ExactIn sells exactly `amount` and requires at least `other_amount_threshold` out;
+    // ExactOut buys exactly `amount` and spends at most `other_amount_threshold`. The
+    // protocol fee is taken from the output and left in the pool's reserves.
+    pub fn swap(
+        ctx: Context<SimpleSwapCtx>,
+        direction: SwapDirection,
+        mode: SwapMode,
+        amount: u64,
+        other_amount_threshold: u64,
+    ) -> Result<()> {
+        ctx.accounts.swap.require_active(PAUSE_SWAPS)?;
+        ctx.accounts.pool.require_unlocked()?;
+        require!(!ctx.accounts.pool.breaker_tripped, ErrorCode::CircuitBreakerActive);
+        ctx.accounts.pool.execute_virtual_orders(&ctx.accounts.swap, Clock::get()?.unix_timestamp)?;
+
+        let pool = &ctx.accounts.pool;
+        let fee_rate = ctx.accounts.swap.fee_rate;
+        let (reserve_in, reserve_out) = pool.reserves(direction);
+
+        let (amount_in, amount_out, fee) = match mode {
+            SwapMode::ExactIn => {
+                let (amount_out, fee) = pool.quote_exact_in(direction, amount, fee_rate)?;
+                require!(amount_out >= other_amount_threshold, ErrorCode::SlippageExceeded);
+                (amount, amount_out, fee)
+            }
+            SwapMode::ExactOut => {
+                let gross_out = math::amount_before_fee(amount, fee_rate)?;
+                let amount_in = math::swap_amount_in(gross_out, reserve_in, reserve_out)?;
+                require!(amount_in <= other_amount_threshold, ErrorCode::SlippageExceeded);
+                (amount_in, amount, gross_out - amount)
+            }
+        };
+
+        let pool_key = pool.key();
+        let authority_seeds = &[VAULT_AUTHORITY_SEED, pool_key.as_ref(), &[pool.vault_authority_bump]];
+        let signer = &[&authority_seeds[..]];
+        // The input is pulled before the breaker runs, so only a funded trade can trip it.
+        token::transfer(ctx.accounts.into_transfer_to_pool_context(direction), amount_in)?;
+        if ctx.accounts.pool.settle_swap(pool_key, direction, amount_in, amount_out)? {
+            token::transfer(ctx.accounts.into_refund_context(direction, signer), amount_in)?;
+            return Ok(());
+        }
+        token::transfer(ctx.accounts.into_transfer_to_user_context(direction, signer), amount_out)?;
+
+        emit!(SimpleSwapEvent {
+            user: *ctx.accounts.user.to_account_info().key,
+            direction,
+            mode,
+            amount_in,
+            amount_out,
+            fee,
+        });
+
+        Ok(())
+    }
 
     // Limit Order Functions
-    pub fn place_limit_order(
-        ctx: Context<PlaceLimitOrderCtx>,
-        amount_a: u64,
-        target_price: u64,
-        expiration: i64,
-        partial_fill: bool,
+    // Creates the pool's order book. Limit orders placed with it rest there as makers
+    // that executing orders match against before trading with the pool. Each side only
+    // takes orders of at least its minimum input: token A for asks, token B for bids.
+    pub fn initialize_order_book(
+        ctx: Context<InitializeOrderBookCtx>,
+        min_ask_amount: u64,
+        min_bid_amount: u64,
     ) -> Result<()> {
+        let mut order_book = ctx.accounts.order_book.load_init()?;
+        order_book.pool = ctx.accounts.pool.key();
+        order_book.min_ask_amount = min_ask_amount;
+        order_book.min_bid_amount = min_bid_amount;
+        order_book.bump = ctx.bumps.order_book;
+        ctx.accounts.pool.has_order_book = true;
+        Ok(())
+    }
+
+    // Creates the caller's UserOrderBook; required once before their first order.
+    pub fn initialize_user_order_book(ctx: Context<InitializeUserOrderBookCtx>) -> Result<()> {
+        let book = &mut ctx.accounts.user_order_book;
+        book.user = ctx.accounts.user.key();
+        book.bump = ctx.bumps.user_order_book;
+        book.next_nonce = 0;
+        book.open_orders = Vec::new();
+        Ok(())
+    }
+
+    // Moves `amount_in`, plus any fixed keeper fee, into the order's escrow; execution
+    // sells it through the pool and pays the output to `destination`. Passing the pool's
+    // order book also rests a plain limit order there for other orders to match; such an
+    // order must accept partial fills of any size and escrow no fixed keeper fee.
+    pub fn place_limit_order(ctx: Context<PlaceLimitOrderCtx>, params: LimitOrderParams) -> Result<()> {
+        ctx.accounts.swap.require_active(PAUSE_LIMIT_ORDERS)?;
+
+        let escrowed = match params.keeper_fee {
+            KeeperFee::Fixed { amount } => params.amount_in.checked_add(amount).ok_or(ErrorCode::MathOverflow)?,
+            KeeperFee::OutputBps { bps } => {
+                require!(u64::from(bps) <= math::BPS_DENOMINATOR, ErrorCode::InvalidKeeperFee);
+                params.amount_in
+            }
+        };
+        let trigger_direction = params.order_type.trigger_direction(params.direction);
+        require!(trigger_direction.is_none() || params.trigger_price > 0, ErrorCode::InvalidTrigger);
+        // An auction's limit falls to its end price, which is also the floor it is stored with.
+        let target_price = match params.order_type {
+            OrderType::DutchAuction { start_price, end_price, start_time, end_time, decay } => {
+                require!(
+                    start_price >= end_price
+                        && start_time < end_time
+                        && (decay == PriceDecay::Linear || end_price > 0),
+                    ErrorCode::InvalidAuction
+                );
+                end_price
+            }
+            _ => params.target_price,
+        };
+        token::transfer(ctx.accounts.into_transfer_to_escrow_context(), escrowed)?;
+
+        let (input_mint, output_mint) = ctx.accounts.pool.mints(params.direction);
         let order = &mut ctx.accounts.order;
         order.user = *ctx.accounts.user.key;
-        order.token_a_reserve = amount_a;
+        order.pool = ctx.accounts.pool.key();
+        order.direction = params.direction;
+        order.input_mint = input_mint;
+        order.output_mint = output_mint;
+        order.destination = ctx.accounts.destination.key();
+        order.escrow_bump = ctx.bumps.escrow;
+        order.amount_in = params.amount_in;
+        order.filled_amount = 0;
+        order.remaining_amount = params.amount_in;
+        order.received_amount = 0;
+        order.min_fill_amount = params.min_fill_amount;
+        order.fill_count = 0;
         order.target_price = target_price;
-        order.expiration = expiration;
-        order.partial_fill = partial_fill;
+        order.expiration = params.expiration;
+        order.partial_fill = params.partial_fill;
+        order.keeper_fee = params.keeper_fee;
+        order.order_type = params.order_type;
+        order.trigger_direction = trigger_direction;
+        order.trigger_price = params.trigger_price;
+        order.triggered = trigger_direction.is_none();
+        order.on_book = ctx.accounts.order_book.is_some();
+        if let Some(order_book) = &ctx.accounts.order_book {
+            // Resting orders never refuse a crossing taker, and a fully matched one holds
+            // nothing more in escrow, so it can be closed as it is matched.
+            require!(
+                params.order_type == OrderType::Limit
+                    && params.partial_fill
+                    && params.min_fill_amount == 0
+                    && escrowed == params.amount_in,
+                ErrorCode::InvalidBookOrder
+            );
+            let mut order_book = order_book.load_mut()?;
+            require!(params.amount_in >= order_book.min_amount(params.direction), ErrorCode::BookOrderTooSmall);
+            order_book.insert(params.direction, order.key(), target_price, params.expiration)?;
+        }
+
+        let book = &mut ctx.accounts.user_order_book;
+        require!(book.open_orders.len() < MAX_OPEN_ORDERS, ErrorCode::TooManyOpenOrders);
+        order.nonce = book.next_nonce;
+        order.bump = ctx.bumps.order;
+        book.next_nonce += 1;
+        book.open_orders.push(order.key());
 
         emit!(LimitOrderPlaced {
-            user: *ctx.accounts.user.key,
-            amount_a,
+            order: order.key(),
+            user: order.user,
+            direction: params.direction,
+            amount_in: params.amount_in,
             target_price,
-            expiration,
-            partial_fill,
+            expiration: params.expiration,
+            partial_fill: params.partial_fill,
+            min_fill_amount: params.min_fill_amount,
+            keeper_fee: params.keeper_fee,
+            order_type: params.order_type,
+            trigger_price: params.trigger_price,
         });
 
         Ok(())
     }
 
-    pub fn execute_limit_order(ctx: Context<ExecuteLimitOrderCtx>, current_price: u64) -> Result<()> {
-        let order = &mut ctx.accounts.order;
+    // Permissionless: any keeper may fill an eligible order and collect its keeper fee.
+    // `target_price` is the worst price any order accepts, including triggered ones; a
+    // Dutch auction accepts the price its schedule has decayed to at execution.
+    // The fill is matched first against resting makers from the pool's order book, passed
+    // in `remaining_accounts` as (order, escrow, destination, owner, owner's UserOrderBook)
+    // groups in book order from the best unexpired one, at each maker's price; whatever is
+    // left trades with the pool, but only once no unexpired maker still crosses it. Fully
+    // matched makers are closed and their rent returned to their owners. It goes through
+    // only if the output left for the owner, after swap and keeper fees, meets the order's
+    // limit. Orders that allow partial fills take any `fill_amount` of at least
+    // `min_fill_amount` (or the whole remainder); others must be filled at once. A fully
+    // filled order is closed.
+    pub fn execute_limit_order<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ExecuteLimitOrderCtx<'info>>,
+        fill_amount: u64,
+    ) -> Result<()> {
+        ctx.accounts.swap.require_active(PAUSE_LIMIT_ORDERS | PAUSE_SWAPS)?;
+        ctx.accounts.pool.require_unlocked()?;
+        require!(!ctx.accounts.pool.breaker_tripped, ErrorCode::CircuitBreakerActive);
+        ctx.accounts.pool.execute_virtual_orders(&ctx.accounts.swap, Clock::get()?.unix_timestamp)?;
+
+        let order = &ctx.accounts.order;
 
         let current_time = Clock::get()?.unix_timestamp;
-        require!(order.expiration >= current_time, ErrorCode::OrderExpired);
-        require!(current_price >= order.target_price, ErrorCode::PriceNotMet);
+        let amount_in = fill_amount;
+        let completes = order.require_fillable(amount_in, current_time, ctx.accounts.pool.spot_price()?)?;
+        let limit_price = order.limit_price(current_time)?;
+
+        let order_key = order.key();
+        let pool_key = ctx.accounts.pool.key();
+        let direction = order.direction;
+        let keeper_bps = match order.keeper_fee {
+            KeeperFee::Fixed { .. } => 0,
+            KeeperFee::OutputBps { bps } => u64::from(bps),
+        };
 
-        let amount_a = if order.partial_fill {
-            let available_amount = ctx.accounts.pool.token_a_reserve;
-            std::cmp::min(available_amount, order.token_a_reserve)
+        // A pool with an order book trades only through it, so no crossing maker is skipped.
+        require!(
+            ctx.accounts.order_book.is_some() || (!ctx.accounts.pool.has_order_book && ctx.remaining_accounts.is_empty()),
+            ErrorCode::OrderBookRequired
+        );
+        let mut matches: Vec<MakerMatch> = Vec::new();
+        let mut evicted: Vec<Account<'info, LimitOrder>> = Vec::new();
+        let mut matched_in = 0;
+        if let Some(order_book) = &ctx.accounts.order_book {
+            let order_book = order_book.load()?;
+            // Expired makers stay on the book until they are closed, but no longer trade.
+            let mut makers = order_book
+                .entries(direction.opposite())
+                .iter()
+                .filter(|entry| entry.expiration >= current_time);
+            for maker_accounts in ctx.remaining_accounts.chunks(5) {
+                let [maker_info, maker_escrow, maker_destination, maker_user, maker_user_order_book] = maker_accounts
+                else {
+                    return err!(ErrorCode::InvalidMaker);
+                };
+                let maker: Account<'info, LimitOrder> = Account::try_from(maker_info)?;
+                // Makers are taken best first, each only once the one before it is used up.
+                require!(
+                    !matches!(matches.last(), Some(matched) if matched.maker_in != matched.maker.remaining_amount)
+                        && makers.next().map(|entry| entry.order) == Some(maker.key())
+                        && maker.pool == pool_key
+                        && maker.direction != direction
+                        && maker_escrow.key() == escrow_address(maker.key(), maker.escrow_bump)?
+                        && maker_destination.key() == maker.destination
+                        && maker_user.key() == maker.user,
+                    ErrorCode::InvalidMaker
+                );
+                // A maker whose destination can no longer be paid would block this side of
+                // the book, so it is evicted instead of matched.
+                if !accepts_deposits(maker_destination, maker.output_mint) {
+                    evicted.push(maker);
+                    continue;
+                }
+                let matched = math::match_amounts(
+                    amount_in - matched_in,
+                    limit_price,
+                    maker.remaining_amount,
+                    maker.target_price,
+                )?;
+                let Some((taker_in, maker_in)) = matched else {
+                    return err!(ErrorCode::InvalidMaker);
+                };
+                require!(
+                    math::is_valid_fill(maker_in, maker.remaining_amount, maker.partial_fill, maker.min_fill_amount),
+                    ErrorCode::InvalidMaker
+                );
+                let keeper_fee = math::output_keeper_fee(maker_in, keeper_bps)?;
+                matched_in += taker_in;
+                matches.push(MakerMatch {
+                    maker,
+                    escrow: maker_escrow,
+                    destination: maker_destination,
+                    user: maker_user,
+                    user_order_book: maker_user_order_book,
+                    taker_in,
+                    maker_in,
+                    keeper_fee,
+                });
+            }
+
+            // The best maker left, which may be the last one matched, must not cross the rest.
+            let best_left_price = match matches.last() {
+                Some(matched) if matched.maker_in < matched.maker.remaining_amount => Some(matched.maker.target_price),
+                _ => makers.next().map(|entry| entry.price),
+            };
+            if let (Some(price), true) = (best_left_price, matched_in < amount_in) {
+                require!(
+                    math::match_amounts(amount_in - matched_in, limit_price, u64::MAX, price)?.is_none(),
+                    ErrorCode::CrossingMakerSkipped
+                );
+            }
+        }
+        // Evicted makers leave the book but keep their escrow until their owner cancels.
+        for mut maker in evicted {
+            if let Some(order_book) = &ctx.accounts.order_book {
+                order_book.load_mut()?.remove(maker.direction, maker.key());
+            }
+            maker.on_book = false;
+            maker.exit(&crate::ID)?;
+            emit!(MakerEvicted {
+                taker: order_key,
+                maker: maker.key(),
+            });
+        }
+
+        let pool_in = amount_in - matched_in;
+        let pool = &ctx.accounts.pool;
+        let pool_out = if pool_in > 0 {
+            pool.quote_exact_in(direction, pool_in, ctx.accounts.swap.fee_rate)?.0
         } else {
-            order.token_a_reserve
+            0
+        };
+        let pool_keeper_fee = math::output_keeper_fee(pool_out, keeper_bps)?;
+        let matched_out: u64 = matches.iter().map(|matched| matched.maker_in - matched.keeper_fee).sum();
+        let owner_amount_out = matched_out + pool_out - pool_keeper_fee;
+        let keeper_fee = match order.keeper_fee {
+            KeeperFee::Fixed { amount } => order.fixed_keeper_fee(amount, amount_in, ctx.accounts.escrow.amount)?,
+            KeeperFee::OutputBps { .. } => {
+                pool_keeper_fee + matches.iter().map(|matched| matched.keeper_fee).sum::<u64>()
+            }
         };
+        let min_amount_out = math::mul_div_ceil(amount_in, limit_price, math::PRICE_SCALE)?;
+        require!(owner_amount_out >= min_amount_out, ErrorCode::PriceNotMet);
+        if pool_in > 0 && ctx.accounts.pool.settle_swap(pool_key, direction, pool_in, pool_out)? {
+            return Ok(());
+        }
 
-        let pool = &mut ctx.accounts.pool;
-        pool.token_a_reserve -= amount_a;
-        pool.token_b_reserve += current_price * amount_a;
+        let pool = &ctx.accounts.pool;
+        let authority_seeds = &[VAULT_AUTHORITY_SEED, pool_key.as_ref(), &[pool.vault_authority_bump]];
+        let signer = &[&authority_seeds[..]];
+        let taker_fill_sequence = ctx.accounts.order.fill_count + 1;
+        let mut filled_makers = Vec::new();
+        for MakerMatch {
+            mut maker,
+            escrow: maker_escrow,
+            destination: maker_destination,
+            user: maker_user,
+            user_order_book: maker_user_order_book,
+            taker_in,
+            maker_in,
+            keeper_fee: maker_keeper_fee,
+        } in matches
+        {
+            let escrow = ctx.accounts.escrow.to_account_info();
+            let destination = ctx.accounts.destination.to_account_info();
+            let keeper_token_account = ctx.accounts.keeper_token_account.to_account_info();
+            token::transfer(ctx.accounts.into_escrow_transfer_context(escrow, maker_destination.clone(), signer), taker_in)?;
+            token::transfer(
+                ctx.accounts.into_escrow_transfer_context(maker_escrow.clone(), destination, signer),
+                maker_in - maker_keeper_fee,
+            )?;
+            if maker_keeper_fee > 0 {
+                token::transfer(
+                    ctx.accounts.into_escrow_transfer_context(maker_escrow.clone(), keeper_token_account, signer),
+                    maker_keeper_fee,
+                )?;
+            }
+
+            maker.filled_amount += maker_in;
+            maker.remaining_amount -= maker_in;
+            maker.received_amount += taker_in;
+            maker.fill_count += 1;
+
+            emit!(LimitOrderMatched {
+                taker: order_key,
+                maker: maker.key(),
+                taker_fill_sequence,
+                maker_fill_sequence: maker.fill_count,
+                taker_amount_in: taker_in,
+                maker_amount_in: maker_in,
+                keeper_fee: maker_keeper_fee,
+            });
+
+            if maker.remaining_amount > 0 {
+                maker.exit(&crate::ID)?;
+                continue;
+            }
+            // A fully matched maker leaves the book and its owner's open orders, and its
+            // empty escrow and the order are closed to the owner.
+            let maker_key = maker.key();
+            if let Some(order_book) = &ctx.accounts.order_book {
+                order_book.load_mut()?.remove(maker.direction, maker_key);
+            }
+            // The taker's own UserOrderBook is written back from the context on exit.
+            if maker.user == ctx.accounts.user.key() {
+                ctx.accounts.user_order_book.remove_order(maker_key);
+            } else {
+                let mut maker_book: Account<'info, UserOrderBook> = Account::try_from(maker_user_order_book)?;
+                require!(
+                    maker_book.user == maker.user
+                        && maker_book.key() == user_order_book_address(maker.user, maker_book.bump)?,
+                    ErrorCode::InvalidMaker
+                );
+                maker_book.remove_order(maker_key);
+                maker_book.exit(&crate::ID)?;
+            }
+            token::close_account(ctx.accounts.into_close_maker_escrow_context(
+                maker_escrow.clone(),
+                maker_user.clone(),
+                signer,
+            ))?;
+            filled_makers.push((maker, maker_user));
+        }
+        if pool_in > 0 {
+            token::transfer(ctx.accounts.into_transfer_from_escrow_context(signer), pool_in)?;
+            token::transfer(ctx.accounts.into_transfer_to_destination_context(signer), pool_out - pool_keeper_fee)?;
+        }
+        // Output-based fees on matched fills were already paid out of the makers' escrows.
+        let escrow_or_pool_keeper_fee = match ctx.accounts.order.keeper_fee {
+            KeeperFee::Fixed { .. } => keeper_fee,
+            KeeperFee::OutputBps { .. } => pool_keeper_fee,
+        };
+        token::transfer(ctx.accounts.into_transfer_to_keeper_context(signer), escrow_or_pool_keeper_fee)?;
+
+        let order = &mut ctx.accounts.order;
+        order.record_fill(amount_in, owner_amount_out);
 
         emit!(LimitOrderExecuted {
+            order: order_key,
             user: order.user,
-            amount_a,
-            target_price: order.target_price,
+            keeper: ctx.accounts.keeper.key(),
+            fill_sequence: order.fill_count,
+            amount_in,
+            amount_out: owner_amount_out,
+            keeper_fee,
+            remaining_amount: order.remaining_amount,
+            target_price: limit_price,
+        });
+
+        if completes {
+            leave_order_book(&ctx.accounts.order, order_key, &ctx.accounts.order_book)?;
+            ctx.accounts.user_order_book.remove_order(order_key);
+            token::close_account(ctx.accounts.into_close_escrow_context(signer))?;
+            ctx.accounts.order.close(ctx.accounts.user.to_account_info())?;
+        }
+        // Closing moves lamports directly, so it waits until the last CPI has run.
+        for (maker, maker_user) in filled_makers {
+            maker.close(maker_user.clone())?;
+        }
+
+        Ok(())
+    }
+
+    // Fills an eligible order from the keeper's own inventory instead of the pool: the
+    // keeper pays `amount_out` of the output token to the order's destination and takes
+    // `fill_amount` of the input, plus its share of a fixed keeper fee, from the escrow.
+    // The same fill sizes, triggers and limit price apply as for execute_limit_order. An
+    // output-based keeper fee is not charged, since the keeper sets the output it pays.
+    pub fn fill_limit_order(ctx: Context<FillLimitOrderCtx>, fill_amount: u64, amount_out: u64) -> Result<()> {
+        ctx.accounts.swap.require_active(PAUSE_LIMIT_ORDERS)?;
+        ctx.accounts.pool.require_unlocked()?;
+        ctx.accounts.pool.execute_virtual_orders(&ctx.accounts.swap, Clock::get()?.unix_timestamp)?;
+
+        let order = &ctx.accounts.order;
+        let current_time = Clock::get()?.unix_timestamp;
+        let amount_in = fill_amount;
+        let completes = order.require_fillable(amount_in, current_time, ctx.accounts.pool.spot_price()?)?;
+        let limit_price = order.limit_price(current_time)?;
+        let min_amount_out = math::mul_div_ceil(amount_in, limit_price, math::PRICE_SCALE)?;
+        require!(amount_out >= min_amount_out, ErrorCode::PriceNotMet);
+        let keeper_fee = match order.keeper_fee {
+            KeeperFee::Fixed { amount } => order.fixed_keeper_fee(amount, amount_in, ctx.accounts.escrow.amount)?,
+            KeeperFee::OutputBps { .. } => 0,
+        };
+
+        let pool_key = ctx.accounts.pool.key();
+        let authority_seeds = &[VAULT_AUTHORITY_SEED, pool_key.as_ref(), &[ctx.accounts.pool.vault_authority_bump]];
+        let signer = &[&authority_seeds[..]];
+        token::transfer(ctx.accounts.into_transfer_to_destination_context(), amount_out)?;
+        token::transfer(ctx.accounts.into_transfer_to_keeper_context(signer), amount_in + keeper_fee)?;
+
+        let order_key = ctx.accounts.order.key();
+        let order = &mut ctx.accounts.order;
+        order.record_fill(amount_in, amount_out);
+
+        emit!(LimitOrderExecuted {
+            order: order_key,
+            user: order.user,
+            keeper: ctx.accounts.keeper.key(),
+            fill_sequence: order.fill_count,
+            amount_in,
+            amount_out,
+            keeper_fee,
+            remaining_amount: order.remaining_amount,
+            target_price: limit_price,
+        });
+
+        if completes {
+            leave_order_book(&ctx.accounts.order, order_key, &ctx.accounts.order_book)?;
+            ctx.accounts.user_order_book.remove_order(order_key);
+            token::close_account(ctx.accounts.into_close_escrow_context(signer))?;
+            ctx.accounts.order.close(ctx.accounts.user.to_account_info())?;
+        }
+
+        Ok(())
+    }
+
+    // Refunds everything left in the escrow, including an unpaid fixed keeper fee, and
+    // returns the rent of both the order and its escrow to the owner.
+    pub fn cancel_limit_order(ctx: Context<CancelLimitOrderCtx>) -> Result<()> {
+        let refunded = ctx.accounts.escrow.amount;
+
+        let pool_key = ctx.accounts.pool.key();
+        let authority_seeds = &[VAULT_AUTHORITY_SEED, pool_key.as_ref(), &[ctx.accounts.pool.vault_authority_bump]];
+        let signer = &[&authority_seeds[..]];
+        token::transfer(ctx.accounts.into_refund_context(signer), refunded)?;
+        token::close_account(ctx.accounts.into_close_escrow_context(signer))?;
+        let order_key = ctx.accounts.order.key();
+        leave_order_book(&ctx.accounts.order, order_key, &ctx.accounts.order_book)?;
+        ctx.accounts.user_order_book.remove_order(order_key);
+
+        emit!(LimitOrderCancelled {
+            order: ctx.accounts.order.key(),
+            user: ctx.accounts.user.key(),
+            refunded,
+        });
+
+        Ok(())
+    }
+
+    // Permissionless cleanup of an order past its expiration. The owner is refunded and
+    // gets the rent back; a caller that passes an input-token account is paid the unpaid
+    // part of the order's fixed keeper fee as a cleanup reward.
+    pub fn close_expired_order(ctx: Context<CloseExpiredOrderCtx>) -> Result<()> {
+        let current_time = Clock::get()?.unix_timestamp;
+        require!(current_time > ctx.accounts.order.expiration, ErrorCode::OrderNotExpired);
+
+        // The unpaid part of a fixed keeper fee is whatever the escrow holds beyond the unfilled input.
+        let cleanup_reward = match (ctx.accounts.order.keeper_fee, &ctx.accounts.caller_token_account) {
+            (KeeperFee::Fixed { .. }, Some(_)) => ctx.accounts.escrow.amount - ctx.accounts.order.remaining_amount,
+            _ => 0,
+        };
+        let refunded = ctx.accounts.escrow.amount - cleanup_reward;
+
+        let pool_key = ctx.accounts.pool.key();
+        let authority_seeds = &[VAULT_AUTHORITY_SEED, pool_key.as_ref(), &[ctx.accounts.pool.vault_authority_bump]];
+        let signer = &[&authority_seeds[..]];
+        if let Some(reward_ctx) = ctx.accounts.into_reward_context(signer) {
+            token::transfer(reward_ctx, cleanup_reward)?;
+        }
+        token::transfer(ctx.accounts.into_refund_context(signer), refunded)?;
+        token::close_account(ctx.accounts.into_close_escrow_context(signer))?;
+        let order_key = ctx.accounts.order.key();
+        leave_order_book(&ctx.accounts.order, order_key, &ctx.accounts.order_book)?;
+        ctx.accounts.user_order_book.remove_order(order_key);
+
+        emit!(LimitOrderExpired {
+            order: ctx.accounts.order.key(),
+            user: ctx.accounts.user.key(),
+            caller: ctx.accounts.caller.key(),
+            refunded,
+            cleanup_reward,
+        });
+
+        Ok(())
+    }
+
+    // DCA Functions
+    // Escrows `total_amount` of the input; keepers then sell it through the pool in
+    // slices of `amount_per_cycle`, at most one every `interval` seconds.
+    pub fn place_dca_order(ctx: Context<PlaceDcaOrderCtx>, params: DcaOrderParams) -> Result<()> {
+        ctx.accounts.swap.require_active(PAUSE_LIMIT_ORDERS)?;
+        require!(
+            params.amount_per_cycle > 0 && params.amount_per_cycle <= params.total_amount && params.interval > 0,
+            ErrorCode::InvalidDcaSchedule
+        );
+        token::transfer(ctx.accounts.into_transfer_to_escrow_context(), params.total_amount)?;
+
+        let dca_order = &mut ctx.accounts.dca_order;
+        dca_order.user = *ctx.accounts.user.key;
+        dca_order.pool = ctx.accounts.pool.key();
+        dca_order.bump = ctx.bumps.dca_order;
+        dca_order.direction = params.direction;
+        dca_order.destination = ctx.accounts.destination.key();
+        dca_order.escrow_bump = ctx.bumps.escrow;
+        dca_order.total_amount = params.total_amount;
+        dca_order.amount_per_cycle = params.amount_per_cycle;
+        dca_order.min_amount_out_per_cycle = params.min_amount_out_per_cycle;
+        dca_order.interval = params.interval;
+        // The first slice can be sold right away.
+        dca_order.next_cycle_at = Clock::get()?.unix_timestamp;
+        dca_order.remaining_amount = params.total_amount;
+        dca_order.received_amount = 0;
+        dca_order.cycle_count = 0;
+
+        let book = &mut ctx.accounts.user_order_book;
+        require!(book.open_orders.len() < MAX_OPEN_ORDERS, ErrorCode::TooManyOpenOrders);
+        dca_order.nonce = book.next_nonce;
+        book.next_nonce += 1;
+        book.open_orders.push(dca_order.key());
+
+        emit!(DcaOrderPlaced {
+            order: dca_order.key(),
+            user: dca_order.user,
+            direction: params.direction,
+            total_amount: params.total_amount,
+            amount_per_cycle: params.amount_per_cycle,
+            min_amount_out_per_cycle: params.min_amount_out_per_cycle,
+            interval: params.interval,
+        });
+
+        Ok(())
+    }
+
+    // Permissionless: sells the next slice through the pool once its cycle is due, on the
+    // same terms as `swap`. A short final slice needs a proportionally smaller output.
+    // The order closes after its last slice.
+    pub fn execute_dca_cycle(ctx: Context<ExecuteDcaCycleCtx>) -> Result<()> {
+        ctx.accounts.swap.require_active(PAUSE_LIMIT_ORDERS | PAUSE_SWAPS)?;
+        ctx.accounts.pool.require_unlocked()?;
+        require!(!ctx.accounts.pool.breaker_tripped, ErrorCode::CircuitBreakerActive);
+        ctx.accounts.pool.execute_virtual_orders(&ctx.accounts.swap, Clock::get()?.unix_timestamp)?;
+
+        let dca_order = &ctx.accounts.dca_order;
+        let current_time = Clock::get()?.unix_timestamp;
+        require!(current_time >= dca_order.next_cycle_at, ErrorCode::DcaCycleNotDue);
+
+        let amount_in = dca_order.amount_per_cycle.min(dca_order.remaining_amount);
+        let min_amount_out =
+            math::mul_div_ceil(dca_order.min_amount_out_per_cycle, amount_in, dca_order.amount_per_cycle)?;
+        let direction = dca_order.direction;
+        let pool = &ctx.accounts.pool;
+        let (amount_out, _) = pool.quote_exact_in(direction, amount_in, ctx.accounts.swap.fee_rate)?;
+        require!(amount_out >= min_amount_out, ErrorCode::SlippageExceeded);
+
+        let pool_key = pool.key();
+        if ctx.accounts.pool.settle_swap(pool_key, direction, amount_in, amount_out)? {
+            return Ok(());
+        }
+
+        let pool = &ctx.accounts.pool;
+        let authority_seeds = &[VAULT_AUTHORITY_SEED, pool_key.as_ref(), &[pool.vault_authority_bump]];
+        let signer = &[&authority_seeds[..]];
+        token::transfer(ctx.accounts.into_transfer_from_escrow_context(signer), amount_in)?;
+        token::transfer(ctx.accounts.into_transfer_to_destination_context(signer), amount_out)?;
+
+        let dca_order = &mut ctx.accounts.dca_order;
+        dca_order.remaining_amount -= amount_in;
+        dca_order.received_amount += amount_out;
+        dca_order.cycle_count += 1;
+        // Measured from this execution, so a late keeper cannot sell several slices back to back.
+        dca_order.next_cycle_at = current_time.checked_add(dca_order.interval).ok_or(ErrorCode::MathOverflow)?;
+
+        emit!(DcaCycleExecuted {
+            order: dca_order.key(),
+            user: dca_order.user,
+            keeper: ctx.accounts.keeper.key(),
+            cycle: dca_order.cycle_count,
+            amount_in,
+            amount_out,
+            remaining_amount: dca_order.remaining_amount,
+            next_cycle_at: dca_order.next_cycle_at,
+        });
+
+        if dca_order.remaining_amount == 0 {
+            let order_key = dca_order.key();
+            ctx.accounts.user_order_book.remove_order(order_key);
+            token::close_account(ctx.accounts.into_close_escrow_context(signer))?;
+            ctx.accounts.dca_order.close(ctx.accounts.user.to_account_info())?;
+        }
+
+        Ok(())
+    }
+
+    // Refunds the unspent input and returns the rent of the order and its escrow.
+    pub fn cancel_dca_order(ctx: Context<CancelDcaOrderCtx>) -> Result<()> {
+        let refunded = ctx.accounts.escrow.amount;
+
+        let pool_key = ctx.accounts.pool.key();
+        let authority_seeds = &[VAULT_AUTHORITY_SEED, pool_key.as_ref(), &[ctx.accounts.pool.vault_authority_bump]];
+        let signer = &[&authority_seeds[..]];
+        token::transfer(ctx.accounts.into_refund_context(signer), refunded)?;
+        token::close_account(ctx.accounts.into_close_escrow_context(signer))?;
+        let order_key = ctx.accounts.dca_order.key();
+        ctx.accounts.user_order_book.remove_order(order_key);
+
+        emit!(DcaOrderCancelled {
+            order: order_key,
+            user: ctx.accounts.user.key(),
+            refunded,
+        });
+
+        Ok(())
+    }
+
+    // TWAMM Functions
+    // Sells `amount_in` into the pool at a constant rate from now until the `intervals`-th
+    // TWAMM_INTERVAL boundary ahead, which must leave the order at least one full
+    // interval to run. The input waits in the pool's vault outside the
+    // reserves and, like the proceeds owed back, only enters them as the stream is traded.
+    pub fn place_long_term_order(
+        ctx: Context<PlaceLongTermOrderCtx>,
+        direction: SwapDirection,
+        amount_in: u64,
+        intervals: u64,
+    ) -> Result<()> {
+        ctx.accounts.swap.require_active(PAUSE_SWAPS)?;
+        ctx.accounts.pool.require_unlocked()?;
+        require!(!ctx.accounts.pool.breaker_tripped, ErrorCode::CircuitBreakerActive);
+        let current_time = Clock::get()?.unix_timestamp;
+        ctx.accounts.pool.execute_virtual_orders(&ctx.accounts.swap, current_time)?;
+        require!(ctx.accounts.pool.lp_supply > 0, ErrorCode::ZeroReserves);
+        require!(intervals > 0, ErrorCode::InvalidLongTermOrder);
+
+        let expiry = i64::try_from(intervals)
+            .ok()
+            .and_then(|intervals| (current_time / TWAMM_INTERVAL).checked_add(intervals))
+            .and_then(|boundary| boundary.checked_mul(TWAMM_INTERVAL))
+            .ok_or(ErrorCode::MathOverflow)?;
+        require!(expiry - current_time >= TWAMM_INTERVAL, ErrorCode::InvalidLongTermOrder);
+        // Rounding the rate down leaves a little dust of the input unsold in the vault.
+        let sell_rate = math::mul_div_wide(
+            amount_in.into(),
+            math::SELL_RATE_SCALE.into(),
+            (expiry - current_time) as u128,
+        )?;
+        require!(sell_rate > 0, ErrorCode::InvalidLongTermOrder);
+        ctx.accounts.pool.add_stream_order(direction, expiry, sell_rate)?;
+        token::transfer(ctx.accounts.into_transfer_to_pool_context(), amount_in)?;
+
+        let earnings_snapshot = ctx.accounts.pool.twamm.earnings_per_rate(direction);
+        let order = &mut ctx.accounts.long_term_order;
+        order.user = *ctx.accounts.user.key;
+        order.pool = ctx.accounts.pool.key();
+        order.bump = ctx.bumps.long_term_order;
+        order.direction = direction;
+        order.amount_in = amount_in;
+        order.sell_rate = sell_rate;
+        order.start_time = current_time;
+        order.expiry = expiry;
+        order.earnings_snapshot = earnings_snapshot;
+        order.withdrawn_amount = 0;
+
+        let book = &mut ctx.accounts.user_order_book;
+        require!(book.open_orders.len() < MAX_OPEN_ORDERS, ErrorCode::TooManyOpenOrders);
+        order.nonce = book.next_nonce;
+        book.next_nonce += 1;
+        book.open_orders.push(order.key());
+
+        emit!(LongTermOrderPlaced {
+            order: order.key(),
+            user: order.user,
+            direction,
+            amount_in,
+            sell_rate,
+            expiry,
+        });
+
+        Ok(())
+    }
+
+    // Pays out everything the order has earned since its last withdrawal. Once the order
+    // has expired this is its final payout, and it closes.
+    pub fn withdraw_long_term_proceeds(ctx: Context<WithdrawLongTermProceedsCtx>) -> Result<()> {
+        ctx.accounts.pool.require_unlocked()?;
+        ctx.accounts.pool.execute_virtual_orders(&ctx.accounts.swap, Clock::get()?.unix_timestamp)?;
+
+        let order = &ctx.accounts.long_term_order;
+        let earnings = ctx.accounts.pool.stream_earnings(order.direction, order.expiry)?;
+        let proceeds = order.proceeds(earnings)?;
+        // Halted streams have not sold the whole order yet, even past its expiry.
+        let completed = order.expiry <= ctx.accounts.pool.twamm.last_virtual_order_time;
+
+        let pool_key = ctx.accounts.pool.key();
+        let authority_seeds = &[VAULT_AUTHORITY_SEED, pool_key.as_ref(), &[ctx.accounts.pool.vault_authority_bump]];
+        let signer = &[&authority_seeds[..]];
+        token::transfer(ctx.accounts.into_transfer_proceeds_context(signer), proceeds)?;
+
+        let order = &mut ctx.accounts.long_term_order;
+        order.earnings_snapshot = earnings;
+        order.withdrawn_amount += proceeds;
+        let order_key = order.key();
+
+        emit!(LongTermProceedsWithdrawn {
+            order: order_key,
+            user: order.user,
+            proceeds,
+            completed,
+        });
+
+        if completed {
+            let (direction, expiry) = (order.direction, order.expiry);
+            ctx.accounts.pool.remove_stream_order(direction, expiry, 0)?;
+            ctx.accounts.user_order_book.remove_order(order_key);
+            ctx.accounts.long_term_order.close(ctx.accounts.user.to_account_info())?;
+        }
+
+        Ok(())
+    }
+
+    // Stops an order mid-stream: pays out its proceeds so far, refunds the input it has
+    // not yet sold and returns the rent.
+    pub fn cancel_long_term_order(ctx: Context<CancelLongTermOrderCtx>) -> Result<()> {
+        ctx.accounts.pool.require_unlocked()?;
+        ctx.accounts.pool.execute_virtual_orders(&ctx.accounts.swap, Clock::get()?.unix_timestamp)?;
+
+        // Streams halted before the expiry still owe the order its unsold input.
+        let streamed_until = ctx.accounts.pool.twamm.last_virtual_order_time;
+        let order = &ctx.accounts.long_term_order;
+        require!(streamed_until < order.expiry, ErrorCode::LongTermOrderExpired);
+        let proceeds = order.proceeds(ctx.accounts.pool.twamm.earnings_per_rate(order.direction))?;
+        let refunded = math::stream_amount(order.sell_rate, (order.expiry - streamed_until) as u64)?;
+        let (direction, expiry, sell_rate) = (order.direction, order.expiry, order.sell_rate);
+        ctx.accounts.pool.remove_stream_order(direction, expiry, sell_rate)?;
+
+        let pool_key = ctx.accounts.pool.key();
+        let authority_seeds = &[VAULT_AUTHORITY_SEED, pool_key.as_ref(), &[ctx.accounts.pool.vault_authority_bump]];
+        let signer = &[&authority_seeds[..]];
+        token::transfer(ctx.accounts.into_refund_context(signer), refunded)?;
+        token::transfer(ctx.accounts.into_transfer_proceeds_context(signer), proceeds)?;
+        let order_key = ctx.accounts.long_term_order.key();
+        ctx.accounts.user_order_book.remove_order(order_key);
+
+        emit!(LongTermOrderCancelled {
+            order: order_key,
+            user: ctx.accounts.user.key(),
+            refunded,
+            proceeds,
+        });
+
+        Ok(())
+    }
+
+    // Permissionless settlement of an expired long-term order: pays its last proceeds to
+    // the owner, returns the rent and drops it from its expiry entry, so orders their
+    // owners never withdraw cannot hold the pool's expiry slots forever.
+    pub fn settle_long_term_order(ctx: Context<SettleLongTermOrderCtx>) -> Result<()> {
+        ctx.accounts.pool.require_unlocked()?;
+        let current_time = Clock::get()?.unix_timestamp;
+        ctx.accounts.pool.execute_virtual_orders(&ctx.accounts.swap, current_time)?;
+
+        let order = &ctx.accounts.long_term_order;
+        require!(current_time >= order.expiry, ErrorCode::OrderNotExpired);
+        require!(order.expiry <= ctx.accounts.pool.twamm.last_virtual_order_time, ErrorCode::StreamsHalted);
+        let proceeds = order.proceeds(ctx.accounts.pool.stream_earnings(order.direction, order.expiry)?)?;
+        let (direction, expiry) = (order.direction, order.expiry);
+        ctx.accounts.pool.remove_stream_order(direction, expiry, 0)?;
+
+        let pool_key = ctx.accounts.pool.key();
+        let authority_seeds = &[VAULT_AUTHORITY_SEED, pool_key.as_ref(), &[ctx.accounts.pool.vault_authority_bump]];
+        let signer = &[&authority_seeds[..]];
+        token::transfer(ctx.accounts.into_transfer_proceeds_context(signer), proceeds)?;
+        let order_key = ctx.accounts.long_term_order.key();
+        ctx.accounts.user_order_book.remove_order(order_key);
+
+        emit!(LongTermProceedsWithdrawn {
+            order: order_key,
+            user: ctx.accounts.user.key(),
+            proceeds,
+            completed: true,
         });
 
         Ok(())
     }
 
+    // Permissionless crank that runs the pool's streams forward, crossing at most
+    // `max_expiries` expiries. Every pool instruction catches up in full first, so when
+    // too many expiries have piled up to fit one transaction, keepers crank in steps.
+    pub fn execute_virtual_orders(ctx: Context<ExecuteVirtualOrdersCtx>, max_expiries: u8) -> Result<()> {
+        ctx.accounts.pool.require_unlocked()?;
+        let current_time = Clock::get()?.unix_timestamp;
+        ctx.accounts.pool.execute_virtual_orders_bounded(&ctx.accounts.swap, current_time, max_expiries.into())
+    }
+
     // Multi-Token Swap Function
-    pub fn multi_token_swap(
-        ctx: Context<MultiTokenSwapCtx>,
-        src_token: Pubkey,
-        dst_token: Pubkey,
+    // Swaps `amount` of the source account's token along a route of pools passed in
+    // `remaining_accounts`, one (pool, swap state, pool authority, input vault, output
+    // vault) group per hop. Each hop trades exact-in at its pool's curve and fee, in
+    // whichever direction takes the previous hop's output token, and pays its output
+    // straight into the next hop's input vault. The route must end in the destination
+    // account's token with at least `min_dst_amount`.
+    pub fn multi_token_swap<'info>(
+        ctx: Context<'_, '_, 'info, 'info, MultiTokenSwapCtx<'info>>,
         amount: u64,
         min_dst_amount: u64,
     ) -> Result<()> {
-        let pool = &ctx.accounts.pool;
-
-        let intermediate_amount = route_swap(amount, pool.token_a_reserve, pool.token_b_reserve)?;
-        require!(intermediate_amount >= min_dst_amount, ErrorCode::SlippageExceeded);
-
-        let transfer_ctx = ctx.accounts.into_transfer_context();
-        token::transfer(transfer_ctx, intermediate_amount)?;
+        let hop_accounts = ctx.remaining_accounts.chunks_exact(5);
+        require!(
+            !ctx.remaining_accounts.is_empty() && hop_accounts.remainder().is_empty(),
+            ErrorCode::InvalidRoute
+        );
+
+        let now = Clock::get()?.unix_timestamp;
+        let mut hops: Vec<RouteHop> = Vec::new();
+        let mut mint = ctx.accounts.user_source_account.mint;
+        let mut amount_in = amount;
+        for accounts in hop_accounts {
+            let [pool_info, swap_info, pool_authority, vault_in, vault_out] = accounts else {
+                return err!(ErrorCode::InvalidRoute);
+            };
+            let mut pool: Account<'info, LiquidityPool> = Account::try_from(pool_info)?;
+            let swap: Account<'info, SwapState> = Account::try_from(swap_info)?;
+            require!(
+                pool.swap == swap.key() && hops.iter().all(|hop| hop.pool.key() != pool.key()),
+                ErrorCode::InvalidRoute
+            );
+            swap.require_active(PAUSE_SWAPS)?;
+            pool.require_unlocked()?;
+            require!(!pool.breaker_tripped, ErrorCode::CircuitBreakerActive);
+            pool.execute_virtual_orders(&swap, now)?;
+
+            // Mint continuity: the hop sells whichever side of the pool the route holds.
+            let direction = if mint == pool.token_a_mint {
+                SwapDirection::AToB
+            } else if mint == pool.token_b_mint {
+                SwapDirection::BToA
+            } else {
+                return err!(ErrorCode::InvalidRoute);
+            };
+            let pool_key = pool.key();
+            let authority = Pubkey::create_program_address(
+                &[VAULT_AUTHORITY_SEED, pool_key.as_ref(), &[pool.vault_authority_bump]],
+                &crate::ID,
+            )
+            .map_err(|_| ErrorCode::InvalidRoute)?;
+            require_keys_eq!(pool_authority.key(), authority, ErrorCode::InvalidRoute);
+            let (expected_vault_in, expected_vault_out) = pool.vaults(direction);
+            require!(
+                vault_in.key() == expected_vault_in && vault_out.key() == expected_vault_out,
+                ErrorCode::InvalidVault
+            );
+
+            // The input is pulled into the first hop before any breaker runs, so only a
+            // funded route can trip one.
+            if hops.is_empty() {
+                token::transfer(ctx.accounts.into_transfer_to_pool_context(vault_in.clone()), amount)?;
+            }
+            let (amount_out, fee) = pool.quote_exact_in(direction, amount_in, swap.fee_rate)?;
+            if pool.settle_swap(pool_key, direction, amount_in, amount_out)? {
+                // Refund the input from the first hop, persist the auto-pause and drop the
+                // rest of the route; earlier hops are never written back.
+                let (first_pool, first_vault, first_authority) = match hops.first() {
+                    Some(hop) => (&hop.pool, hop.vault_in, hop.pool_authority),
+                    None => (&pool, vault_in, pool_authority),
+                };
+                let first_key = first_pool.key();
+                let authority_seeds = &[VAULT_AUTHORITY_SEED, first_key.as_ref(), &[first_pool.vault_authority_bump]];
+                let signer = &[&authority_seeds[..]];
+                token::transfer(
+                    ctx.accounts.into_vault_transfer_context(
+                        first_vault.clone(),
+                        ctx.accounts.user_source_account.to_account_info(),
+                        first_authority.clone(),
+                        signer,
+                    ),
+                    amount,
+                )?;
+                pool.exit(&crate::ID)?;
+                return Ok(());
+            }
+            mint = pool.mints(direction).1;
+            hops.push(RouteHop {
+                pool,
+                pool_authority,
+                vault_in,
+                vault_out,
+                direction,
+                amount_in,
+                amount_out,
+                fee,
+            });
+            amount_in = amount_out;
+        }
+        require_keys_eq!(mint, ctx.accounts.user_destination_account.mint, ErrorCode::InvalidRoute);
+        require!(amount_in >= min_dst_amount, ErrorCode::SlippageExceeded);
+
+        for (index, hop) in hops.iter().enumerate() {
+            let to = match hops.get(index + 1) {
+                Some(next) => next.vault_in.clone(),
+                None => ctx.accounts.user_destination_account.to_account_info(),
+            };
+            let pool_key = hop.pool.key();
+            let authority_seeds = &[VAULT_AUTHORITY_SEED, pool_key.as_ref(), &[hop.pool.vault_authority_bump]];
+            let signer = &[&authority_seeds[..]];
+            token::transfer(
+                ctx.accounts.into_vault_transfer_context(hop.vault_out.clone(), to, hop.pool_authority.clone(), signer),
+                hop.amount_out,
+            )?;
+            hop.pool.exit(&crate::ID)?;
+        }
 
         emit!(MultiTokenSwapEvent {
-            user: *ctx.accounts.user.to_account_info().key,
-            src_token,
-            dst_token,
+            user: ctx.accounts.user.key(),
+            src_token: ctx.accounts.user_source_account.mint,
+            dst_token: mint,
             amount,
-            received: intermediate_amount,
+            received: amount_in,
+            hops: hops
+                .iter()
+                .map(|hop| SwapHop {
+                    pool: hop.pool.key(),
+                    direction: hop.direction,
+                    amount_in: hop.amount_in,
+                    amount_out: hop.amount_out,
+                    fee: hop.fee,
+                })
+                .collect(),
         });
 
         Ok(())
     }
 
     // Flash Swap Function
+    // Lends `amount_a` of token A to `user_token_a_account`, then calls `on_flash_swap`
+    // on `target_contract` with the remaining accounts. The borrower must return the
+    // principal plus the flash fee to the vault before its callback returns.
     pub fn flash_swap(ctx: Context<FlashSwapCtx>, amount_a: u64, target_contract: Pubkey) -> Result<()> {
-        let initial_balance = ctx.accounts.pool.token_a_reserve;
-
-        let transfer_ctx = ctx.accounts.into_transfer_context();
+        ctx.accounts.swap.require_active(PAUSE_FLASH_SWAPS)?;
+        ctx.accounts.pool.require_unlocked()?;
+        require!(amount_a <= ctx.accounts.pool.token_a_reserve, ErrorCode::InsufficientReserves);
+
+        let fee = ctx.accounts.pool.flash_fee(amount_a)?;
+        let required_balance = ctx
+            .accounts
+            .pool_token_a_account
+            .amount
+            .checked_add(fee)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let pool_key = ctx.accounts.pool.key();
+        let authority_seeds = &[VAULT_AUTHORITY_SEED, pool_key.as_ref(), &[ctx.accounts.pool.vault_authority_bump]];
+        let signer = &[&authority_seeds[..]];
+        let transfer_ctx = ctx.accounts.into_transfer_to_user_context(signer);
         token::transfer(transfer_ctx, amount_a)?;
 
+        // The runtime already refuses a callback that CPIs back into this program. The lock
+        // is what guards the flash_borrow/flash_repay window; it is persisted here too so
+        // that the pool account never reads as unlocked while its token A is out on loan.
+        ctx.accounts.pool.locked = true;
+        ctx.accounts.pool.exit(&crate::ID)?;
+
+        let mut data = FLASH_SWAP_CALLBACK_DISCRIMINATOR.to_vec();
+        FlashSwapCallback { amount: amount_a, fee }.serialize(&mut data)?;
         let ix = solana_program::instruction::Instruction {
             program_id: target_contract,
-            accounts: vec![],
-            data: vec![],
+            accounts: ctx
+                .remaining_accounts
+                .iter()
+                .flat_map(|account| account.to_account_metas(None))
+                .collect(),
+            data,
         };
-        solana_program::program::invoke_signed(&ix, &[], &[])?;
+        solana_program::program::invoke(&ix, ctx.remaining_accounts)?;
 
+        ctx.accounts.pool_token_a_account.reload()?;
+        require!(
+            ctx.accounts.pool_token_a_account.amount >= required_balance,
+            ErrorCode::FlashSwapFailed
+        );
+
+        ctx.accounts.pool.reload()?;
         let pool = &mut ctx.accounts.pool;
-        let current_balance = pool.token_a_reserve;
-        require!(current_balance >= initial_balance, ErrorCode::FlashSwapFailed);
+        pool.locked = false;
+        pool.record_flash_loan(amount_a, fee);
 
         emit!(FlashSwapEvent {
             user: *ctx.accounts.user.to_account_info().key,
             amount_a,
+            fee,
             target_contract,
         });
 
         Ok(())
     }
+
+    // Callback-free flash loan: lends `amount` of token A and locks the pool until a
+    // `flash_repay` for the same pool and amount, which must appear later in this
+    // transaction, returns it with the flash fee. Anything may run in between.
+    pub fn flash_borrow(ctx: Context<FlashBorrowCtx>, amount: u64) -> Result<()> {
+        ctx.accounts.swap.require_active(PAUSE_FLASH_SWAPS)?;
+        ctx.accounts.pool.require_unlocked()?;
+        require!(amount > 0, ErrorCode::ZeroOutput);
+        require!(amount <= ctx.accounts.pool.token_a_reserve, ErrorCode::InsufficientReserves);
+
+        let pool_key = ctx.accounts.pool.key();
+        require!(
+            has_matching_repay(&ctx.accounts.instructions, pool_key, amount)?,
+            ErrorCode::FlashRepayMissing
+        );
+
+        let authority_seeds = &[VAULT_AUTHORITY_SEED, pool_key.as_ref(), &[ctx.accounts.pool.vault_authority_bump]];
+        let signer = &[&authority_seeds[..]];
+        let transfer_ctx = ctx.accounts.into_transfer_to_user_context(signer);
+        token::transfer(transfer_ctx, amount)?;
+
+        let pool = &mut ctx.accounts.pool;
+        pool.locked = true;
+        pool.flash_loan_amount = amount;
+
+        Ok(())
+    }
+
+    // Closes the loan opened by `flash_borrow`; the signer pays the principal plus the fee.
+    pub fn flash_repay(ctx: Context<FlashRepayCtx>, amount: u64) -> Result<()> {
+        let pool = &ctx.accounts.pool;
+        require!(
+            pool.locked && pool.flash_loan_amount > 0 && pool.flash_loan_amount == amount,
+            ErrorCode::NoOutstandingFlashLoan
+        );
+
+        let fee = pool.flash_fee(amount)?;
+        let repayment = amount.checked_add(fee).ok_or(ErrorCode::MathOverflow)?;
+        token::transfer(ctx.accounts.into_transfer_to_pool_context(), repayment)?;
+
+        let pool = &mut ctx.accounts.pool;
+        pool.locked = false;
+        pool.flash_loan_amount = 0;
+        pool.record_flash_loan(amount, fee);
+
+        emit!(FlashRepayEvent {
+            user: *ctx.accounts.user.to_account_info().key,
+            amount,
+            fee,
+        });
+
+        Ok(())
+    }
+}
+
+// One leg of a multi_token_swap route, validated and priced but not yet paid out.
+struct RouteHop<'a, 'info> {
+    pool: Account<'info, LiquidityPool>,
+    pool_authority: &'a AccountInfo<'info>,
+    vault_in: &'a AccountInfo<'info>,
+    vault_out: &'a AccountInfo<'info>,
+    direction: SwapDirection,
+    amount_in: u64,
+    amount_out: u64,
+    fee: u64,
+}
+
+// A resting maker order crossed by an executing order, and what each side pays.
+struct MakerMatch<'a, 'info> {
+    maker: Account<'info, LimitOrder>,
+    escrow: &'a AccountInfo<'info>,
+    destination: &'a AccountInfo<'info>,
+    // The maker's owner and their UserOrderBook, used once the maker is fully matched.
+    user: &'a AccountInfo<'info>,
+    user_order_book: &'a AccountInfo<'info>,
+    taker_in: u64,
+    maker_in: u64,
+    keeper_fee: u64,
+}
+
+// Whether `destination` is a live, unfrozen token account of `mint` that a transfer can pay.
+fn accepts_deposits(destination: &AccountInfo, mint: Pubkey) -> bool {
+    if destination.owner != &token::ID {
+        return false;
+    }
+    let Ok(data) = destination.try_borrow_data() else {
+        return false;
+    };
+    matches!(
+        token::spl_token::state::Account::unpack(&data),
+        Ok(account) if account.mint == mint && !account.is_frozen()
+    )
+}
+
+fn escrow_address(order: Pubkey, escrow_bump: u8) -> Result<Pubkey> {
+    Pubkey::create_program_address(&[ESCROW_SEED, order.as_ref(), &[escrow_bump]], &crate::ID)
+        .map_err(|_| ErrorCode::InvalidMaker.into())
+}
+
+fn user_order_book_address(user: Pubkey, bump: u8) -> Result<Pubkey> {
+    Pubkey::create_program_address(&[USER_ORDER_BOOK_SEED, user.as_ref(), &[bump]], &crate::ID)
+        .map_err(|_| ErrorCode::InvalidMaker.into())
+}
+
+// Takes a resting order off its pool's order book, which must then be passed in.
+fn leave_order_book(order: &LimitOrder, order_key: Pubkey, order_book: &Option<AccountLoader<OrderBook>>) -> Result<()> {
+    if order.on_book {
+        let order_book = order_book.as_ref().ok_or(ErrorCode::OrderBookRequired)?;
+        order_book.load_mut()?.remove(order.direction, order_key);
+    }
+    Ok(())
+}
+
+// Scans the instructions after the current one for a `flash_repay` of `amount` against `pool`.
+fn has_matching_repay(instructions: &AccountInfo, pool: Pubkey, amount: u64) -> Result<bool> {
+    let current = sysvar_instructions::load_current_index_checked(instructions)? as usize;
+    let mut index = current + 1;
+    while let Ok(ix) = sysvar_instructions::load_instruction_at_checked(index, instructions) {
+        index += 1;
+        if ix.program_id != crate::ID || ix.data.len() < 8 || ix.data[..8] != instruction::FlashRepay::DISCRIMINATOR {
+            continue;
+        }
+        let Ok(args) = instruction::FlashRepay::try_from_slice(&ix.data[8..]) else {
+            continue;
+        };
+        // `pool` is the second account of FlashRepayCtx.
+        if args.amount == amount && ix.accounts.get(1).map(|meta| meta.pubkey) == Some(pool) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
 }
 
 // Context Structs
 #[derive(Accounts)]
 pub struct Initialize<'info> {
-    #[account(init, payer = user, space = 8 + 8 + 1)]
+    #[account(init, payer = user, space = 8 + SwapState::INIT_SPACE)]
     pub swap: Account<'info, SwapState>,
     #[account(mut)]
     pub user: Signer<'info>,
@@ -189,18 +1587,96 @@ pub struct Initialize<'info> {
 }
 
 #[derive(Accounts)]
-pub struct AddLiquidityCtx<'info> {
-    #[account(mut)]
-    pub user: Signer<'info>,
-    #[account(mut)]
-    pub pool: Account<'info, LiquidityPool>,
-    #[account(mut)]
-    pub user_token_a_account: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub user_token_b_account: Account<'info, TokenAccount>,
+pub struct AdminCtx<'info> {
+    pub admin: Signer<'info>,
+    #[account(mut, has_one = admin @ ErrorCode::Unauthorized)]
+    pub swap: Account<'info, SwapState>,
+}
+
+#[derive(Accounts)]
+pub struct PoolAdminCtx<'info> {
+    pub admin: Signer<'info>,
+    #[account(has_one = admin @ ErrorCode::Unauthorized)]
+    pub swap: Account<'info, SwapState>,
+    #[account(mut, has_one = swap)]
+    pub pool: Account<'info, LiquidityPool>,
+}
+
+#[derive(Accounts)]
+pub struct InitializePoolCtx<'info> {
     #[account(mut)]
+    pub user: Signer<'info>,
+    // Only the swap's admin opens pools under it.
+    #[account(constraint = swap.admin == user.key() @ ErrorCode::Unauthorized)]
+    pub swap: Account<'info, SwapState>,
+    // Mints are canonically ordered so each pair maps to exactly one pool per swap.
+    #[account(constraint = token_a_mint.key() < token_b_mint.key() @ ErrorCode::MintsNotOrdered)]
+    pub token_a_mint: Account<'info, Mint>,
+    pub token_b_mint: Account<'info, Mint>,
+    #[account(
+        init,
+        payer = user,
+        space = 8 + LiquidityPool::INIT_SPACE,
+        seeds = [POOL_SEED, swap.key().as_ref(), token_a_mint.key().as_ref(), token_b_mint.key().as_ref()],
+        bump
+    )]
+    pub pool: Account<'info, LiquidityPool>,
+    /// CHECK: PDA that owns the pool vaults; it holds no data and only signs payouts.
+    #[account(seeds = [VAULT_AUTHORITY_SEED, pool.key().as_ref()], bump)]
+    pub pool_authority: UncheckedAccount<'info>,
+    #[account(
+        init,
+        payer = user,
+        seeds = [VAULT_SEED, pool.key().as_ref(), token_a_mint.key().as_ref()],
+        bump,
+        token::mint = token_a_mint,
+        token::authority = pool_authority
+    )]
     pub pool_token_a_account: Account<'info, TokenAccount>,
+    #[account(
+        init,
+        payer = user,
+        seeds = [VAULT_SEED, pool.key().as_ref(), token_b_mint.key().as_ref()],
+        bump,
+        token::mint = token_b_mint,
+        token::authority = pool_authority
+    )]
+    pub pool_token_b_account: Account<'info, TokenAccount>,
+    #[account(
+        init,
+        payer = user,
+        seeds = [LP_MINT_SEED, pool.key().as_ref()],
+        bump,
+        mint::decimals = LP_DECIMALS,
+        mint::authority = pool_authority
+    )]
+    pub lp_mint: Account<'info, Mint>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct AddLiquidityCtx<'info> {
     #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(mut, has_one = swap)]
+    pub pool: Account<'info, LiquidityPool>,
+    pub swap: Account<'info, SwapState>,
+    /// CHECK: PDA that owns the pool vaults; it holds no data and only signs payouts.
+    #[account(seeds = [VAULT_AUTHORITY_SEED, pool.key().as_ref()], bump = pool.vault_authority_bump)]
+    pub pool_authority: UncheckedAccount<'info>,
+    #[account(mut, address = pool.lp_mint @ ErrorCode::InvalidMint)]
+    pub lp_mint: Account<'info, Mint>,
+    #[account(mut, constraint = user_lp_account.mint == pool.lp_mint @ ErrorCode::InvalidMint)]
+    pub user_lp_account: Account<'info, TokenAccount>,
+    #[account(mut, constraint = user_token_a_account.mint == pool.token_a_mint @ ErrorCode::InvalidMint)]
+    pub user_token_a_account: Account<'info, TokenAccount>,
+    #[account(mut, constraint = user_token_b_account.mint == pool.token_b_mint @ ErrorCode::InvalidMint)]
+    pub user_token_b_account: Account<'info, TokenAccount>,
+    #[account(mut, address = pool.token_a_vault @ ErrorCode::InvalidVault)]
+    pub pool_token_a_account: Account<'info, TokenAccount>,
+    #[account(mut, address = pool.token_b_vault @ ErrorCode::InvalidVault)]
     pub pool_token_b_account: Account<'info, TokenAccount>,
     pub token_program: Program<'info, Token>,
 }
@@ -228,133 +1704,1265 @@ impl<'info> AddLiquidityCtx<'info> {
             },
         )
     }
+
+    fn into_mint_lp_context<'a, 'b, 'c>(
+        &self,
+        signer: &'a [&'b [&'c [u8]]],
+    ) -> CpiContext<'a, 'b, 'c, 'info, MintTo<'info>> {
+        CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            MintTo {
+                mint: self.lp_mint.to_account_info(),
+                to: self.user_lp_account.to_account_info(),
+                authority: self.pool_authority.to_account_info(),
+            },
+            signer,
+        )
+    }
 }
 
 #[derive(Accounts)]
-pub struct SimpleSwapCtx<'info> {
+pub struct RemoveLiquidityCtx<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
+    #[account(mut, has_one = swap)]
+    pub pool: Account<'info, LiquidityPool>,
+    pub swap: Account<'info, SwapState>,
+    /// CHECK: PDA that owns the pool vaults; it holds no data and only signs payouts.
+    #[account(seeds = [VAULT_AUTHORITY_SEED, pool.key().as_ref()], bump = pool.vault_authority_bump)]
+    pub pool_authority: UncheckedAccount<'info>,
+    #[account(mut, address = pool.lp_mint @ ErrorCode::InvalidMint)]
+    pub lp_mint: Account<'info, Mint>,
+    #[account(mut, constraint = user_lp_account.mint == pool.lp_mint @ ErrorCode::InvalidMint)]
+    pub user_lp_account: Account<'info, TokenAccount>,
+    #[account(mut, constraint = user_token_a_account.mint == pool.token_a_mint @ ErrorCode::InvalidMint)]
+    pub user_token_a_account: Account<'info, TokenAccount>,
+    #[account(mut, constraint = user_token_b_account.mint == pool.token_b_mint @ ErrorCode::InvalidMint)]
+    pub user_token_b_account: Account<'info, TokenAccount>,
+    #[account(mut, address = pool.token_a_vault @ ErrorCode::InvalidVault)]
+    pub pool_token_a_account: Account<'info, TokenAccount>,
+    #[account(mut, address = pool.token_b_vault @ ErrorCode::InvalidVault)]
+    pub pool_token_b_account: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+}
+
+// Implement the helper functions for RemoveLiquidityCtx
+impl<'info> RemoveLiquidityCtx<'info> {
+    fn into_burn_lp_context(&self) -> CpiContext<'_, '_, '_, 'info, Burn<'info>> {
+        CpiContext::new(
+            self.token_program.to_account_info(),
+            Burn {
+                mint: self.lp_mint.to_account_info(),
+                from: self.user_lp_account.to_account_info(),
+                authority: self.user.to_account_info(),
+            },
+        )
+    }
+
+    fn into_transfer_to_user_context_a<'a, 'b, 'c>(
+        &self,
+        signer: &'a [&'b [&'c [u8]]],
+    ) -> CpiContext<'a, 'b, 'c, 'info, Transfer<'info>> {
+        CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            Transfer {
+                from: self.pool_token_a_account.to_account_info(),
+                to: self.user_token_a_account.to_account_info(),
+                authority: self.pool_authority.to_account_info(),
+            },
+            signer,
+        )
+    }
+
+    fn into_transfer_to_user_context_b<'a, 'b, 'c>(
+        &self,
+        signer: &'a [&'b [&'c [u8]]],
+    ) -> CpiContext<'a, 'b, 'c, 'info, Transfer<'info>> {
+        CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            Transfer {
+                from: self.pool_token_b_account.to_account_info(),
+                to: self.user_token_b_account.to_account_info(),
+                authority: self.pool_authority.to_account_info(),
+            },
+            signer,
+        )
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(direction: SwapDirection)]
+pub struct AddLiquiditySingleCtx<'info> {
     #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(mut, has_one = swap)]
     pub pool: Account<'info, LiquidityPool>,
+    pub swap: Account<'info, SwapState>,
+    /// CHECK: PDA that owns the pool vaults; it holds no data and only signs payouts.
+    #[account(seeds = [VAULT_AUTHORITY_SEED, pool.key().as_ref()], bump = pool.vault_authority_bump)]
+    pub pool_authority: UncheckedAccount<'info>,
+    #[account(mut, address = pool.lp_mint @ ErrorCode::InvalidMint)]
+    pub lp_mint: Account<'info, Mint>,
+    #[account(mut, constraint = user_lp_account.mint == pool.lp_mint @ ErrorCode::InvalidMint)]
+    pub user_lp_account: Account<'info, TokenAccount>,
+    #[account(mut, constraint = user_token_account.mint == pool.mints(direction).0 @ ErrorCode::InvalidMint)]
+    pub user_token_account: Account<'info, TokenAccount>,
+    #[account(mut, address = pool.vaults(direction).0 @ ErrorCode::InvalidVault)]
+    pub pool_token_account: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+}
+
+// Implement the helper functions for AddLiquiditySingleCtx
+impl<'info> AddLiquiditySingleCtx<'info> {
+    fn into_transfer_to_pool_context(&self) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
+        CpiContext::new(
+            self.token_program.to_account_info(),
+            Transfer {
+                from: self.user_token_account.to_account_info(),
+                to: self.pool_token_account.to_account_info(),
+                authority: self.user.to_account_info(),
+            },
+        )
+    }
+
+    fn into_mint_lp_context<'a, 'b, 'c>(
+        &self,
+        signer: &'a [&'b [&'c [u8]]],
+    ) -> CpiContext<'a, 'b, 'c, 'info, MintTo<'info>> {
+        CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            MintTo {
+                mint: self.lp_mint.to_account_info(),
+                to: self.user_lp_account.to_account_info(),
+                authority: self.pool_authority.to_account_info(),
+            },
+            signer,
+        )
+    }
+
+    // Returns the input of a deposit skipped by the circuit breaker.
+    fn into_refund_context<'a, 'b, 'c>(
+        &self,
+        signer: &'a [&'b [&'c [u8]]],
+    ) -> CpiContext<'a, 'b, 'c, 'info, Transfer<'info>> {
+        CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            Transfer {
+                from: self.pool_token_account.to_account_info(),
+                to: self.user_token_account.to_account_info(),
+                authority: self.pool_authority.to_account_info(),
+            },
+            signer,
+        )
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(direction: SwapDirection)]
+pub struct RemoveLiquiditySingleCtx<'info> {
     #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(mut, has_one = swap)]
+    pub pool: Account<'info, LiquidityPool>,
     pub swap: Account<'info, SwapState>,
+    /// CHECK: PDA that owns the pool vaults; it holds no data and only signs payouts.
+    #[account(seeds = [VAULT_AUTHORITY_SEED, pool.key().as_ref()], bump = pool.vault_authority_bump)]
+    pub pool_authority: UncheckedAccount<'info>,
+    #[account(mut, address = pool.lp_mint @ ErrorCode::InvalidMint)]
+    pub lp_mint: Account<'info, Mint>,
+    #[account(mut, constraint = user_lp_account.mint == pool.lp_mint @ ErrorCode::InvalidMint)]
+    pub user_lp_account: Account<'info, TokenAccount>,
+    #[account(mut, constraint = user_token_account.mint == pool.mints(direction).1 @ ErrorCode::InvalidMint)]
+    pub user_token_account: Account<'info, TokenAccount>,
+    #[account(mut, address = pool.vaults(direction).1 @ ErrorCode::InvalidVault)]
+    pub pool_token_account: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+}
+
+// Implement the helper functions for RemoveLiquiditySingleCtx
+impl<'info> RemoveLiquiditySingleCtx<'info> {
+    fn into_burn_lp_context(&self) -> CpiContext<'_, '_, '_, 'info, Burn<'info>> {
+        CpiContext::new(
+            self.token_program.to_account_info(),
+            Burn {
+                mint: self.lp_mint.to_account_info(),
+                from: self.user_lp_account.to_account_info(),
+                authority: self.user.to_account_info(),
+            },
+        )
+    }
+
+    fn into_transfer_to_user_context<'a, 'b, 'c>(
+        &self,
+        signer: &'a [&'b [&'c [u8]]],
+    ) -> CpiContext<'a, 'b, 'c, 'info, Transfer<'info>> {
+        CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            Transfer {
+                from: self.pool_token_account.to_account_info(),
+                to: self.user_token_account.to_account_info(),
+                authority: self.pool_authority.to_account_info(),
+            },
+            signer,
+        )
+    }
+
+    // Re-mints the LP tokens of a withdrawal skipped by the circuit breaker.
+    fn into_mint_lp_context<'a, 'b, 'c>(
+        &self,
+        signer: &'a [&'b [&'c [u8]]],
+    ) -> CpiContext<'a, 'b, 'c, 'info, MintTo<'info>> {
+        CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            MintTo {
+                mint: self.lp_mint.to_account_info(),
+                to: self.user_lp_account.to_account_info(),
+                authority: self.pool_authority.to_account_info(),
+            },
+            signer,
+        )
+    }
+}
+
+#[derive(Accounts)]
+pub struct SimpleSwapCtx<'info> {
     #[account(mut)]
-    pub user_token_a_account: Account<'info, TokenAccount>,
+    pub user: Signer<'info>,
+    #[account(mut, has_one = swap)]
+    pub pool: Account<'info, LiquidityPool>,
     #[account(mut)]
+    pub swap: Account<'info, SwapState>,
+    /// CHECK: PDA that owns the pool vaults; it holds no data and only signs payouts.
+    #[account(seeds = [VAULT_AUTHORITY_SEED, pool.key().as_ref()], bump = pool.vault_authority_bump)]
+    pub pool_authority: UncheckedAccount<'info>,
+    #[account(mut, constraint = user_token_a_account.mint == pool.token_a_mint @ ErrorCode::InvalidMint)]
+    pub user_token_a_account: Account<'info, TokenAccount>,
+    #[account(mut, constraint = user_token_b_account.mint == pool.token_b_mint @ ErrorCode::InvalidMint)]
     pub user_token_b_account: Account<'info, TokenAccount>,
-    #[account(mut)]
+    #[account(mut, address = pool.token_a_vault @ ErrorCode::InvalidVault)]
     pub pool_token_a_account: Account<'info, TokenAccount>,
-    #[account(mut)]
+    #[account(mut, address = pool.token_b_vault @ ErrorCode::InvalidVault)]
     pub pool_token_b_account: Account<'info, TokenAccount>,
     pub token_program: Program<'info, Token>,
 }
 
 // Implement the helper functions for SimpleSwapCtx
 impl<'info> SimpleSwapCtx<'info> {
-    fn into_transfer_to_pool_context(&self) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
+    fn into_transfer_to_pool_context(&self, direction: SwapDirection) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
+        let (from, to) = match direction {
+            SwapDirection::AToB => (&self.user_token_a_account, &self.pool_token_a_account),
+            SwapDirection::BToA => (&self.user_token_b_account, &self.pool_token_b_account),
+        };
         CpiContext::new(
             self.token_program.to_account_info(),
             Transfer {
-                from: self.user_token_a_account.to_account_info(),
-                to: self.pool_token_a_account.to_account_info(),
+                from: from.to_account_info(),
+                to: to.to_account_info(),
                 authority: self.user.to_account_info(),
             },
         )
     }
 
-    fn into_transfer_to_user_context(&self) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
+    fn into_transfer_to_user_context<'a, 'b, 'c>(
+        &self,
+        direction: SwapDirection,
+        signer: &'a [&'b [&'c [u8]]],
+    ) -> CpiContext<'a, 'b, 'c, 'info, Transfer<'info>> {
+        let (from, to) = match direction {
+            SwapDirection::AToB => (&self.pool_token_b_account, &self.user_token_b_account),
+            SwapDirection::BToA => (&self.pool_token_a_account, &self.user_token_a_account),
+        };
+        CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            Transfer {
+                from: from.to_account_info(),
+                to: to.to_account_info(),
+                authority: self.pool_authority.to_account_info(),
+            },
+            signer,
+        )
+    }
+
+    // Returns the input of a swap skipped by the circuit breaker.
+    fn into_refund_context<'a, 'b, 'c>(
+        &self,
+        direction: SwapDirection,
+        signer: &'a [&'b [&'c [u8]]],
+    ) -> CpiContext<'a, 'b, 'c, 'info, Transfer<'info>> {
+        let (from, to) = match direction {
+            SwapDirection::AToB => (&self.pool_token_a_account, &self.user_token_a_account),
+            SwapDirection::BToA => (&self.pool_token_b_account, &self.user_token_b_account),
+        };
+        CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            Transfer {
+                from: from.to_account_info(),
+                to: to.to_account_info(),
+                authority: self.pool_authority.to_account_info(),
+            },
+            signer,
+        )
+    }
+}
+
+#[derive(Accounts)]
+pub struct InitializeOrderBookCtx<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(mut, has_one = swap)]
+    pub pool: Account<'info, LiquidityPool>,
+    #[account(constraint = swap.admin == payer.key() @ ErrorCode::Unauthorized)]
+    pub swap: Account<'info, SwapState>,
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + std::mem::size_of::<OrderBook>(),
+        seeds = [ORDER_BOOK_SEED, pool.key().as_ref()],
+        bump
+    )]
+    pub order_book: AccountLoader<'info, OrderBook>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeUserOrderBookCtx<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(
+        init,
+        payer = user,
+        space = 8 + UserOrderBook::INIT_SPACE,
+        seeds = [USER_ORDER_BOOK_SEED, user.key().as_ref()],
+        bump
+    )]
+    pub user_order_book: Account<'info, UserOrderBook>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(params: LimitOrderParams)]
+pub struct PlaceLimitOrderCtx<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(has_one = swap)]
+    pub pool: Account<'info, LiquidityPool>,
+    pub swap: Account<'info, SwapState>,
+    /// CHECK: PDA that owns the pool vaults; it holds no data and only signs payouts.
+    #[account(seeds = [VAULT_AUTHORITY_SEED, pool.key().as_ref()], bump = pool.vault_authority_bump)]
+    pub pool_authority: UncheckedAccount<'info>,
+    #[account(mut, has_one = user, seeds = [USER_ORDER_BOOK_SEED, user.key().as_ref()], bump = user_order_book.bump)]
+    pub user_order_book: Account<'info, UserOrderBook>,
+    #[account(
+        init,
+        payer = user,
+        space = 8 + LimitOrder::INIT_SPACE,
+        seeds = [ORDER_SEED, pool.key().as_ref(), user.key().as_ref(), &user_order_book.next_nonce.to_le_bytes()],
+        bump
+    )]
+    pub order: Account<'info, LimitOrder>,
+    // Optional: rests the order on the pool's order book.
+    #[account(mut, has_one = pool)]
+    pub order_book: Option<AccountLoader<'info, OrderBook>>,
+    #[account(address = pool.mints(params.direction).0 @ ErrorCode::InvalidMint)]
+    pub input_mint: Account<'info, Mint>,
+    #[account(
+        init,
+        payer = user,
+        seeds = [ESCROW_SEED, order.key().as_ref()],
+        bump,
+        token::mint = input_mint,
+        token::authority = pool_authority
+    )]
+    pub escrow: Account<'info, TokenAccount>,
+    #[account(mut, constraint = user_token_account.mint == pool.mints(params.direction).0 @ ErrorCode::InvalidMint)]
+    pub user_token_account: Account<'info, TokenAccount>,
+    // Receives the order's output; it need not belong to the signer.
+    #[account(constraint = destination.mint == pool.mints(params.direction).1 @ ErrorCode::InvalidMint)]
+    pub destination: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+// Implement the helper functions for PlaceLimitOrderCtx
+impl<'info> PlaceLimitOrderCtx<'info> {
+    fn into_transfer_to_escrow_context(&self) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
         CpiContext::new(
             self.token_program.to_account_info(),
             Transfer {
-                from: self.pool_token_b_account.to_account_info(),
-                to: self.user_token_b_account.to_account_info(),
+                from: self.user_token_account.to_account_info(),
+                to: self.escrow.to_account_info(),
                 authority: self.user.to_account_info(),
             },
         )
     }
+}
+
+#[derive(Accounts)]
+pub struct ExecuteLimitOrderCtx<'info> {
+    pub keeper: Signer<'info>,
+    // The order owner; receives the rent once the order is fully filled.
+    #[account(mut)]
+    pub user: SystemAccount<'info>,
+    #[account(mut, has_one = swap)]
+    pub pool: Account<'info, LiquidityPool>,
+    pub swap: Account<'info, SwapState>,
+    /// CHECK: PDA that owns the pool vaults; it holds no data and only signs payouts.
+    #[account(seeds = [VAULT_AUTHORITY_SEED, pool.key().as_ref()], bump = pool.vault_authority_bump)]
+    pub pool_authority: UncheckedAccount<'info>,
+    #[account(mut, has_one = user, has_one = pool, has_one = destination)]
+    pub order: Account<'info, LimitOrder>,
+    #[account(mut, seeds = [USER_ORDER_BOOK_SEED, user.key().as_ref()], bump = user_order_book.bump)]
+    pub user_order_book: Account<'info, UserOrderBook>,
+    // Required whenever the pool has an order book.
+    #[account(mut, has_one = pool)]
+    pub order_book: Option<AccountLoader<'info, OrderBook>>,
+    #[account(mut, seeds = [ESCROW_SEED, order.key().as_ref()], bump = order.escrow_bump)]
+    pub escrow: Account<'info, TokenAccount>,
+    #[account(mut, address = pool.vaults(order.direction).0 @ ErrorCode::InvalidVault)]
+    pub pool_token_in_account: Account<'info, TokenAccount>,
+    #[account(mut, address = pool.vaults(order.direction).1 @ ErrorCode::InvalidVault)]
+    pub pool_token_out_account: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub destination: Account<'info, TokenAccount>,
+    // Paid in the input token for a fixed keeper fee and in the output token otherwise.
+    #[account(
+        mut,
+        constraint = keeper_token_account.mint == order.keeper_fee_mint() @ ErrorCode::InvalidMint
+    )]
+    pub keeper_token_account: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+}
+
+// Implement the helper functions for ExecuteLimitOrderCtx
+impl<'info> ExecuteLimitOrderCtx<'info> {
+    // Transfer out of an order escrow, which may be a maker's from `remaining_accounts`.
+    fn into_escrow_transfer_context<'a, 'b, 'c>(
+        &self,
+        from: AccountInfo<'info>,
+        to: AccountInfo<'info>,
+        signer: &'a [&'b [&'c [u8]]],
+    ) -> CpiContext<'a, 'b, 'c, 'info, Transfer<'info>> {
+        CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            Transfer {
+                from,
+                to,
+                authority: self.pool_authority.to_account_info(),
+            },
+            signer,
+        )
+    }
 
-    fn into_transfer_fee_context(&self) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
+    // Closes a fully matched maker's empty escrow, returning its rent to `destination`.
+    fn into_close_maker_escrow_context<'a, 'b, 'c>(
+        &self,
+        account: AccountInfo<'info>,
+        destination: AccountInfo<'info>,
+        signer: &'a [&'b [&'c [u8]]],
+    ) -> CpiContext<'a, 'b, 'c, 'info, CloseAccount<'info>> {
+        CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            CloseAccount {
+                account,
+                destination,
+                authority: self.pool_authority.to_account_info(),
+            },
+            signer,
+        )
+    }
+
+    fn into_transfer_from_escrow_context<'a, 'b, 'c>(
+        &self,
+        signer: &'a [&'b [&'c [u8]]],
+    ) -> CpiContext<'a, 'b, 'c, 'info, Transfer<'info>> {
+        CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            Transfer {
+                from: self.escrow.to_account_info(),
+                to: self.pool_token_in_account.to_account_info(),
+                authority: self.pool_authority.to_account_info(),
+            },
+            signer,
+        )
+    }
+
+    fn into_transfer_to_destination_context<'a, 'b, 'c>(
+        &self,
+        signer: &'a [&'b [&'c [u8]]],
+    ) -> CpiContext<'a, 'b, 'c, 'info, Transfer<'info>> {
+        CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            Transfer {
+                from: self.pool_token_out_account.to_account_info(),
+                to: self.destination.to_account_info(),
+                authority: self.pool_authority.to_account_info(),
+            },
+            signer,
+        )
+    }
+
+    fn into_transfer_to_keeper_context<'a, 'b, 'c>(
+        &self,
+        signer: &'a [&'b [&'c [u8]]],
+    ) -> CpiContext<'a, 'b, 'c, 'info, Transfer<'info>> {
+        let from = match self.order.keeper_fee {
+            KeeperFee::Fixed { .. } => &self.escrow,
+            KeeperFee::OutputBps { .. } => &self.pool_token_out_account,
+        };
+        CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            Transfer {
+                from: from.to_account_info(),
+                to: self.keeper_token_account.to_account_info(),
+                authority: self.pool_authority.to_account_info(),
+            },
+            signer,
+        )
+    }
+
+    fn into_close_escrow_context<'a, 'b, 'c>(
+        &self,
+        signer: &'a [&'b [&'c [u8]]],
+    ) -> CpiContext<'a, 'b, 'c, 'info, CloseAccount<'info>> {
+        CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            CloseAccount {
+                account: self.escrow.to_account_info(),
+                destination: self.user.to_account_info(),
+                authority: self.pool_authority.to_account_info(),
+            },
+            signer,
+        )
+    }
+}
+
+#[derive(Accounts)]
+pub struct FillLimitOrderCtx<'info> {
+    pub keeper: Signer<'info>,
+    // The order owner; receives the rent once the order is fully filled.
+    #[account(mut)]
+    pub user: SystemAccount<'info>,
+    #[account(mut, has_one = swap)]
+    pub pool: Account<'info, LiquidityPool>,
+    pub swap: Account<'info, SwapState>,
+    /// CHECK: PDA that owns the pool vaults; it holds no data and only signs payouts.
+    #[account(seeds = [VAULT_AUTHORITY_SEED, pool.key().as_ref()], bump = pool.vault_authority_bump)]
+    pub pool_authority: UncheckedAccount<'info>,
+    #[account(mut, has_one = user, has_one = pool, has_one = destination)]
+    pub order: Account<'info, LimitOrder>,
+    #[account(mut, seeds = [USER_ORDER_BOOK_SEED, user.key().as_ref()], bump = user_order_book.bump)]
+    pub user_order_book: Account<'info, UserOrderBook>,
+    // Required when the order rests on the book.
+    #[account(mut, has_one = pool)]
+    pub order_book: Option<AccountLoader<'info, OrderBook>>,
+    #[account(mut, seeds = [ESCROW_SEED, order.key().as_ref()], bump = order.escrow_bump)]
+    pub escrow: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub destination: Account<'info, TokenAccount>,
+    // Receives the input and any fixed keeper fee.
+    #[account(mut, constraint = keeper_token_in_account.mint == order.input_mint @ ErrorCode::InvalidMint)]
+    pub keeper_token_in_account: Account<'info, TokenAccount>,
+    // Pays the output to the order's destination.
+    #[account(mut, constraint = keeper_token_out_account.mint == order.output_mint @ ErrorCode::InvalidMint)]
+    pub keeper_token_out_account: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+}
+
+// Implement the helper functions for FillLimitOrderCtx
+impl<'info> FillLimitOrderCtx<'info> {
+    fn into_transfer_to_destination_context(&self) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
         CpiContext::new(
             self.token_program.to_account_info(),
             Transfer {
-                from: self.pool_token_b_account.to_account_info(),
-                to: self.user_token_b_account.to_account_info(),
-                authority: self.user.to_account_info(),
+                from: self.keeper_token_out_account.to_account_info(),
+                to: self.destination.to_account_info(),
+                authority: self.keeper.to_account_info(),
+            },
+        )
+    }
+
+    fn into_transfer_to_keeper_context<'a, 'b, 'c>(
+        &self,
+        signer: &'a [&'b [&'c [u8]]],
+    ) -> CpiContext<'a, 'b, 'c, 'info, Transfer<'info>> {
+        CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            Transfer {
+                from: self.escrow.to_account_info(),
+                to: self.keeper_token_in_account.to_account_info(),
+                authority: self.pool_authority.to_account_info(),
             },
+            signer,
+        )
+    }
+
+    fn into_close_escrow_context<'a, 'b, 'c>(
+        &self,
+        signer: &'a [&'b [&'c [u8]]],
+    ) -> CpiContext<'a, 'b, 'c, 'info, CloseAccount<'info>> {
+        CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            CloseAccount {
+                account: self.escrow.to_account_info(),
+                destination: self.user.to_account_info(),
+                authority: self.pool_authority.to_account_info(),
+            },
+            signer,
         )
     }
 }
 
 #[derive(Accounts)]
-pub struct PlaceLimitOrderCtx<'info> {
+pub struct CancelLimitOrderCtx<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
+    pub pool: Account<'info, LiquidityPool>,
+    /// CHECK: PDA that owns the pool vaults; it holds no data and only signs payouts.
+    #[account(seeds = [VAULT_AUTHORITY_SEED, pool.key().as_ref()], bump = pool.vault_authority_bump)]
+    pub pool_authority: UncheckedAccount<'info>,
+    #[account(mut, has_one = user, has_one = pool, close = user)]
+    pub order: Account<'info, LimitOrder>,
+    #[account(mut, seeds = [USER_ORDER_BOOK_SEED, user.key().as_ref()], bump = user_order_book.bump)]
+    pub user_order_book: Account<'info, UserOrderBook>,
+    // Required when the order rests on the book.
+    #[account(mut, has_one = pool)]
+    pub order_book: Option<AccountLoader<'info, OrderBook>>,
+    #[account(mut, seeds = [ESCROW_SEED, order.key().as_ref()], bump = order.escrow_bump)]
+    pub escrow: Account<'info, TokenAccount>,
+    #[account(mut, constraint = user_token_account.mint == order.input_mint @ ErrorCode::InvalidMint)]
+    pub user_token_account: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+}
+
+// Implement the helper functions for CancelLimitOrderCtx
+impl<'info> CancelLimitOrderCtx<'info> {
+    fn into_refund_context<'a, 'b, 'c>(
+        &self,
+        signer: &'a [&'b [&'c [u8]]],
+    ) -> CpiContext<'a, 'b, 'c, 'info, Transfer<'info>> {
+        CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            Transfer {
+                from: self.escrow.to_account_info(),
+                to: self.user_token_account.to_account_info(),
+                authority: self.pool_authority.to_account_info(),
+            },
+            signer,
+        )
+    }
+
+    fn into_close_escrow_context<'a, 'b, 'c>(
+        &self,
+        signer: &'a [&'b [&'c [u8]]],
+    ) -> CpiContext<'a, 'b, 'c, 'info, CloseAccount<'info>> {
+        CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            CloseAccount {
+                account: self.escrow.to_account_info(),
+                destination: self.user.to_account_info(),
+                authority: self.pool_authority.to_account_info(),
+            },
+            signer,
+        )
+    }
+}
+
+#[derive(Accounts)]
+pub struct CloseExpiredOrderCtx<'info> {
+    pub caller: Signer<'info>,
+    // The order owner; receives the refund and the rent.
     #[account(mut)]
+    pub user: SystemAccount<'info>,
     pub pool: Account<'info, LiquidityPool>,
-    #[account(init, payer = user, space = 8 + 64)]
+    /// CHECK: PDA that owns the pool vaults; it holds no data and only signs payouts.
+    #[account(seeds = [VAULT_AUTHORITY_SEED, pool.key().as_ref()], bump = pool.vault_authority_bump)]
+    pub pool_authority: UncheckedAccount<'info>,
+    #[account(mut, has_one = user, has_one = pool, close = user)]
     pub order: Account<'info, LimitOrder>,
+    #[account(mut, seeds = [USER_ORDER_BOOK_SEED, user.key().as_ref()], bump = user_order_book.bump)]
+    pub user_order_book: Account<'info, UserOrderBook>,
+    // Required when the order rests on the book.
+    #[account(mut, has_one = pool)]
+    pub order_book: Option<AccountLoader<'info, OrderBook>>,
+    #[account(mut, seeds = [ESCROW_SEED, order.key().as_ref()], bump = order.escrow_bump)]
+    pub escrow: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = user_token_account.mint == order.input_mint @ ErrorCode::InvalidMint,
+        constraint = user_token_account.owner == order.user @ ErrorCode::InvalidOwner
+    )]
+    pub user_token_account: Account<'info, TokenAccount>,
+    // Optional: where the cleanup reward is paid. Without it the reward is refunded too.
+    #[account(mut, constraint = caller_token_account.mint == order.input_mint @ ErrorCode::InvalidMint)]
+    pub caller_token_account: Option<Account<'info, TokenAccount>>,
+    pub token_program: Program<'info, Token>,
+}
+
+// Implement the helper functions for CloseExpiredOrderCtx
+impl<'info> CloseExpiredOrderCtx<'info> {
+    fn into_reward_context<'a, 'b, 'c>(
+        &self,
+        signer: &'a [&'b [&'c [u8]]],
+    ) -> Option<CpiContext<'a, 'b, 'c, 'info, Transfer<'info>>> {
+        let caller_token_account = self.caller_token_account.as_ref()?;
+        Some(CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            Transfer {
+                from: self.escrow.to_account_info(),
+                to: caller_token_account.to_account_info(),
+                authority: self.pool_authority.to_account_info(),
+            },
+            signer,
+        ))
+    }
+
+    fn into_refund_context<'a, 'b, 'c>(
+        &self,
+        signer: &'a [&'b [&'c [u8]]],
+    ) -> CpiContext<'a, 'b, 'c, 'info, Transfer<'info>> {
+        CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            Transfer {
+                from: self.escrow.to_account_info(),
+                to: self.user_token_account.to_account_info(),
+                authority: self.pool_authority.to_account_info(),
+            },
+            signer,
+        )
+    }
+
+    fn into_close_escrow_context<'a, 'b, 'c>(
+        &self,
+        signer: &'a [&'b [&'c [u8]]],
+    ) -> CpiContext<'a, 'b, 'c, 'info, CloseAccount<'info>> {
+        CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            CloseAccount {
+                account: self.escrow.to_account_info(),
+                destination: self.user.to_account_info(),
+                authority: self.pool_authority.to_account_info(),
+            },
+            signer,
+        )
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(params: DcaOrderParams)]
+pub struct PlaceDcaOrderCtx<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(has_one = swap)]
+    pub pool: Account<'info, LiquidityPool>,
+    pub swap: Account<'info, SwapState>,
+    /// CHECK: PDA that owns the pool vaults; it holds no data and only signs payouts.
+    #[account(seeds = [VAULT_AUTHORITY_SEED, pool.key().as_ref()], bump = pool.vault_authority_bump)]
+    pub pool_authority: UncheckedAccount<'info>,
+    #[account(mut, has_one = user, seeds = [USER_ORDER_BOOK_SEED, user.key().as_ref()], bump = user_order_book.bump)]
+    pub user_order_book: Account<'info, UserOrderBook>,
+    #[account(
+        init,
+        payer = user,
+        space = 8 + DcaOrder::INIT_SPACE,
+        seeds = [DCA_SEED, pool.key().as_ref(), user.key().as_ref(), &user_order_book.next_nonce.to_le_bytes()],
+        bump
+    )]
+    pub dca_order: Account<'info, DcaOrder>,
+    #[account(address = pool.mints(params.direction).0 @ ErrorCode::InvalidMint)]
+    pub input_mint: Account<'info, Mint>,
+    #[account(
+        init,
+        payer = user,
+        seeds = [ESCROW_SEED, dca_order.key().as_ref()],
+        bump,
+        token::mint = input_mint,
+        token::authority = pool_authority
+    )]
+    pub escrow: Account<'info, TokenAccount>,
+    #[account(mut, constraint = user_token_account.mint == pool.mints(params.direction).0 @ ErrorCode::InvalidMint)]
+    pub user_token_account: Account<'info, TokenAccount>,
+    // Receives every slice's output; it need not belong to the signer.
+    #[account(constraint = destination.mint == pool.mints(params.direction).1 @ ErrorCode::InvalidMint)]
+    pub destination: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
     pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+// Implement the helper functions for PlaceDcaOrderCtx
+impl<'info> PlaceDcaOrderCtx<'info> {
+    fn into_transfer_to_escrow_context(&self) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
+        CpiContext::new(
+            self.token_program.to_account_info(),
+            Transfer {
+                from: self.user_token_account.to_account_info(),
+                to: self.escrow.to_account_info(),
+                authority: self.user.to_account_info(),
+            },
+        )
+    }
 }
 
 #[derive(Accounts)]
-pub struct ExecuteLimitOrderCtx<'info> {
+pub struct ExecuteDcaCycleCtx<'info> {
+    pub keeper: Signer<'info>,
+    // The order owner; receives the rent once the last slice is sold.
+    #[account(mut)]
+    pub user: SystemAccount<'info>,
+    #[account(mut, has_one = swap)]
+    pub pool: Account<'info, LiquidityPool>,
+    pub swap: Account<'info, SwapState>,
+    /// CHECK: PDA that owns the pool vaults; it holds no data and only signs payouts.
+    #[account(seeds = [VAULT_AUTHORITY_SEED, pool.key().as_ref()], bump = pool.vault_authority_bump)]
+    pub pool_authority: UncheckedAccount<'info>,
+    #[account(mut, has_one = user, has_one = pool, has_one = destination)]
+    pub dca_order: Account<'info, DcaOrder>,
+    #[account(mut, seeds = [USER_ORDER_BOOK_SEED, user.key().as_ref()], bump = user_order_book.bump)]
+    pub user_order_book: Account<'info, UserOrderBook>,
+    #[account(mut, seeds = [ESCROW_SEED, dca_order.key().as_ref()], bump = dca_order.escrow_bump)]
+    pub escrow: Account<'info, TokenAccount>,
+    #[account(mut, address = pool.vaults(dca_order.direction).0 @ ErrorCode::InvalidVault)]
+    pub pool_token_in_account: Account<'info, TokenAccount>,
+    #[account(mut, address = pool.vaults(dca_order.direction).1 @ ErrorCode::InvalidVault)]
+    pub pool_token_out_account: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub destination: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+}
+
+// Implement the helper functions for ExecuteDcaCycleCtx
+impl<'info> ExecuteDcaCycleCtx<'info> {
+    fn into_transfer_from_escrow_context<'a, 'b, 'c>(
+        &self,
+        signer: &'a [&'b [&'c [u8]]],
+    ) -> CpiContext<'a, 'b, 'c, 'info, Transfer<'info>> {
+        CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            Transfer {
+                from: self.escrow.to_account_info(),
+                to: self.pool_token_in_account.to_account_info(),
+                authority: self.pool_authority.to_account_info(),
+            },
+            signer,
+        )
+    }
+
+    fn into_transfer_to_destination_context<'a, 'b, 'c>(
+        &self,
+        signer: &'a [&'b [&'c [u8]]],
+    ) -> CpiContext<'a, 'b, 'c, 'info, Transfer<'info>> {
+        CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            Transfer {
+                from: self.pool_token_out_account.to_account_info(),
+                to: self.destination.to_account_info(),
+                authority: self.pool_authority.to_account_info(),
+            },
+            signer,
+        )
+    }
+
+    fn into_close_escrow_context<'a, 'b, 'c>(
+        &self,
+        signer: &'a [&'b [&'c [u8]]],
+    ) -> CpiContext<'a, 'b, 'c, 'info, CloseAccount<'info>> {
+        CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            CloseAccount {
+                account: self.escrow.to_account_info(),
+                destination: self.user.to_account_info(),
+                authority: self.pool_authority.to_account_info(),
+            },
+            signer,
+        )
+    }
+}
+
+#[derive(Accounts)]
+pub struct CancelDcaOrderCtx<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub pool: Account<'info, LiquidityPool>,
+    /// CHECK: PDA that owns the pool vaults; it holds no data and only signs payouts.
+    #[account(seeds = [VAULT_AUTHORITY_SEED, pool.key().as_ref()], bump = pool.vault_authority_bump)]
+    pub pool_authority: UncheckedAccount<'info>,
+    #[account(mut, has_one = user, has_one = pool, close = user)]
+    pub dca_order: Account<'info, DcaOrder>,
+    #[account(mut, seeds = [USER_ORDER_BOOK_SEED, user.key().as_ref()], bump = user_order_book.bump)]
+    pub user_order_book: Account<'info, UserOrderBook>,
+    #[account(mut, seeds = [ESCROW_SEED, dca_order.key().as_ref()], bump = dca_order.escrow_bump)]
+    pub escrow: Account<'info, TokenAccount>,
+    #[account(mut, constraint = user_token_account.mint == escrow.mint @ ErrorCode::InvalidMint)]
+    pub user_token_account: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+}
+
+// Implement the helper functions for CancelDcaOrderCtx
+impl<'info> CancelDcaOrderCtx<'info> {
+    fn into_refund_context<'a, 'b, 'c>(
+        &self,
+        signer: &'a [&'b [&'c [u8]]],
+    ) -> CpiContext<'a, 'b, 'c, 'info, Transfer<'info>> {
+        CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            Transfer {
+                from: self.escrow.to_account_info(),
+                to: self.user_token_account.to_account_info(),
+                authority: self.pool_authority.to_account_info(),
+            },
+            signer,
+        )
+    }
+
+    fn into_close_escrow_context<'a, 'b, 'c>(
+        &self,
+        signer: &'a [&'b [&'c [u8]]],
+    ) -> CpiContext<'a, 'b, 'c, 'info, CloseAccount<'info>> {
+        CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            CloseAccount {
+                account: self.escrow.to_account_info(),
+                destination: self.user.to_account_info(),
+                authority: self.pool_authority.to_account_info(),
+            },
+            signer,
+        )
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(direction: SwapDirection)]
+pub struct PlaceLongTermOrderCtx<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
+    #[account(mut, has_one = swap)]
+    pub pool: Account<'info, LiquidityPool>,
+    pub swap: Account<'info, SwapState>,
+    #[account(mut, has_one = user, seeds = [USER_ORDER_BOOK_SEED, user.key().as_ref()], bump = user_order_book.bump)]
+    pub user_order_book: Account<'info, UserOrderBook>,
+    #[account(
+        init,
+        payer = user,
+        space = 8 + LongTermOrder::INIT_SPACE,
+        seeds = [LONG_TERM_ORDER_SEED, pool.key().as_ref(), user.key().as_ref(), &user_order_book.next_nonce.to_le_bytes()],
+        bump
+    )]
+    pub long_term_order: Account<'info, LongTermOrder>,
+    #[account(mut, constraint = user_token_account.mint == pool.mints(direction).0 @ ErrorCode::InvalidMint)]
+    pub user_token_account: Account<'info, TokenAccount>,
+    #[account(mut, address = pool.vaults(direction).0 @ ErrorCode::InvalidVault)]
+    pub pool_token_in_account: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+// Implement the helper functions for PlaceLongTermOrderCtx
+impl<'info> PlaceLongTermOrderCtx<'info> {
+    fn into_transfer_to_pool_context(&self) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
+        CpiContext::new(
+            self.token_program.to_account_info(),
+            Transfer {
+                from: self.user_token_account.to_account_info(),
+                to: self.pool_token_in_account.to_account_info(),
+                authority: self.user.to_account_info(),
+            },
+        )
+    }
+}
+
+#[derive(Accounts)]
+pub struct WithdrawLongTermProceedsCtx<'info> {
     #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(mut, has_one = swap)]
     pub pool: Account<'info, LiquidityPool>,
-    #[account(mut, has_one = user)]
-    pub order: Account<'info, LimitOrder>,
+    pub swap: Account<'info, SwapState>,
+    /// CHECK: PDA that owns the pool vaults; it holds no data and only signs payouts.
+    #[account(seeds = [VAULT_AUTHORITY_SEED, pool.key().as_ref()], bump = pool.vault_authority_bump)]
+    pub pool_authority: UncheckedAccount<'info>,
+    #[account(mut, has_one = user, has_one = pool)]
+    pub long_term_order: Account<'info, LongTermOrder>,
+    #[account(mut, seeds = [USER_ORDER_BOOK_SEED, user.key().as_ref()], bump = user_order_book.bump)]
+    pub user_order_book: Account<'info, UserOrderBook>,
+    #[account(mut, address = pool.vaults(long_term_order.direction).1 @ ErrorCode::InvalidVault)]
+    pub pool_token_out_account: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = user_token_out_account.mint == pool.mints(long_term_order.direction).1 @ ErrorCode::InvalidMint
+    )]
+    pub user_token_out_account: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+}
+
+// Implement the helper functions for WithdrawLongTermProceedsCtx
+impl<'info> WithdrawLongTermProceedsCtx<'info> {
+    fn into_transfer_proceeds_context<'a, 'b, 'c>(
+        &self,
+        signer: &'a [&'b [&'c [u8]]],
+    ) -> CpiContext<'a, 'b, 'c, 'info, Transfer<'info>> {
+        CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            Transfer {
+                from: self.pool_token_out_account.to_account_info(),
+                to: self.user_token_out_account.to_account_info(),
+                authority: self.pool_authority.to_account_info(),
+            },
+            signer,
+        )
+    }
+}
+
+#[derive(Accounts)]
+pub struct CancelLongTermOrderCtx<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(mut, has_one = swap)]
+    pub pool: Account<'info, LiquidityPool>,
+    pub swap: Account<'info, SwapState>,
+    /// CHECK: PDA that owns the pool vaults; it holds no data and only signs payouts.
+    #[account(seeds = [VAULT_AUTHORITY_SEED, pool.key().as_ref()], bump = pool.vault_authority_bump)]
+    pub pool_authority: UncheckedAccount<'info>,
+    #[account(mut, has_one = user, has_one = pool, close = user)]
+    pub long_term_order: Account<'info, LongTermOrder>,
+    #[account(mut, seeds = [USER_ORDER_BOOK_SEED, user.key().as_ref()], bump = user_order_book.bump)]
+    pub user_order_book: Account<'info, UserOrderBook>,
+    #[account(mut, address = pool.vaults(long_term_order.direction).0 @ ErrorCode::InvalidVault)]
+    pub pool_token_in_account: Account<'info, TokenAccount>,
+    #[account(mut, address = pool.vaults(long_term_order.direction).1 @ ErrorCode::InvalidVault)]
+    pub pool_token_out_account: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = user_token_in_account.mint == pool.mints(long_term_order.direction).0 @ ErrorCode::InvalidMint
+    )]
+    pub user_token_in_account: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = user_token_out_account.mint == pool.mints(long_term_order.direction).1 @ ErrorCode::InvalidMint
+    )]
+    pub user_token_out_account: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+}
+
+// Implement the helper functions for CancelLongTermOrderCtx
+impl<'info> CancelLongTermOrderCtx<'info> {
+    fn into_refund_context<'a, 'b, 'c>(
+        &self,
+        signer: &'a [&'b [&'c [u8]]],
+    ) -> CpiContext<'a, 'b, 'c, 'info, Transfer<'info>> {
+        CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            Transfer {
+                from: self.pool_token_in_account.to_account_info(),
+                to: self.user_token_in_account.to_account_info(),
+                authority: self.pool_authority.to_account_info(),
+            },
+            signer,
+        )
+    }
+
+    fn into_transfer_proceeds_context<'a, 'b, 'c>(
+        &self,
+        signer: &'a [&'b [&'c [u8]]],
+    ) -> CpiContext<'a, 'b, 'c, 'info, Transfer<'info>> {
+        CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            Transfer {
+                from: self.pool_token_out_account.to_account_info(),
+                to: self.user_token_out_account.to_account_info(),
+                authority: self.pool_authority.to_account_info(),
+            },
+            signer,
+        )
+    }
+}
+
+#[derive(Accounts)]
+pub struct SettleLongTermOrderCtx<'info> {
+    pub caller: Signer<'info>,
+    // The order owner; receives the proceeds and the rent.
+    #[account(mut)]
+    pub user: SystemAccount<'info>,
+    #[account(mut, has_one = swap)]
+    pub pool: Account<'info, LiquidityPool>,
+    pub swap: Account<'info, SwapState>,
+    /// CHECK: PDA that owns the pool vaults; it holds no data and only signs payouts.
+    #[account(seeds = [VAULT_AUTHORITY_SEED, pool.key().as_ref()], bump = pool.vault_authority_bump)]
+    pub pool_authority: UncheckedAccount<'info>,
+    #[account(mut, has_one = user, has_one = pool, close = user)]
+    pub long_term_order: Account<'info, LongTermOrder>,
+    #[account(mut, seeds = [USER_ORDER_BOOK_SEED, user.key().as_ref()], bump = user_order_book.bump)]
+    pub user_order_book: Account<'info, UserOrderBook>,
+    #[account(mut, address = pool.vaults(long_term_order.direction).1 @ ErrorCode::InvalidVault)]
+    pub pool_token_out_account: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = user_token_out_account.mint == pool.mints(long_term_order.direction).1 @ ErrorCode::InvalidMint,
+        constraint = user_token_out_account.owner == long_term_order.user @ ErrorCode::InvalidOwner
+    )]
+    pub user_token_out_account: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+}
+
+// Implement the helper functions for SettleLongTermOrderCtx
+impl<'info> SettleLongTermOrderCtx<'info> {
+    fn into_transfer_proceeds_context<'a, 'b, 'c>(
+        &self,
+        signer: &'a [&'b [&'c [u8]]],
+    ) -> CpiContext<'a, 'b, 'c, 'info, Transfer<'info>> {
+        CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            Transfer {
+                from: self.pool_token_out_account.to_account_info(),
+                to: self.user_token_out_account.to_account_info(),
+                authority: self.pool_authority.to_account_info(),
+            },
+            signer,
+        )
+    }
+}
+
+#[derive(Accounts)]
+pub struct ExecuteVirtualOrdersCtx<'info> {
+    #[account(mut, has_one = swap)]
+    pub pool: Account<'info, LiquidityPool>,
+    pub swap: Account<'info, SwapState>,
+}
+
+#[derive(Accounts)]
+pub struct MultiTokenSwapCtx<'info> {
+    pub user: Signer<'info>,
+    // Holds the route's input token.
+    #[account(mut)]
+    pub user_source_account: Account<'info, TokenAccount>,
+    // Receives the route's output token.
+    #[account(mut)]
+    pub user_destination_account: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+}
+
+// Implement the helper functions for MultiTokenSwapCtx
+impl<'info> MultiTokenSwapCtx<'info> {
+    fn into_transfer_to_pool_context(&self, to: AccountInfo<'info>) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
+        CpiContext::new(
+            self.token_program.to_account_info(),
+            Transfer {
+                from: self.user_source_account.to_account_info(),
+                to,
+                authority: self.user.to_account_info(),
+            },
+        )
+    }
+
+    // Transfer out of a hop's output vault, signed by that pool's authority.
+    fn into_vault_transfer_context<'a, 'b, 'c>(
+        &self,
+        from: AccountInfo<'info>,
+        to: AccountInfo<'info>,
+        authority: AccountInfo<'info>,
+        signer: &'a [&'b [&'c [u8]]],
+    ) -> CpiContext<'a, 'b, 'c, 'info, Transfer<'info>> {
+        CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            Transfer { from, to, authority },
+            signer,
+        )
+    }
+}
+
+#[derive(Accounts)]
+pub struct FlashSwapCtx<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(mut, has_one = swap)]
+    pub pool: Account<'info, LiquidityPool>,
+    pub swap: Account<'info, SwapState>,
+    /// CHECK: PDA that owns the pool vaults; it holds no data and only signs payouts.
+    #[account(seeds = [VAULT_AUTHORITY_SEED, pool.key().as_ref()], bump = pool.vault_authority_bump)]
+    pub pool_authority: UncheckedAccount<'info>,
+    #[account(mut, constraint = user_token_a_account.mint == pool.token_a_mint @ ErrorCode::InvalidMint)]
+    pub user_token_a_account: Account<'info, TokenAccount>,
+    #[account(mut, address = pool.token_a_vault @ ErrorCode::InvalidVault)]
+    pub pool_token_a_account: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+}
+
+// Implement the helper functions for FlashSwapCtx
+impl<'info> FlashSwapCtx<'info> {
+    fn into_transfer_to_user_context<'a, 'b, 'c>(
+        &self,
+        signer: &'a [&'b [&'c [u8]]],
+    ) -> CpiContext<'a, 'b, 'c, 'info, Transfer<'info>> {
+        CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            Transfer {
+                from: self.pool_token_a_account.to_account_info(),
+                to: self.user_token_a_account.to_account_info(),
+                authority: self.pool_authority.to_account_info(),
+            },
+            signer,
+        )
+    }
 }
 
 #[derive(Accounts)]
-pub struct MultiTokenSwapCtx<'info> {
+pub struct FlashBorrowCtx<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
-    #[account(mut)]
+    #[account(mut, has_one = swap)]
     pub pool: Account<'info, LiquidityPool>,
-    #[account(mut)]
-    pub user_token_a_account: Account<'info, TokenAccount>,  // Add user token account
-    #[account(mut)]
-    pub user_token_b_account: Account<'info, TokenAccount>,  // Add user token account for the other token
-    #[account(mut)]
-    pub pool_token_a_account: Account<'info, TokenAccount>,  // Add pool token account for the input token
-    #[account(mut)]
-    pub pool_token_b_account: Account<'info, TokenAccount>,  // Add pool token account for the output token
+    pub swap: Account<'info, SwapState>,
+    /// CHECK: PDA that owns the pool vaults; it holds no data and only signs payouts.
+    #[account(seeds = [VAULT_AUTHORITY_SEED, pool.key().as_ref()], bump = pool.vault_authority_bump)]
+    pub pool_authority: UncheckedAccount<'info>,
+    #[account(mut, constraint = user_token_a_account.mint == pool.token_a_mint @ ErrorCode::InvalidMint)]
+    pub user_token_a_account: Account<'info, TokenAccount>,
+    #[account(mut, address = pool.token_a_vault @ ErrorCode::InvalidVault)]
+    pub pool_token_a_account: Account<'info, TokenAccount>,
+    /// CHECK: the instructions sysvar, read to find the matching flash_repay.
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
     pub token_program: Program<'info, Token>,
 }
 
-
-// Implement the helper functions for MultiTokenSwapCtx
-impl<'info> MultiTokenSwapCtx<'info> {
-    fn into_transfer_context(&self) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
-        CpiContext::new(
+// Implement the helper functions for FlashBorrowCtx
+impl<'info> FlashBorrowCtx<'info> {
+    fn into_transfer_to_user_context<'a, 'b, 'c>(
+        &self,
+        signer: &'a [&'b [&'c [u8]]],
+    ) -> CpiContext<'a, 'b, 'c, 'info, Transfer<'info>> {
+        CpiContext::new_with_signer(
             self.token_program.to_account_info(),
             Transfer {
-                from: self.user_token_a_account.to_account_info(),  // Assuming `user_token_a_account` exists for swap
-                to: self.pool_token_a_account.to_account_info(),  // Assuming `pool_token_a_account` exists for swap
-                authority: self.user.to_account_info(),
+                from: self.pool_token_a_account.to_account_info(),
+                to: self.user_token_a_account.to_account_info(),
+                authority: self.pool_authority.to_account_info(),
             },
+            signer,
         )
     }
 }
 
 #[derive(Accounts)]
-pub struct FlashSwapCtx<'info> {
-    #[account(mut)]
+pub struct FlashRepayCtx<'info> {
     pub user: Signer<'info>,
     #[account(mut)]
     pub pool: Account<'info, LiquidityPool>,
+    #[account(mut, constraint = user_token_a_account.mint == pool.token_a_mint @ ErrorCode::InvalidMint)]
+    pub user_token_a_account: Account<'info, TokenAccount>,
+    #[account(mut, address = pool.token_a_vault @ ErrorCode::InvalidVault)]
+    pub pool_token_a_account: Account<'info, TokenAccount>,
     pub token_program: Program<'info, Token>,
 }
 
-// Implement the helper functions for FlashSwapCtx
-impl<'info> FlashSwapCtx<'info> {
-    fn into_transfer_context(&self) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
+// Implement the helper functions for FlashRepayCtx
+impl<'info> FlashRepayCtx<'info> {
+    fn into_transfer_to_pool_context(&self) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
         CpiContext::new(
             self.token_program.to_account_info(),
             Transfer {
-                from: self.user.to_account_info(),
-                to: self.pool.to_account_info(),
+                from: self.user_token_a_account.to_account_info(),
+                to: self.pool_token_a_account.to_account_info(),
                 authority: self.user.to_account_info(),
             },
         )
@@ -362,37 +2970,182 @@ impl<'info> FlashSwapCtx<'info> {
 }
 
 // Event Definitions
+#[event]
+pub struct PauseEvent {
+    pub admin: Pubkey,
+    pub paused: u8,
+}
+
+#[event]
+pub struct InitializePoolEvent {
+    pub pool: Pubkey,
+    pub swap: Pubkey,
+    pub token_a_mint: Pubkey,
+    pub token_b_mint: Pubkey,
+}
+
+#[event]
+pub struct CircuitBreakerTripped {
+    pub pool: Pubkey,
+    pub price_before: u128,
+    pub price_after: u128,
+    pub reference_price: u128,
+    pub auto_paused: bool,
+}
+
 #[event]
 pub struct AddLiquidityEvent {
     pub user: Pubkey,
     pub amount_a: u64,
     pub amount_b: u64,
+    pub lp_amount: u64,
+    pub token_a_reserve: u64,
+    pub token_b_reserve: u64,
 }
 
 #[event]
-pub struct SimpleSwapEvent {
+pub struct RemoveLiquidityEvent {
     pub user: Pubkey,
+    pub lp_amount: u64,
     pub amount_a: u64,
     pub amount_b: u64,
+    pub token_a_reserve: u64,
+    pub token_b_reserve: u64,
+}
+
+#[event]
+pub struct SimpleSwapEvent {
+    pub user: Pubkey,
+    pub direction: SwapDirection,
+    pub mode: SwapMode,
+    pub amount_in: u64,
+    pub amount_out: u64,
     pub fee: u64,
 }
 
 #[event]
 pub struct LimitOrderPlaced {
+    pub order: Pubkey,
     pub user: Pubkey,
-    pub amount_a: u64,
+    pub direction: SwapDirection,
+    pub amount_in: u64,
     pub target_price: u64,
     pub expiration: i64,
     pub partial_fill: bool,
+    pub min_fill_amount: u64,
+    pub keeper_fee: KeeperFee,
+    pub order_type: OrderType,
+    pub trigger_price: u64,
 }
 
 #[event]
 pub struct LimitOrderExecuted {
+    pub order: Pubkey,
     pub user: Pubkey,
-    pub amount_a: u64,
+    pub keeper: Pubkey,
+    // 1 for an order's first fill, 2 for its second, and so on.
+    pub fill_sequence: u64,
+    pub amount_in: u64,
+    // Output delivered to the order's destination, after the keeper fee.
+    pub amount_out: u64,
+    pub keeper_fee: u64,
+    pub remaining_amount: u64,
     pub target_price: u64,
 }
 
+#[event]
+pub struct LimitOrderMatched {
+    pub taker: Pubkey,
+    pub maker: Pubkey,
+    pub taker_fill_sequence: u64,
+    pub maker_fill_sequence: u64,
+    // Paid by the taker to the maker's destination.
+    pub taker_amount_in: u64,
+    // Paid by the maker to the taker's destination, including any keeper fee.
+    pub maker_amount_in: u64,
+    pub keeper_fee: u64,
+}
+
+#[event]
+pub struct MakerEvicted {
+    pub taker: Pubkey,
+    // Left the book because its destination can no longer be paid.
+    pub maker: Pubkey,
+}
+
+#[event]
+pub struct LimitOrderCancelled {
+    pub order: Pubkey,
+    pub user: Pubkey,
+    pub refunded: u64,
+}
+
+#[event]
+pub struct LimitOrderExpired {
+    pub order: Pubkey,
+    pub user: Pubkey,
+    pub caller: Pubkey,
+    pub refunded: u64,
+    pub cleanup_reward: u64,
+}
+
+#[event]
+pub struct DcaOrderPlaced {
+    pub order: Pubkey,
+    pub user: Pubkey,
+    pub direction: SwapDirection,
+    pub total_amount: u64,
+    pub amount_per_cycle: u64,
+    pub min_amount_out_per_cycle: u64,
+    pub interval: i64,
+}
+
+#[event]
+pub struct DcaCycleExecuted {
+    pub order: Pubkey,
+    pub user: Pubkey,
+    pub keeper: Pubkey,
+    pub cycle: u64,
+    pub amount_in: u64,
+    pub amount_out: u64,
+    pub remaining_amount: u64,
+    pub next_cycle_at: i64,
+}
+
+#[event]
+pub struct DcaOrderCancelled {
+    pub order: Pubkey,
+    pub user: Pubkey,
+    pub refunded: u64,
+}
+
+#[event]
+pub struct LongTermOrderPlaced {
+    pub order: Pubkey,
+    pub user: Pubkey,
+    pub direction: SwapDirection,
+    pub amount_in: u64,
+    pub sell_rate: u128,
+    pub expiry: i64,
+}
+
+#[event]
+pub struct LongTermProceedsWithdrawn {
+    pub order: Pubkey,
+    pub user: Pubkey,
+    pub proceeds: u64,
+    // The order had expired and is now closed.
+    pub completed: bool,
+}
+
+#[event]
+pub struct LongTermOrderCancelled {
+    pub order: Pubkey,
+    pub user: Pubkey,
+    pub refunded: u64,
+    pub proceeds: u64,
+}
+
 #[event]
 pub struct MultiTokenSwapEvent {
     pub user: Pubkey,
@@ -400,52 +3153,742 @@ pub struct MultiTokenSwapEvent {
     pub dst_token: Pubkey,
     pub amount: u64,
     pub received: u64,
+    // Every hop of the route, in order.
+    pub hops: Vec<SwapHop>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SwapHop {
+    pub pool: Pubkey,
+    pub direction: SwapDirection,
+    pub amount_in: u64,
+    // Net of the pool's protocol fee.
+    pub amount_out: u64,
+    pub fee: u64,
+}
+
+#[event]
+pub struct FlashRepayEvent {
+    pub user: Pubkey,
+    pub amount: u64,
+    pub fee: u64,
 }
 
 #[event]
 pub struct FlashSwapEvent {
     pub user: Pubkey,
     pub amount_a: u64,
+    pub fee: u64,
     pub target_contract: Pubkey,
 }
 
-// Utility Functions
-fn calculate_fee(amount: u64, fee_rate: u64) -> u64 {
-    (amount * fee_rate) / 1000
-}
-
-fn get_swap_amount(amount_in: u64, reserve_in: u64, reserve_out: u64) -> Result<u64> {
-    let amount_in_with_fee = amount_in * 997;
-    let numerator = amount_in_with_fee * reserve_out;
-    let denominator = (reserve_in * 1000) + amount_in_with_fee;
-    Ok(numerator / denominator)
-}
-
-fn route_swap(amount: u64, reserve_a: u64, reserve_b: u64) -> Result<u64> {
-    let amount_out = (amount * reserve_b) / (reserve_a + amount);
-    Ok(amount_out)
-}
-
 // Account Data Structures
 #[account]
+#[derive(InitSpace)]
 pub struct SwapState {
+    pub admin: Pubkey,
     pub fee_rate: u64,
-    pub paused: bool,
+    // Bitmask of PAUSE_* operation groups that are currently halted.
+    pub paused: u8,
+}
+
+impl SwapState {
+    fn require_active(&self, operations: u8) -> Result<()> {
+        require!(self.paused & operations == 0, ErrorCode::ProgramPaused);
+        Ok(())
+    }
 }
 
 #[account]
+#[derive(InitSpace)]
 pub struct LiquidityPool {
+    pub swap: Pubkey,
+    pub token_a_mint: Pubkey,
+    pub token_b_mint: Pubkey,
+    pub token_a_vault: Pubkey,
+    pub token_b_vault: Pubkey,
+    pub lp_mint: Pubkey,
+    pub bump: u8,
+    pub vault_authority_bump: u8,
     pub token_a_reserve: u64,
     pub token_b_reserve: u64,
+    // Total LP supply, including the MINIMUM_LIQUIDITY locked on the first deposit.
+    pub lp_supply: u64,
+    // Price-movement circuit breaker; prices are token B per token A in Q64.64. The
+    // reference is the price at the start of the current window, unset until a trade.
+    pub max_trade_move_bps: u16,
+    pub max_window_move_bps: u16,
+    pub breaker_window_slots: u64,
+    pub breaker_auto_pause: bool,
+    pub breaker_tripped: bool,
+    pub window_start_slot: u64,
+    pub reference_price: Option<u128>,
+    // Set while a flash swap callback runs or a flash_borrow is outstanding; every other
+    // pool instruction refuses to run.
+    pub locked: bool,
+    // Principal lent by an outstanding flash_borrow, zero otherwise.
+    pub flash_loan_amount: u64,
+    pub flash_fee_bps: u16,
+    // Lifetime flash loan principal and fees, in token A.
+    pub total_flash_volume: u128,
+    pub total_flash_fees: u128,
+    pub twamm: TwammState,
+    // Set once the pool's order book exists; limit orders then execute only through it.
+    pub has_order_book: bool,
+}
+
+impl LiquidityPool {
+    fn require_unlocked(&self) -> Result<()> {
+        require!(!self.locked, ErrorCode::PoolLocked);
+        Ok(())
+    }
+
+    fn flash_fee(&self, amount: u64) -> Result<u64> {
+        Ok(math::mul_div_ceil(amount, self.flash_fee_bps.into(), math::BPS_DENOMINATOR)?)
+    }
+
+    // Credits a repaid flash loan's fee to the token A reserve and updates the totals.
+    fn record_flash_loan(&mut self, amount: u64, fee: u64) {
+        self.token_a_reserve += fee;
+        self.total_flash_volume += u128::from(amount);
+        self.total_flash_fees += u128::from(fee);
+    }
+
+    // (input, output) reserves for a swap along `direction`.
+    fn reserves(&self, direction: SwapDirection) -> (u64, u64) {
+        match direction {
+            SwapDirection::AToB => (self.token_a_reserve, self.token_b_reserve),
+            SwapDirection::BToA => (self.token_b_reserve, self.token_a_reserve),
+        }
+    }
+
+    fn set_reserves(&mut self, direction: SwapDirection, reserve_in: u64, reserve_out: u64) {
+        match direction {
+            SwapDirection::AToB => {
+                self.token_a_reserve = reserve_in;
+                self.token_b_reserve = reserve_out;
+            }
+            SwapDirection::BToA => {
+                self.token_b_reserve = reserve_in;
+                self.token_a_reserve = reserve_out;
+            }
+        }
+    }
+
+    fn spot_price(&self) -> Result<u128> {
+        Ok(math::spot_price(self.token_a_reserve, self.token_b_reserve)?)
+    }
+
+    // Q64.64 price the circuit breaker sees with these reserves on either side of `direction`.
+    fn breaker_price(direction: SwapDirection, reserve_in: u64, reserve_out: u64) -> Result<u128> {
+        let price = match direction {
+            SwapDirection::AToB => math::price_q64(reserve_in, reserve_out)?,
+            SwapDirection::BToA => math::price_q64(reserve_out, reserve_in)?,
+        };
+        Ok(price)
+    }
+
+    // Checks a trade moving the price from `price_before` to `price_after` against the
+    // per-trade and per-window thresholds, rolling the window forward when it expires.
+    // Returns Ok(true) when the pool auto-paused and the trade must be skipped: the
+    // pause has to be committed, so that path cannot be reported as an error.
+    fn enforce_circuit_breaker(&mut self, pool_key: Pubkey, price_before: u128, price_after: u128) -> Result<bool> {
+        if self.max_trade_move_bps == 0 && self.max_window_move_bps == 0 {
+            return Ok(false);
+        }
+
+        let slot = Clock::get()?.slot;
+        let reference_price = match self.reference_price {
+            Some(price) if slot < self.window_start_slot.saturating_add(self.breaker_window_slots) => price,
+            _ => {
+                self.window_start_slot = slot;
+                self.reference_price = Some(price_before);
+                price_before
+            }
+        };
+
+        let trade_move = math::price_move_bps(price_before, price_after)?;
+        let window_move = math::price_move_bps(reference_price, price_after)?;
+        let tripped = math::exceeds_move_limit(trade_move, self.max_trade_move_bps)
+            || math::exceeds_move_limit(window_move, self.max_window_move_bps);
+        if !tripped {
+            return Ok(false);
+        }
+
+        emit!(CircuitBreakerTripped {
+            pool: pool_key,
+            price_before,
+            price_after,
+            reference_price,
+            auto_paused: self.breaker_auto_pause,
+        });
+        require!(self.breaker_auto_pause, ErrorCode::PriceMoveTooLarge);
+        self.breaker_tripped = true;
+        Ok(true)
+    }
+
+    // Net output and protocol fee for an exact-in swap of `amount_in` along `direction`.
+    fn quote_exact_in(&self, direction: SwapDirection, amount_in: u64, fee_rate: u64) -> Result<(u64, u64)> {
+        let (reserve_in, reserve_out) = self.reserves(direction);
+        Ok(math::quote_exact_in(amount_in, reserve_in, reserve_out, fee_rate)?)
+    }
+
+    // Books a trade against the reserves after running it past the circuit breaker.
+    // Returns Ok(true), leaving the reserves untouched, when the breaker auto-paused the
+    // pool and the caller must skip the trade. Callers hold the input by then, so an
+    // unfunded trade cannot pause the pool.
+    fn settle_swap(&mut self, pool_key: Pubkey, direction: SwapDirection, amount_in: u64, amount_out: u64) -> Result<bool> {
+        let (reserve_in, reserve_out) = self.reserves(direction);
+        let price_before = Self::breaker_price(direction, reserve_in, reserve_out)?;
+        let price_after = Self::breaker_price(direction, reserve_in + amount_in, reserve_out - amount_out)?;
+        if self.enforce_circuit_breaker(pool_key, price_before, price_after)? {
+            return Ok(true);
+        }
+        self.set_reserves(direction, reserve_in + amount_in, reserve_out - amount_out);
+        Ok(false)
+    }
+
+    fn execute_virtual_orders(&mut self, swap: &SwapState, now: i64) -> Result<()> {
+        self.execute_virtual_orders_bounded(swap, now, usize::MAX)
+    }
+
+    // Runs the long-term order streams from the last update to `now` in closed form,
+    // stopping at each expiry on the way to record its earnings and retire its sell rates.
+    // Past `max_expiries` expiries it stops at the last one crossed instead of `now`.
+    // Streams stand still while swaps are paused or the circuit breaker is tripped and
+    // catch up once trading resumes; otherwise virtual trades skip the breaker, which
+    // would stall every stream.
+    fn execute_virtual_orders_bounded(&mut self, swap: &SwapState, now: i64, max_expiries: usize) -> Result<()> {
+        if swap.paused & PAUSE_SWAPS != 0 || self.breaker_tripped {
+            return Ok(());
+        }
+        let last_time = self.twamm.last_virtual_order_time;
+        let pending = self.twamm.expiries.iter().filter(|entry| entry.expiry > last_time && entry.expiry <= now);
+        let pending = pending.map(|entry| entry.expiry).collect::<Vec<_>>();
+        for &expiry in pending.iter().take(max_expiries) {
+            self.advance_streams(expiry)?;
+            let twamm = &mut self.twamm;
+            if let Some(entry) = twamm.expiries.iter_mut().find(|entry| entry.expiry == expiry) {
+                entry.earnings_per_rate_a = twamm.earnings_per_rate_a;
+                entry.earnings_per_rate_b = twamm.earnings_per_rate_b;
+                twamm.sell_rate_a -= entry.sell_rate_a;
+                twamm.sell_rate_b -= entry.sell_rate_b;
+            }
+        }
+        if pending.len() > max_expiries {
+            return Ok(());
+        }
+        self.advance_streams(now)
+    }
+
+    fn advance_streams(&mut self, until: i64) -> Result<()> {
+        let twamm = &mut self.twamm;
+        let elapsed = until - twamm.last_virtual_order_time;
+        if elapsed <= 0 {
+            return Ok(());
+        }
+        twamm.last_virtual_order_time = until;
+        let amount_a = math::stream_amount(twamm.sell_rate_a, elapsed as u64)?;
+        let amount_b = math::stream_amount(twamm.sell_rate_b, elapsed as u64)?;
+        if amount_a == 0 && amount_b == 0 {
+            return Ok(());
+        }
+
+        let (b_out, a_out) = math::twamm_amounts_out(amount_a, amount_b, self.token_a_reserve, self.token_b_reserve)?;
+        // Each side can pay out more than its old reserve when the other stream dominates.
+        self.token_a_reserve = self.token_a_reserve.checked_add(amount_a).ok_or(ErrorCode::MathOverflow)? - a_out;
+        self.token_b_reserve = self.token_b_reserve.checked_add(amount_b).ok_or(ErrorCode::MathOverflow)? - b_out;
+        // Earnings are only ever compared by difference, so they wrap instead of overflowing.
+        if twamm.sell_rate_a > 0 {
+            let earned = math::mul_div_wide(b_out.into(), math::EARNINGS_SCALE, twamm.sell_rate_a)?;
+            twamm.earnings_per_rate_a = twamm.earnings_per_rate_a.wrapping_add(earned);
+        }
+        if twamm.sell_rate_b > 0 {
+            let earned = math::mul_div_wide(a_out.into(), math::EARNINGS_SCALE, twamm.sell_rate_b)?;
+            twamm.earnings_per_rate_b = twamm.earnings_per_rate_b.wrapping_add(earned);
+        }
+        Ok(())
+    }
+
+    // Adds a long-term order's sell rate to its direction's stream and to its expiry entry.
+    fn add_stream_order(&mut self, direction: SwapDirection, expiry: i64, sell_rate: u128) -> Result<()> {
+        let twamm = &mut self.twamm;
+        let index = twamm.expiries.partition_point(|entry| entry.expiry < expiry);
+        if twamm.expiries.get(index).map(|entry| entry.expiry) != Some(expiry) {
+            require!(twamm.expiries.len() < MAX_STREAM_EXPIRIES, ErrorCode::TooManyStreamExpiries);
+            twamm.expiries.insert(index, StreamExpiry { expiry, ..Default::default() });
+        }
+        let entry = &mut twamm.expiries[index];
+        *entry.sell_rate_mut(direction) += sell_rate;
+        entry.order_count += 1;
+        *twamm.sell_rate_mut(direction) += sell_rate;
+        Ok(())
+    }
+
+    // Drops a long-term order from its expiry entry, along with the `sell_rate` it still
+    // streams (zero once the expiry has passed). The entry goes with its last order.
+    fn remove_stream_order(&mut self, direction: SwapDirection, expiry: i64, sell_rate: u128) -> Result<()> {
+        let twamm = &mut self.twamm;
+        let index = twamm
+            .expiries
+            .iter()
+            .position(|entry| entry.expiry == expiry)
+            .ok_or(ErrorCode::InvalidLongTermOrder)?;
+        let entry = &mut twamm.expiries[index];
+        *entry.sell_rate_mut(direction) -= sell_rate;
+        entry.order_count -= 1;
+        if entry.order_count == 0 {
+            twamm.expiries.remove(index);
+        }
+        *twamm.sell_rate_mut(direction) -= sell_rate;
+        Ok(())
+    }
+
+    // Earnings per rate for orders along `direction` ending at `expiry`: frozen at the
+    // expiry once the streams have run past it.
+    fn stream_earnings(&self, direction: SwapDirection, expiry: i64) -> Result<u128> {
+        if expiry > self.twamm.last_virtual_order_time {
+            return Ok(self.twamm.earnings_per_rate(direction));
+        }
+        let entry = self
+            .twamm
+            .expiries
+            .iter()
+            .find(|entry| entry.expiry == expiry)
+            .ok_or(ErrorCode::InvalidLongTermOrder)?;
+        Ok(entry.earnings_per_rate(direction))
+    }
+
+    // (input, output) mints for a swap along `direction`.
+    fn mints(&self, direction: SwapDirection) -> (Pubkey, Pubkey) {
+        match direction {
+            SwapDirection::AToB => (self.token_a_mint, self.token_b_mint),
+            SwapDirection::BToA => (self.token_b_mint, self.token_a_mint),
+        }
+    }
+
+    // (input, output) vaults for a swap along `direction`.
+    fn vaults(&self, direction: SwapDirection) -> (Pubkey, Pubkey) {
+        match direction {
+            SwapDirection::AToB => (self.token_a_vault, self.token_b_vault),
+            SwapDirection::BToA => (self.token_b_vault, self.token_a_vault),
+        }
+    }
 }
 
 #[account]
+#[derive(InitSpace)]
 pub struct LimitOrder {
     pub user: Pubkey,
-    pub token_a_reserve: u64,
+    pub pool: Pubkey,
+    // The order lives at [ORDER_SEED, pool, user, nonce].
+    pub nonce: u64,
+    pub bump: u8,
+    pub direction: SwapDirection,
+    pub input_mint: Pubkey,
+    pub output_mint: Pubkey,
+    // Token account the output is paid to.
+    pub destination: Pubkey,
+    // The input sits in a token account at [ESCROW_SEED, order] owned by the vault authority.
+    pub escrow_bump: u8,
+    // Input placed with the order; filled_amount + remaining_amount always equals it.
+    pub amount_in: u64,
+    pub filled_amount: u64,
+    pub remaining_amount: u64,
+    // Output delivered to `destination` so far, after keeper fees.
+    pub received_amount: u64,
+    // Smallest partial fill accepted; the final fill of the remainder may be smaller.
+    pub min_fill_amount: u64,
+    pub fill_count: u64,
+    // Minimum output per unit of input, scaled by PRICE_SCALE; a Dutch auction's end price.
+    pub target_price: u64,
+    pub expiration: i64,
+    pub partial_fill: bool,
+    pub keeper_fee: KeeperFee,
+    pub order_type: OrderType,
+    // Stop-loss and take-profit orders become fillable once the pool's spot price (token B
+    // per token A, scaled by PRICE_SCALE) crosses `trigger_price` in `trigger_direction`.
+    pub trigger_direction: Option<TriggerDirection>,
+    pub trigger_price: u64,
+    // Latched on the first fill; always set for plain limit orders.
+    pub triggered: bool,
+    // Resting on the pool's order book as a maker.
+    pub on_book: bool,
+}
+
+impl LimitOrder {
+    // Checks that `fill_amount` may be filled now and returns whether it completes the order.
+    fn require_fillable(&self, fill_amount: u64, now: i64, spot_price: u128) -> Result<bool> {
+        require!(self.expiration >= now, ErrorCode::OrderExpired);
+        // Stop-loss and take-profit orders wait for the pool price to cross their trigger;
+        // once it has, later partial fills no longer depend on it.
+        require!(self.triggered || self.trigger_reached(spot_price), ErrorCode::TriggerNotReached);
+        require!(
+            math::is_valid_fill(fill_amount, self.remaining_amount, self.partial_fill, self.min_fill_amount),
+            ErrorCode::InvalidFillAmount
+        );
+        Ok(fill_amount == self.remaining_amount)
+    }
+
+    // Least output per unit of input accepted at `now`, scaled by PRICE_SCALE.
+    fn limit_price(&self, now: i64) -> Result<u64> {
+        match self.order_type {
+            OrderType::DutchAuction { start_price, end_price, start_time, end_time, decay } => Ok(
+                math::dutch_auction_price(
+                    start_price,
+                    end_price,
+                    start_time,
+                    end_time,
+                    now,
+                    decay == PriceDecay::Exponential,
+                )?,
+            ),
+            _ => Ok(self.target_price),
+        }
+    }
+
+    // Share of a fixed keeper `fee` owed for filling `fill_amount`. The escrow holds the
+    // unfilled input plus whatever part of the fee earlier fills left unpaid.
+    fn fixed_keeper_fee(&self, fee: u64, fill_amount: u64, escrowed: u64) -> Result<u64> {
+        let fee_left = escrowed - self.remaining_amount;
+        Ok(math::fixed_keeper_fee_share(fee, fee_left, fill_amount, self.remaining_amount, self.amount_in)?)
+    }
+
+    fn record_fill(&mut self, amount_in: u64, amount_out: u64) {
+        self.triggered = true;
+        self.filled_amount += amount_in;
+        self.remaining_amount -= amount_in;
+        self.received_amount += amount_out;
+        self.fill_count += 1;
+    }
+
+    fn trigger_reached(&self, spot_price: u128) -> bool {
+        let trigger_price = u128::from(self.trigger_price);
+        match self.trigger_direction {
+            None => true,
+            Some(TriggerDirection::AtOrAbove) => spot_price >= trigger_price,
+            Some(TriggerDirection::AtOrBelow) => spot_price <= trigger_price,
+        }
+    }
+
+    // Mint the keeper fee is paid in.
+    fn keeper_fee_mint(&self) -> Pubkey {
+        match self.keeper_fee {
+            KeeperFee::Fixed { .. } => self.input_mint,
+            KeeperFee::OutputBps { .. } => self.output_mint,
+        }
+    }
+}
+
+// Resting limit orders of one pool. Asks sell token A and bids sell token B; each side is
+// kept sorted best first by the maker's limit price, which for both sides is the least
+// output per unit of input and so lower is better for a taker.
+#[account(zero_copy)]
+pub struct OrderBook {
+    pub pool: Pubkey,
+    pub ask_count: u32,
+    pub bid_count: u32,
+    // Smallest input an order may rest with, keeping the book from filling with dust.
+    pub min_ask_amount: u64,
+    pub min_bid_amount: u64,
+    pub asks: [BookEntry; ORDER_BOOK_DEPTH],
+    pub bids: [BookEntry; ORDER_BOOK_DEPTH],
+    pub bump: u8,
+    pub _padding: [u8; 7],
+}
+
+#[zero_copy]
+#[derive(Default)]
+pub struct BookEntry {
+    pub order: Pubkey,
+    pub price: u64,
+    pub expiration: i64,
+}
+
+impl OrderBook {
+    fn side_mut(&mut self, direction: SwapDirection) -> (&mut [BookEntry; ORDER_BOOK_DEPTH], &mut u32) {
+        match direction {
+            SwapDirection::AToB => (&mut self.asks, &mut self.ask_count),
+            SwapDirection::BToA => (&mut self.bids, &mut self.bid_count),
+        }
+    }
+
+    fn entries(&self, direction: SwapDirection) -> &[BookEntry] {
+        match direction {
+            SwapDirection::AToB => &self.asks[..self.ask_count as usize],
+            SwapDirection::BToA => &self.bids[..self.bid_count as usize],
+        }
+    }
+
+    fn min_amount(&self, direction: SwapDirection) -> u64 {
+        match direction {
+            SwapDirection::AToB => self.min_ask_amount,
+            SwapDirection::BToA => self.min_bid_amount,
+        }
+    }
+
+    // Inserts behind every entry at the same or a better price, so ties keep time priority.
+    fn insert(&mut self, direction: SwapDirection, order: Pubkey, price: u64, expiration: i64) -> Result<()> {
+        let position = self.entries(direction).partition_point(|entry| entry.price <= price);
+        let (entries, count) = self.side_mut(direction);
+        let len = *count as usize;
+        require!(len < ORDER_BOOK_DEPTH, ErrorCode::OrderBookFull);
+        entries.copy_within(position..len, position + 1);
+        entries[position] = BookEntry { order, price, expiration };
+        *count += 1;
+        Ok(())
+    }
+
+    fn remove(&mut self, direction: SwapDirection, order: Pubkey) {
+        let (entries, count) = self.side_mut(direction);
+        let len = *count as usize;
+        if let Some(position) = entries[..len].iter().position(|entry| entry.order == order) {
+            entries.copy_within(position + 1..len, position);
+            entries[len - 1] = BookEntry::default();
+            *count -= 1;
+        }
+    }
+}
+
+// Per-user index of limit, DCA and long-term orders, so clients can list a user's open orders without
+// scanning every program account.
+#[account]
+#[derive(InitSpace)]
+pub struct UserOrderBook {
+    pub user: Pubkey,
+    pub bump: u8,
+    // Nonce the user's next order is derived from; never reused.
+    pub next_nonce: u64,
+    #[max_len(MAX_OPEN_ORDERS)]
+    pub open_orders: Vec<Pubkey>,
+}
+
+impl UserOrderBook {
+    fn remove_order(&mut self, order: Pubkey) {
+        self.open_orders.retain(|open_order| *open_order != order);
+    }
+}
+
+// Long-term order streams of a pool. Sell rates are tokens per second scaled by
+// SELL_RATE_SCALE; earnings are the output paid per unit of sell rate, scaled by
+// EARNINGS_SCALE, for orders selling token A (`_a`) or token B (`_b`).
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, Default, PartialEq, Eq, InitSpace)]
+pub struct TwammState {
+    pub last_virtual_order_time: i64,
+    pub sell_rate_a: u128,
+    pub sell_rate_b: u128,
+    pub earnings_per_rate_a: u128,
+    pub earnings_per_rate_b: u128,
+    // Sorted by expiry; an entry outlives its expiry until its last order is withdrawn or settled.
+    #[max_len(MAX_STREAM_EXPIRIES)]
+    pub expiries: Vec<StreamExpiry>,
+}
+
+impl TwammState {
+    fn sell_rate_mut(&mut self, direction: SwapDirection) -> &mut u128 {
+        match direction {
+            SwapDirection::AToB => &mut self.sell_rate_a,
+            SwapDirection::BToA => &mut self.sell_rate_b,
+        }
+    }
+
+    fn earnings_per_rate(&self, direction: SwapDirection) -> u128 {
+        match direction {
+            SwapDirection::AToB => self.earnings_per_rate_a,
+            SwapDirection::BToA => self.earnings_per_rate_b,
+        }
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq, InitSpace)]
+pub struct StreamExpiry {
+    pub expiry: i64,
+    // Sell rates that stop at `expiry`.
+    pub sell_rate_a: u128,
+    pub sell_rate_b: u128,
+    // The pool's earnings per rate at `expiry`, recorded once the streams pass it.
+    pub earnings_per_rate_a: u128,
+    pub earnings_per_rate_b: u128,
+    pub order_count: u32,
+}
+
+impl StreamExpiry {
+    fn sell_rate_mut(&mut self, direction: SwapDirection) -> &mut u128 {
+        match direction {
+            SwapDirection::AToB => &mut self.sell_rate_a,
+            SwapDirection::BToA => &mut self.sell_rate_b,
+        }
+    }
+
+    fn earnings_per_rate(&self, direction: SwapDirection) -> u128 {
+        match direction {
+            SwapDirection::AToB => self.earnings_per_rate_a,
+            SwapDirection::BToA => self.earnings_per_rate_b,
+        }
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct LongTermOrder {
+    pub user: Pubkey,
+    pub pool: Pubkey,
+    // The order lives at [LONG_TERM_ORDER_SEED, pool, user, nonce].
+    pub nonce: u64,
+    pub bump: u8,
+    pub direction: SwapDirection,
+    pub amount_in: u64,
+    // Input sold per second, scaled by SELL_RATE_SCALE.
+    pub sell_rate: u128,
+    pub start_time: i64,
+    pub expiry: i64,
+    // The stream's earnings per rate when proceeds were last paid out.
+    pub earnings_snapshot: u128,
+    pub withdrawn_amount: u64,
+}
+
+impl LongTermOrder {
+    // Output earned since the last withdrawal, given the stream's current earnings per rate.
+    fn proceeds(&self, earnings_per_rate: u128) -> Result<u64> {
+        let earned = earnings_per_rate.wrapping_sub(self.earnings_snapshot);
+        Ok(math::stream_proceeds(earned, self.sell_rate)?)
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct DcaOrder {
+    pub user: Pubkey,
+    pub pool: Pubkey,
+    // The order lives at [DCA_SEED, pool, user, nonce].
+    pub nonce: u64,
+    pub bump: u8,
+    pub direction: SwapDirection,
+    // Token account every slice's output is paid to.
+    pub destination: Pubkey,
+    // The unsold input sits in a token account at [ESCROW_SEED, dca_order].
+    pub escrow_bump: u8,
+    pub total_amount: u64,
+    pub amount_per_cycle: u64,
+    pub min_amount_out_per_cycle: u64,
+    // Seconds between slices.
+    pub interval: i64,
+    // Earliest time the next slice can be sold.
+    pub next_cycle_at: i64,
+    pub remaining_amount: u64,
+    pub received_amount: u64,
+    pub cycle_count: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DcaOrderParams {
+    pub direction: SwapDirection,
+    pub total_amount: u64,
+    pub amount_per_cycle: u64,
+    // Least output a full slice accepts.
+    pub min_amount_out_per_cycle: u64,
+    pub interval: i64,
+}
+
+// `target_price` is the least output per unit of input the order accepts, scaled by PRICE_SCALE;
+// Dutch auctions ignore it and follow their own schedule.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LimitOrderParams {
+    pub direction: SwapDirection,
+    pub amount_in: u64,
     pub target_price: u64,
     pub expiration: i64,
     pub partial_fill: bool,
+    pub min_fill_amount: u64,
+    pub keeper_fee: KeeperFee,
+    pub order_type: OrderType,
+    // Pool spot price, token B per token A scaled by PRICE_SCALE; ignored for limit orders.
+    pub trigger_price: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq, InitSpace)]
+pub enum OrderType {
+    // Fillable whenever the pool meets `target_price`.
+    Limit,
+    // Sells once the price of the input token falls to the trigger.
+    StopLoss,
+    // Sells once the price of the input token rises to the trigger.
+    TakeProfit,
+    // Accepts a limit price that decays from `start_price` to `end_price` between
+    // `start_time` and `end_time`, so keepers compete to fill it as soon as it pays.
+    DutchAuction {
+        start_price: u64,
+        end_price: u64,
+        start_time: i64,
+        end_time: i64,
+        decay: PriceDecay,
+    },
+}
+
+impl OrderType {
+    // The pool price is quoted as token B per token A, so the input token's price falls
+    // with it when selling A and rises with it when selling B.
+    fn trigger_direction(self, direction: SwapDirection) -> Option<TriggerDirection> {
+        match (self, direction) {
+            (OrderType::Limit | OrderType::DutchAuction { .. }, _) => None,
+            (OrderType::StopLoss, SwapDirection::AToB) | (OrderType::TakeProfit, SwapDirection::BToA) => {
+                Some(TriggerDirection::AtOrBelow)
+            }
+            (OrderType::StopLoss, SwapDirection::BToA) | (OrderType::TakeProfit, SwapDirection::AToB) => {
+                Some(TriggerDirection::AtOrAbove)
+            }
+        }
+    }
+}
+
+// Shape of a Dutch auction's price curve; an exponential one falls by the same
+// fraction every second.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq, InitSpace)]
+pub enum PriceDecay {
+    Linear,
+    Exponential,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq, InitSpace)]
+pub enum TriggerDirection {
+    AtOrAbove,
+    AtOrBelow,
+}
+
+// Reward for whoever executes an order. A fixed fee is in the input token and escrowed
+// on top of the order amount; a bps fee is taken from the output at execution.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq, InitSpace)]
+pub enum KeeperFee {
+    Fixed { amount: u64 },
+    OutputBps { bps: u16 },
+}
+
+// Instruction args passed to a flash swap borrower after FLASH_SWAP_CALLBACK_DISCRIMINATOR.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FlashSwapCallback {
+    pub amount: u64,
+    pub fee: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq, InitSpace)]
+pub enum SwapDirection {
+    AToB,
+    BToA,
+}
+
+impl SwapDirection {
+    fn opposite(self) -> Self {
+        match self {
+            SwapDirection::AToB => SwapDirection::BToA,
+            SwapDirection::BToA => SwapDirection::AToB,
+        }
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SwapMode {
+    ExactIn,
+    ExactOut,
 }
 
 // Error Codes
@@ -459,5 +3902,100 @@ pub enum ErrorCode {
     PriceNotMet,
     #[msg("Flash swap failed, tokens not returned")]
     FlashSwapFailed,
+    #[msg("Pool mints must be passed in canonical order")]
+    MintsNotOrdered,
+    #[msg("Token account mint does not match the pool")]
+    InvalidMint,
+    #[msg("Vault does not belong to the pool")]
+    InvalidVault,
+    #[msg("Fee rate exceeds the fee denominator")]
+    InvalidFeeRate,
+    #[msg("Deposit too small to mint LP tokens")]
+    InsufficientLiquidityMinted,
+    #[msg("Withdrawal too small to return any tokens")]
+    InsufficientLiquidityBurned,
+    #[msg("Arithmetic overflow")]
+    MathOverflow,
+    #[msg("Pool reserves are empty")]
+    ZeroReserves,
+    #[msg("Trade too small to produce any output")]
+    ZeroOutput,
+    #[msg("Pool reserves cannot cover the requested output")]
+    InsufficientReserves,
+    #[msg("This operation is paused")]
+    ProgramPaused,
+    #[msg("Signer is not the swap admin")]
+    Unauthorized,
+    #[msg("Trade moves the pool price beyond the circuit breaker threshold")]
+    PriceMoveTooLarge,
+    #[msg("Pool circuit breaker has tripped; swaps are halted until reset")]
+    CircuitBreakerActive,
+    #[msg("Pool is locked by an in-flight flash swap")]
+    PoolLocked,
+    #[msg("flash_borrow must be followed by a matching flash_repay in the same transaction")]
+    FlashRepayMissing,
+    #[msg("No outstanding flash loan matches this repayment")]
+    NoOutstandingFlashLoan,
+    #[msg("Flash loan fee exceeds the maximum")]
+    InvalidFlashFee,
+    #[msg("Keeper fee exceeds the order output")]
+    InvalidKeeperFee,
+    #[msg("Order has not expired yet")]
+    OrderNotExpired,
+    #[msg("Token account does not belong to the order owner")]
+    InvalidOwner,
+    #[msg("Fill amount is below the order minimum or exceeds what remains")]
+    InvalidFillAmount,
+    #[msg("User already has the maximum number of open orders")]
+    TooManyOpenOrders,
+    #[msg("Trigger orders need a non-zero trigger price")]
+    InvalidTrigger,
+    #[msg("Pool price has not reached the order's trigger")]
+    TriggerNotReached,
+    #[msg("Only plain limit orders can rest on the order book")]
+    InvalidBookOrder,
+    #[msg("Pool order book is full on this side")]
+    OrderBookFull,
+    #[msg("The pool's order book must be passed for this order")]
+    OrderBookRequired,
+    #[msg("Maker order is not a crossing, resting order of this pool")]
+    InvalidMaker,
+    #[msg("DCA slices must be non-zero, fit the total and be spaced by a positive interval")]
+    InvalidDcaSchedule,
+    #[msg("The next DCA cycle is not due yet")]
+    DcaCycleNotDue,
+    #[msg("Long-term orders need a non-zero sell rate over at least one interval")]
+    InvalidLongTermOrder,
+    #[msg("Pool already tracks the maximum number of long-term order expiries")]
+    TooManyStreamExpiries,
+    #[msg("Long-term order has run to completion; withdraw its proceeds instead")]
+    LongTermOrderExpired,
+    #[msg("Dutch auctions must decay over a non-empty window to a non-zero end price when exponential")]
+    InvalidAuction,
+    #[msg("Route hops must chain distinct pools from the source token to the destination token")]
+    InvalidRoute,
+    #[msg("Order is below the order book's minimum size for its side")]
+    BookOrderTooSmall,
+    #[msg("A resting maker still crosses the part of the fill left for the pool")]
+    CrossingMakerSkipped,
+    #[msg("Long-term order streams are halted while swaps are paused or the circuit breaker is tripped")]
+    StreamsHalted,
+}
+
+impl From<math::MathError> for ErrorCode {
+    fn from(error: math::MathError) -> Self {
+        match error {
+            math::MathError::Overflow => ErrorCode::MathOverflow,
+            math::MathError::ZeroReserves => ErrorCode::ZeroReserves,
+            math::MathError::ZeroOutput => ErrorCode::ZeroOutput,
+            math::MathError::InsufficientReserves => ErrorCode::InsufficientReserves,
+        }
+    }
+}
+
+impl From<math::MathError> for Error {
+    fn from(error: math::MathError) -> Self {
+        ErrorCode::from(error).into()
+    }
 }
 
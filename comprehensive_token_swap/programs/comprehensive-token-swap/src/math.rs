@@ -0,0 +1,932 @@
+//! Checked constant-product math for the swap program.
+//!
+//! Everything here is plain Rust with no Anchor or Solana types, so clients can quote
+//! trades off-chain with exactly the numbers the program uses. Intermediates are u128,
+//! nothing panics, and every rounding step is taken in the pool's favour.
+
+/// Denominator for `SwapState::fee_rate` and for the curve fee.
+pub const FEE_DENOMINATOR: u64 = 1000;
+/// Share of each input that reaches the curve after the 0.3% LP fee.
+pub const CURVE_FEE_NUMERATOR: u64 = 997;
+/// Fixed-point scale for prices quoted as token B per token A.
+pub const PRICE_SCALE: u64 = 1_000_000_000;
+pub const BPS_DENOMINATOR: u64 = 10_000;
+/// Fixed-point scale for long-term order sell rates, in tokens per second.
+pub const SELL_RATE_SCALE: u64 = 1_000_000_000;
+/// Fixed-point scale for long-term order proceeds per unit of sell rate.
+pub const EARNINGS_SCALE: u128 = 1_000_000_000_000_000_000;
+
+// Fixed-point scale for the TWAMM closed form, and e at that scale.
+const WAD: u128 = 1_000_000_000_000_000_000;
+const E_WAD: u128 = 2_718_281_828_459_045_235;
+const LN_2_WAD: u128 = 693_147_180_559_945_309;
+// Past e^40 the closed form equals its limit to well within WAD precision.
+const MAX_EXPONENT_WAD: u128 = 40 * WAD;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MathError {
+    /// An intermediate or the result does not fit its integer type.
+    Overflow,
+    /// A reserve or supply used as a divisor is zero.
+    ZeroReserves,
+    /// A non-zero input would produce nothing.
+    ZeroOutput,
+    /// The requested output is not smaller than the reserve it comes from.
+    InsufficientReserves,
+}
+
+pub type MathResult<T> = Result<T, MathError>;
+
+fn to_u64(value: u128) -> MathResult<u64> {
+    u64::try_from(value).map_err(|_| MathError::Overflow)
+}
+
+// `numerator / denominator` rounded up; u128::div_ceil is newer than the SBF toolchains.
+fn ceil_div(numerator: u128, denominator: u128) -> u128 {
+    let quotient = numerator / denominator;
+    if quotient * denominator == numerator {
+        quotient
+    } else {
+        quotient + 1
+    }
+}
+
+/// `amount * numerator / denominator`, rounded down.
+pub fn mul_div_floor(amount: u64, numerator: u64, denominator: u64) -> MathResult<u64> {
+    if denominator == 0 {
+        return Err(MathError::ZeroReserves);
+    }
+    to_u64(amount as u128 * numerator as u128 / denominator as u128)
+}
+
+/// `amount * numerator / denominator`, rounded up.
+pub fn mul_div_ceil(amount: u64, numerator: u64, denominator: u64) -> MathResult<u64> {
+    if denominator == 0 {
+        return Err(MathError::ZeroReserves);
+    }
+    to_u64(ceil_div(amount as u128 * numerator as u128, denominator as u128))
+}
+
+/// Protocol fee on `amount` at `fee_rate` parts per `FEE_DENOMINATOR`, rounded up.
+pub fn fee_amount(amount: u64, fee_rate: u64) -> MathResult<u64> {
+    if fee_rate > FEE_DENOMINATOR {
+        return Err(MathError::Overflow);
+    }
+    mul_div_ceil(amount, fee_rate, FEE_DENOMINATOR)
+}
+
+/// Output of the 0.3% constant-product curve for `amount_in`, rounded down.
+pub fn swap_amount_out(amount_in: u64, reserve_in: u64, reserve_out: u64) -> MathResult<u64> {
+    if reserve_in == 0 || reserve_out == 0 {
+        return Err(MathError::ZeroReserves);
+    }
+    if amount_in == 0 {
+        return Ok(0);
+    }
+    let amount_in_with_fee = amount_in as u128 * CURVE_FEE_NUMERATOR as u128;
+    let numerator = amount_in_with_fee
+        .checked_mul(reserve_out as u128)
+        .ok_or(MathError::Overflow)?;
+    let denominator = reserve_in as u128 * FEE_DENOMINATOR as u128 + amount_in_with_fee;
+    match to_u64(numerator / denominator)? {
+        0 => Err(MathError::ZeroOutput),
+        amount_out => Ok(amount_out),
+    }
+}
+
+/// Output of an exact-in swap after the 0.3% curve fee and the protocol fee at
+/// `fee_rate`, returned as `(net_out, fee)`. The fee is taken from the output.
+pub fn quote_exact_in(amount_in: u64, reserve_in: u64, reserve_out: u64, fee_rate: u64) -> MathResult<(u64, u64)> {
+    let gross_out = swap_amount_out(amount_in, reserve_in, reserve_out)?;
+    let fee = fee_amount(gross_out, fee_rate)?;
+    Ok((gross_out - fee, fee))
+}
+
+/// Gross amount that still leaves `net_amount` after `fee_amount` at `fee_rate`, rounded up.
+pub fn amount_before_fee(net_amount: u64, fee_rate: u64) -> MathResult<u64> {
+    if fee_rate >= FEE_DENOMINATOR {
+        return Err(MathError::Overflow);
+    }
+    mul_div_ceil(net_amount, FEE_DENOMINATOR, FEE_DENOMINATOR - fee_rate)
+}
+
+/// Input the 0.3% constant-product curve needs to release `amount_out`, rounded up.
+pub fn swap_amount_in(amount_out: u64, reserve_in: u64, reserve_out: u64) -> MathResult<u64> {
+    if reserve_in == 0 || reserve_out == 0 {
+        return Err(MathError::ZeroReserves);
+    }
+    if amount_out >= reserve_out {
+        return Err(MathError::InsufficientReserves);
+    }
+    if amount_out == 0 {
+        return Ok(0);
+    }
+    let numerator = (reserve_in as u128 * amount_out as u128)
+        .checked_mul(FEE_DENOMINATOR as u128)
+        .ok_or(MathError::Overflow)?;
+    let denominator = (reserve_out - amount_out) as u128 * CURVE_FEE_NUMERATOR as u128;
+    to_u64(ceil_div(numerator, denominator))
+}
+
+/// Fee-free constant-product output for `amount` against `(reserve_in, reserve_out)`,
+/// rounded down.
+pub fn constant_product_out(amount: u64, reserve_in: u64, reserve_out: u64) -> MathResult<u64> {
+    if reserve_in == 0 || reserve_out == 0 {
+        return Err(MathError::ZeroReserves);
+    }
+    if amount == 0 {
+        return Ok(0);
+    }
+    let numerator = amount as u128 * reserve_out as u128;
+    let denominator = reserve_in as u128 + amount as u128;
+    match to_u64(numerator / denominator)? {
+        0 => Err(MathError::ZeroOutput),
+        amount_out => Ok(amount_out),
+    }
+}
+
+/// Spot price of token A in token B, scaled by `PRICE_SCALE` and rounded down.
+pub fn spot_price(reserve_a: u64, reserve_b: u64) -> MathResult<u128> {
+    if reserve_a == 0 {
+        return Err(MathError::ZeroReserves);
+    }
+    Ok(reserve_b as u128 * PRICE_SCALE as u128 / reserve_a as u128)
+}
+
+/// Spot price of token A in token B as a Q64.64 fixed-point number, rounded down. Unlike
+/// `spot_price` it never rounds a non-zero price to zero.
+pub fn price_q64(reserve_a: u64, reserve_b: u64) -> MathResult<u128> {
+    if reserve_a == 0 {
+        return Err(MathError::ZeroReserves);
+    }
+    Ok(((reserve_b as u128) << 64) / reserve_a as u128)
+}
+
+/// Relative move from `price_before` to `price_after` in basis points, rounded up and
+/// saturating at u64::MAX. Whole multiples of `price_before` are split off first, so
+/// Q64.64 prices near u128::MAX do not overflow.
+pub fn price_move_bps(price_before: u128, price_after: u128) -> MathResult<u64> {
+    if price_before == 0 {
+        return Err(MathError::ZeroReserves);
+    }
+    let change = price_before.abs_diff(price_after);
+    let bps = BPS_DENOMINATOR as u128;
+    let fraction = match (change % price_before).checked_mul(bps) {
+        Some(scaled) => ceil_div(scaled, price_before),
+        // Only reachable above 2^114, where dropping the low 14 bits of both sides
+        // shifts the quotient by far less than one bps.
+        None => ceil_div(((change % price_before) >> 14) * bps, price_before >> 14),
+    };
+    let move_bps = (change / price_before).saturating_mul(bps).saturating_add(fraction);
+    Ok(u64::try_from(move_bps).unwrap_or(u64::MAX))
+}
+
+/// Whether a move of `move_bps` breaks a circuit breaker limit; a zero limit is disabled.
+pub fn exceeds_move_limit(move_bps: u64, max_move_bps: u16) -> bool {
+    max_move_bps > 0 && move_bps > max_move_bps as u64
+}
+
+/// Largest deposit within `(max_a, max_b)` that matches the reserve ratio. The side
+/// derived from the ratio is rounded up, so depositors never under-pay.
+pub fn balanced_deposit(max_a: u64, max_b: u64, reserve_a: u64, reserve_b: u64) -> MathResult<(u64, u64)> {
+    let optimal_b = mul_div_ceil(max_a, reserve_b, reserve_a)?;
+    if optimal_b <= max_b {
+        Ok((max_a, optimal_b))
+    } else {
+        Ok((mul_div_ceil(max_b, reserve_a, reserve_b)?.min(max_a), max_b))
+    }
+}
+
+/// Portion of a single-sided deposit to swap so that the remainder matches the
+/// post-swap reserve ratio, solved in closed form from the 0.3% curve and the protocol
+/// fee at `fee_rate` taken from the swap output, as in `quote_exact_in`. Rounded down.
+pub fn zap_swap_amount(amount_in: u64, reserve_in: u64, fee_rate: u64) -> MathResult<u64> {
+    if reserve_in == 0 {
+        return Err(MathError::ZeroReserves);
+    }
+    if fee_rate > FEE_DENOMINATOR {
+        return Err(MathError::Overflow);
+    }
+    let (amount_in, reserve_in) = (amount_in as u128, reserve_in as u128);
+    let (denominator, curve, fee_rate) = (FEE_DENOMINATOR as u128, CURVE_FEE_NUMERATOR as u128, fee_rate as u128);
+    // With g the curve share and p the protocol share kept, the swap s solves
+    // g*s^2 + (R*(1 + p*g) - a*g*(1 - p))*s - a*R = 0; scaled by FEE_DENOMINATOR^2 here.
+    let linear_up = reserve_in * (denominator * denominator + (denominator - fee_rate) * curve);
+    let linear_down = amount_in * curve * fee_rate;
+    let linear = linear_up.abs_diff(linear_down);
+    let discriminant = add_wide(
+        widening_mul(linear, linear),
+        widening_mul(4 * curve * denominator * denominator * denominator * amount_in, reserve_in),
+    );
+    let root = sqrt_wide(discriminant);
+    let numerator = if linear_up >= linear_down { root - linear } else { root + linear };
+    to_u64(numerator / (2 * curve * denominator))
+}
+
+/// Whether `fill_amount` may be taken from an order with `remaining` input left: the
+/// whole remainder always may, a smaller fill only if the order allows partial fills and
+/// it is at least `min_fill`.
+pub fn is_valid_fill(fill_amount: u64, remaining: u64, partial_fill: bool, min_fill: u64) -> bool {
+    fill_amount > 0
+        && fill_amount <= remaining
+        && (fill_amount == remaining || (partial_fill && fill_amount >= min_fill))
+}
+
+/// Part of a fixed keeper `fee` earned by filling `fill_amount` of an order for
+/// `order_amount`, rounded down. The fill that empties the order, with `remaining` input
+/// left, takes `fee_left` instead, so the shares always add up to the whole fee.
+pub fn fixed_keeper_fee_share(
+    fee: u64,
+    fee_left: u64,
+    fill_amount: u64,
+    remaining: u64,
+    order_amount: u64,
+) -> MathResult<u64> {
+    if fill_amount == remaining {
+        return Ok(fee_left);
+    }
+    mul_div_floor(fee, fill_amount, order_amount)
+}
+
+/// Keeper's cut of `amount_out` at `bps` basis points, rounded down.
+pub fn output_keeper_fee(amount_out: u64, bps: u64) -> MathResult<u64> {
+    mul_div_floor(amount_out, bps, BPS_DENOMINATOR)
+}
+
+/// Sizes a match between a taker with `taker_left` input to sell and a resting maker
+/// with `maker_remaining` of the opposite token. Prices are each side's minimum output
+/// per unit of input, scaled by `PRICE_SCALE`; the match executes at the maker's price.
+/// Returns `(taker_in, maker_in)`, with the taker's payment rounded up in the maker's
+/// favour, or `None` if nothing can be matched or the maker's price is worse than the
+/// taker's limit.
+pub fn match_amounts(
+    taker_left: u64,
+    taker_price: u64,
+    maker_remaining: u64,
+    maker_price: u64,
+) -> MathResult<Option<(u64, u64)>> {
+    let affordable = if maker_price == 0 {
+        u128::MAX
+    } else {
+        taker_left as u128 * PRICE_SCALE as u128 / maker_price as u128
+    };
+    let maker_in = (maker_remaining as u128).min(affordable) as u64;
+    if maker_in == 0 {
+        return Ok(None);
+    }
+    let taker_in = mul_div_ceil(maker_in, maker_price, PRICE_SCALE)?;
+    if (maker_in as u128) * (PRICE_SCALE as u128) < (taker_in as u128) * (taker_price as u128) {
+        return Ok(None);
+    }
+    Ok(Some((taker_in, maker_in)))
+}
+
+/// Output of two opposing streams that sell `amount_a` of token A and `amount_b` of
+/// token B into the pool at constant rates over the same period, in the closed form of
+/// the TWAMM paper. Both inputs pay the 0.3% curve fee up front. Returns
+/// `(b_out, a_out)`: token B owed to the A sellers and token A owed to the B sellers,
+/// rounded so the fee-free product of the reserves never shrinks. Streams too small to
+/// buy anything are absorbed into the reserves rather than failing.
+pub fn twamm_amounts_out(amount_a: u64, amount_b: u64, reserve_a: u64, reserve_b: u64) -> MathResult<(u64, u64)> {
+    if reserve_a == 0 || reserve_b == 0 {
+        return Err(MathError::ZeroReserves);
+    }
+    let net_a = amount_a as u128 * CURVE_FEE_NUMERATOR as u128 / FEE_DENOMINATOR as u128;
+    let net_b = amount_b as u128 * CURVE_FEE_NUMERATOR as u128 / FEE_DENOMINATOR as u128;
+    if net_b == 0 {
+        return Ok((or_zero(swap_amount_out(amount_a, reserve_a, reserve_b))?, 0));
+    }
+    if net_a == 0 {
+        return Ok((0, or_zero(swap_amount_out(amount_b, reserve_b, reserve_a))?));
+    }
+    let (reserve_a, reserve_b) = (reserve_a as u128, reserve_b as u128);
+    let k = reserve_a * reserve_b;
+
+    // exponent = 2 * sqrt(net_a * net_b / k), capped where e^exponent swamps the rest.
+    let exponent = match mul_div_wide(net_a * net_b, WAD * WAD, k) {
+        Ok(half_squared) => (2 * integer_sqrt(half_squared)).min(MAX_EXPONENT_WAD),
+        Err(_) => MAX_EXPONENT_WAD,
+    };
+    let e = exp_wad(exponent)?;
+
+    // p = sqrt(reserve_a * net_b) and q = sqrt(reserve_b * net_a), sharing a WAD scale;
+    // the reserve of A tends to reserve_a * q / p and starts off at c = (p - q) / (p + q).
+    let p = sqrt_product(reserve_a * net_b, WAD);
+    let q = sqrt_product(reserve_b * net_a, WAD);
+    let c = mul_div_wide(p.abs_diff(q), WAD, p + q)?;
+    let (numerator, denominator) = if p >= q { (e + c, e - c) } else { (e - c, e + c) };
+    let ratio = mul_div_wide(numerator, WAD, denominator)?;
+    let final_a = mul_div_wide(reserve_a, mul_div_wide(q, ratio, p)?, WAD)?
+        .clamp(1, reserve_a + net_a);
+    let final_b = ceil_div(k, final_a).min(reserve_b + net_b);
+
+    Ok((to_u64(reserve_b + net_b - final_b)?, to_u64(reserve_a + net_a - final_a)?))
+}
+
+/// Limit price of a Dutch auction at `now`: `start_price` until `start_time`,
+/// `end_price` from `end_time`, and in between either a straight line or, when
+/// `exponential`, a curve that falls by the same factor every second. Prices are scaled
+/// by `PRICE_SCALE`; the result is rounded up so an order never accepts less than its
+/// schedule.
+pub fn dutch_auction_price(
+    start_price: u64,
+    end_price: u64,
+    start_time: i64,
+    end_time: i64,
+    now: i64,
+    exponential: bool,
+) -> MathResult<u64> {
+    if now <= start_time {
+        return Ok(start_price);
+    }
+    if now >= end_time {
+        return Ok(end_price);
+    }
+    let elapsed = now.abs_diff(start_time) as u128;
+    let duration = end_time.abs_diff(start_time) as u128;
+    if !exponential {
+        let decayed = (start_price.saturating_sub(end_price)) as u128 * elapsed / duration;
+        return to_u64(start_price as u128 - decayed);
+    }
+    if end_price == 0 {
+        return Err(MathError::ZeroReserves);
+    }
+    // start * (end / start)^(elapsed / duration) = start / e^(ln(start / end) * elapsed / duration)
+    let log_ratio = ln_wad(mul_div_wide(start_price as u128, WAD, end_price as u128)?.max(WAD));
+    let decay = exp_wad(mul_div_wide(log_ratio, elapsed, duration)?)?;
+    let price = ceil_div(start_price as u128 * WAD, decay);
+    Ok(to_u64(price)?.clamp(end_price.min(start_price), start_price))
+}
+
+/// `ln(value)` for `value` of at least one, both sides scaled by 10^18, rounded down.
+fn ln_wad(value: u128) -> u128 {
+    // Halve into [1, 2), then ln(m) = 2 * atanh(z) = 2 * (z + z^3/3 + z^5/5 + ...)
+    // with z = (m - 1) / (m + 1) at most 1/3, so the series converges quickly.
+    let mut mantissa = value;
+    let mut halvings = 0;
+    while mantissa >= 2 * WAD {
+        mantissa /= 2;
+        halvings += 1;
+    }
+    let z = (mantissa - WAD) * WAD / (mantissa + WAD);
+    let z_squared = z * z / WAD;
+    let mut term = z;
+    let mut series = 0;
+    let mut denominator = 1;
+    while term > 0 {
+        series += term / denominator;
+        term = term * z_squared / WAD;
+        denominator += 2;
+    }
+    halvings * LN_2_WAD + 2 * series
+}
+
+/// Input a stream selling at `sell_rate` (scaled by `SELL_RATE_SCALE`) sells over
+/// `seconds`, rounded down.
+pub fn stream_amount(sell_rate: u128, seconds: u64) -> MathResult<u64> {
+    to_u64(mul_div_wide(sell_rate, seconds as u128, SELL_RATE_SCALE as u128)?)
+}
+
+/// Output owed to an order selling at `sell_rate` for `earnings` per unit of sell rate
+/// (scaled by `EARNINGS_SCALE`), rounded down.
+pub fn stream_proceeds(earnings: u128, sell_rate: u128) -> MathResult<u64> {
+    to_u64(mul_div_wide(earnings, sell_rate, EARNINGS_SCALE)?)
+}
+
+// Treats an output too small to be worth anything as nothing.
+fn or_zero(amount_out: MathResult<u64>) -> MathResult<u64> {
+    match amount_out {
+        Err(MathError::ZeroOutput) => Ok(0),
+        result => result,
+    }
+}
+
+/// `e^exponent` with both sides scaled by 10^18, rounded down.
+fn exp_wad(exponent: u128) -> MathResult<u128> {
+    let (whole, fraction) = (exponent / WAD, exponent % WAD);
+    // Taylor series for the fractional part, which converges in under 25 terms.
+    let mut term = WAD;
+    let mut result = WAD;
+    for i in 1.. {
+        term = term * fraction / WAD / i;
+        if term == 0 {
+            break;
+        }
+        result += term;
+    }
+    for _ in 0..whole {
+        result = mul_div_wide(result, E_WAD, WAD)?;
+    }
+    Ok(result)
+}
+
+/// `floor(a * b / denominator)` with a full 256-bit intermediate product.
+pub fn mul_div_wide(a: u128, b: u128, denominator: u128) -> MathResult<u128> {
+    if denominator == 0 {
+        return Err(MathError::ZeroReserves);
+    }
+    let (high, low) = widening_mul(a, b);
+    if high == 0 {
+        return Ok(low / denominator);
+    }
+    if high >= denominator {
+        return Err(MathError::Overflow);
+    }
+    // Long division one bit at a time; the remainder stays below the denominator, with
+    // any bit shifted out of it accounted for by `carry`.
+    let mut remainder = high;
+    let mut quotient = 0;
+    for bit in (0..128).rev() {
+        let carry = remainder >> 127;
+        remainder = (remainder << 1) | ((low >> bit) & 1);
+        quotient <<= 1;
+        if carry == 1 || remainder >= denominator {
+            remainder = remainder.wrapping_sub(denominator);
+            quotient |= 1;
+        }
+    }
+    Ok(quotient)
+}
+
+/// `floor(sqrt(value))`.
+pub fn integer_sqrt(value: u128) -> u128 {
+    if value < 2 {
+        return value;
+    }
+    let mut x = value;
+    let mut y = ceil_div(x, 2);
+    while y < x {
+        x = y;
+        y = (x + value / x) / 2;
+    }
+    x
+}
+
+/// `floor(sqrt(a * b))` without requiring the product to fit in u128.
+pub fn sqrt_product(a: u128, b: u128) -> u128 {
+    if let Some(product) = a.checked_mul(b) {
+        return integer_sqrt(product);
+    }
+    let target = widening_mul(a, b);
+    // sqrt(a) * sqrt(b) brackets the root to within sqrt(a) + sqrt(b) + 1.
+    let (root_a, root_b) = (integer_sqrt(a), integer_sqrt(b));
+    let mut low = root_a * root_b;
+    let mut high = (root_a + 1).saturating_mul(root_b + 1);
+    while low < high {
+        let mid = low + (high - low) / 2 + 1;
+        if widening_mul(mid, mid) <= target {
+            low = mid;
+        } else {
+            high = mid - 1;
+        }
+    }
+    low
+}
+
+// Square root of a 256-bit value given as (high, low) halves, rounded down.
+fn sqrt_wide(value: (u128, u128)) -> u128 {
+    let (mut low, mut high) = (0, u128::MAX);
+    while low < high {
+        let mid = low + (high - low) / 2 + 1;
+        if widening_mul(mid, mid) <= value {
+            low = mid;
+        } else {
+            high = mid - 1;
+        }
+    }
+    low
+}
+
+// Sum of two 256-bit values as (high, low) halves; callers keep it below 2^256.
+fn add_wide(a: (u128, u128), b: (u128, u128)) -> (u128, u128) {
+    let (low, carry) = a.1.overflowing_add(b.1);
+    (a.0 + b.0 + carry as u128, low)
+}
+
+// Full 256-bit product as (high, low) halves, ordered so tuples compare numerically.
+fn widening_mul(a: u128, b: u128) -> (u128, u128) {
+    const MASK: u128 = u64::MAX as u128;
+    let (a_hi, a_lo) = (a >> 64, a & MASK);
+    let (b_hi, b_lo) = (b >> 64, b & MASK);
+    let lo_lo = a_lo * b_lo;
+    let lo_hi = a_lo * b_hi;
+    let hi_lo = a_hi * b_lo;
+    let hi_hi = a_hi * b_hi;
+    let middle = (lo_lo >> 64) + (lo_hi & MASK) + (hi_lo & MASK);
+    let low = (lo_lo & MASK) | (middle << 64);
+    let high = hi_hi + (lo_hi >> 64) + (hi_lo >> 64) + (middle >> 64);
+    (high, low)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX: u64 = u64::MAX;
+
+    #[test]
+    fn mul_div_rounds_in_opposite_directions() {
+        assert_eq!(mul_div_floor(10, 1, 3), Ok(3));
+        assert_eq!(mul_div_ceil(10, 1, 3), Ok(4));
+        assert_eq!(mul_div_floor(9, 1, 3), Ok(3));
+        assert_eq!(mul_div_ceil(9, 1, 3), Ok(3));
+        assert_eq!(mul_div_floor(0, MAX, 1), Ok(0));
+        assert_eq!(mul_div_ceil(0, MAX, 1), Ok(0));
+    }
+
+    #[test]
+    fn mul_div_at_u64_boundaries() {
+        assert_eq!(mul_div_floor(MAX, MAX, MAX), Ok(MAX));
+        assert_eq!(mul_div_ceil(MAX, MAX, MAX), Ok(MAX));
+        assert_eq!(mul_div_floor(MAX, MAX - 1, MAX), Ok(MAX - 1));
+        assert_eq!(mul_div_ceil(MAX, 1, 2), Ok(MAX / 2 + 1));
+        assert_eq!(mul_div_floor(MAX, 2, 1), Err(MathError::Overflow));
+        assert_eq!(mul_div_ceil(MAX, MAX, MAX - 1), Err(MathError::Overflow));
+    }
+
+    #[test]
+    fn mul_div_rejects_zero_denominator() {
+        assert_eq!(mul_div_floor(1, 1, 0), Err(MathError::ZeroReserves));
+        assert_eq!(mul_div_ceil(1, 1, 0), Err(MathError::ZeroReserves));
+    }
+
+    #[test]
+    fn fee_rounds_up() {
+        assert_eq!(fee_amount(0, 3), Ok(0));
+        assert_eq!(fee_amount(1, 3), Ok(1));
+        assert_eq!(fee_amount(333, 3), Ok(1));
+        assert_eq!(fee_amount(334, 3), Ok(2));
+        assert_eq!(fee_amount(1000, 3), Ok(3));
+        assert_eq!(fee_amount(1000, 0), Ok(0));
+    }
+
+    #[test]
+    fn fee_at_u64_boundaries() {
+        assert_eq!(fee_amount(MAX, FEE_DENOMINATOR), Ok(MAX));
+        assert_eq!(fee_amount(MAX, 3), Ok(ceil_div(MAX as u128 * 3, 1000) as u64));
+        assert_eq!(fee_amount(1, FEE_DENOMINATOR + 1), Err(MathError::Overflow));
+    }
+
+    #[test]
+    fn swap_out_matches_reference_values() {
+        // 1000 * 997 * 1_000_000 / (1_000_000 * 1000 + 997_000) = 996.00...
+        assert_eq!(swap_amount_out(1000, 1_000_000, 1_000_000), Ok(996));
+        assert_eq!(swap_amount_out(500, 1000, 1000), Ok(332));
+        assert_eq!(swap_amount_out(0, 1000, 1000), Ok(0));
+    }
+
+    #[test]
+    fn swap_out_never_breaks_the_invariant() {
+        let cases = [(1, 1, 1), (7, 3, 11), (1 << 40, 1 << 50, 1 << 30), (1 << 50, MAX, MAX / 2)];
+        for (amount_in, reserve_in, reserve_out) in cases {
+            let amount_out = swap_amount_out(amount_in, reserve_in, reserve_out).unwrap_or(0);
+            assert!(amount_out < reserve_out);
+            let before = reserve_in as u128 * reserve_out as u128;
+            let after = (reserve_in as u128 + amount_in as u128) * (reserve_out - amount_out) as u128;
+            assert!(after >= before);
+        }
+    }
+
+    #[test]
+    fn swap_out_at_u64_boundaries() {
+        assert_eq!(swap_amount_out(1, MAX, MAX), Err(MathError::ZeroOutput));
+        assert_eq!(swap_amount_out(MAX, 1, MAX), Err(MathError::Overflow));
+        assert!(swap_amount_out(1_000_000_000_000_000, MAX, MAX).is_ok());
+        assert_eq!(swap_amount_out(MAX, MAX, 1), Err(MathError::ZeroOutput));
+    }
+
+    #[test]
+    fn swap_out_rejects_empty_pools() {
+        assert_eq!(swap_amount_out(1, 0, 1000), Err(MathError::ZeroReserves));
+        assert_eq!(swap_amount_out(1, 1000, 0), Err(MathError::ZeroReserves));
+        assert_eq!(swap_amount_out(0, 0, 0), Err(MathError::ZeroReserves));
+    }
+
+    #[test]
+    fn amount_before_fee_covers_the_fee() {
+        for (net_amount, fee_rate) in [(0, 3), (1, 3), (996, 3), (997, 3), (1000, 0), (12_345, 999), (MAX / 2, 3)] {
+            let gross = amount_before_fee(net_amount, fee_rate).unwrap();
+            assert!(gross - fee_amount(gross, fee_rate).unwrap() >= net_amount);
+            if gross > 0 {
+                assert!(gross - 1 - fee_amount(gross - 1, fee_rate).unwrap() < net_amount);
+            }
+        }
+        assert_eq!(amount_before_fee(MAX, 3), Err(MathError::Overflow));
+        assert_eq!(amount_before_fee(1, FEE_DENOMINATOR), Err(MathError::Overflow));
+    }
+
+    #[test]
+    fn swap_in_is_the_smallest_sufficient_input() {
+        let cases = [(1, 1000, 1000), (332, 1000, 1000), (996, 1_000_000, 1_000_000), (1 << 30, 1 << 50, 1 << 40)];
+        for (amount_out, reserve_in, reserve_out) in cases {
+            let amount_in = swap_amount_in(amount_out, reserve_in, reserve_out).unwrap();
+            assert!(swap_amount_out(amount_in, reserve_in, reserve_out).unwrap() >= amount_out);
+            assert!(swap_amount_out(amount_in - 1, reserve_in, reserve_out).unwrap_or(0) < amount_out);
+        }
+    }
+
+    #[test]
+    fn swap_in_at_u64_boundaries() {
+        assert_eq!(swap_amount_in(0, MAX, MAX), Ok(0));
+        assert_eq!(swap_amount_in(MAX, MAX, MAX), Err(MathError::InsufficientReserves));
+        assert_eq!(swap_amount_in(1 << 40, MAX, MAX), Ok(1_102_820_153_774));
+        assert_eq!(swap_amount_in(MAX - 1, 1, MAX), Err(MathError::Overflow));
+        assert_eq!(swap_amount_in(MAX - 1, MAX, MAX), Err(MathError::Overflow));
+        assert_eq!(swap_amount_in(1, 0, MAX), Err(MathError::ZeroReserves));
+    }
+
+    #[test]
+    fn constant_product_out_at_boundaries() {
+        assert_eq!(constant_product_out(500, 1000, 1000), Ok(333));
+        assert_eq!(constant_product_out(MAX, MAX, MAX), Ok(MAX / 2));
+        assert_eq!(constant_product_out(1, MAX, 1), Err(MathError::ZeroOutput));
+        assert_eq!(constant_product_out(0, 1, 1), Ok(0));
+        assert_eq!(constant_product_out(1, 0, 1), Err(MathError::ZeroReserves));
+    }
+
+    #[test]
+    fn spot_price_at_u64_boundaries() {
+        assert_eq!(spot_price(1000, 2000), Ok(2 * PRICE_SCALE as u128));
+        assert_eq!(spot_price(3, 1), Ok(333_333_333));
+        assert_eq!(spot_price(1, MAX), Ok(MAX as u128 * PRICE_SCALE as u128));
+        assert_eq!(spot_price(MAX, 1), Ok(0));
+        assert_eq!(spot_price(0, 1), Err(MathError::ZeroReserves));
+    }
+
+    #[test]
+    fn price_move_is_symmetric_in_direction_and_rounds_up() {
+        assert_eq!(price_move_bps(1000, 1000), Ok(0));
+        assert_eq!(price_move_bps(1000, 1100), Ok(1000));
+        assert_eq!(price_move_bps(1000, 900), Ok(1000));
+        assert_eq!(price_move_bps(3, 4), Ok(3334));
+        assert_eq!(price_move_bps(1, 0), Ok(BPS_DENOMINATOR));
+        assert_eq!(price_move_bps(0, 1), Err(MathError::ZeroReserves));
+    }
+
+    #[test]
+    fn q64_price_stays_positive_at_u64_boundaries() {
+        assert_eq!(price_q64(1000, 2000), Ok(2 << 64));
+        assert_eq!(price_q64(MAX, 1), Ok(1));
+        assert_eq!(price_q64(1, MAX), Ok((MAX as u128) << 64));
+        assert_eq!(price_q64(0, 1), Err(MathError::ZeroReserves));
+    }
+
+    #[test]
+    fn move_limits_trip_only_past_the_threshold() {
+        // 1% of the price is exactly 100 bps; one unit more rounds up to 101 either way.
+        assert_eq!(price_move_bps(1_000_000, 1_010_000), Ok(100));
+        assert_eq!(price_move_bps(1_000_000, 990_000), Ok(100));
+        assert_eq!(price_move_bps(1_000_000, 1_010_001), Ok(101));
+        assert_eq!(price_move_bps(1_000_000, 989_999), Ok(101));
+        assert!(!exceeds_move_limit(100, 100));
+        assert!(exceeds_move_limit(101, 100));
+        assert!(!exceeds_move_limit(u16::MAX as u64, u16::MAX));
+        assert!(exceeds_move_limit(u64::MAX, u16::MAX));
+        // A zero limit is disabled rather than tripping on every move.
+        assert!(!exceeds_move_limit(1, 0));
+        assert!(!exceeds_move_limit(u64::MAX, 0));
+    }
+
+    #[test]
+    fn price_move_at_boundaries() {
+        let max_price = spot_price(1, MAX).unwrap();
+        assert_eq!(price_move_bps(1, max_price), Ok(u64::MAX));
+        assert_eq!(price_move_bps(1, u128::MAX), Ok(u64::MAX));
+        assert_eq!(price_move_bps(max_price, 0), Ok(BPS_DENOMINATOR));
+        // Q64.64 prices past u128::MAX / BPS_DENOMINATOR still round up.
+        assert_eq!(price_move_bps(u128::MAX, u128::MAX / 2), Ok(5001));
+        assert_eq!(price_move_bps(u128::MAX, u128::MAX - 1), Ok(1));
+        let max_q64 = price_q64(1, MAX).unwrap();
+        assert_eq!(price_move_bps(max_q64, max_q64 - max_q64 / 10_000), Ok(1));
+    }
+
+    #[test]
+    fn balanced_deposit_takes_the_limiting_side() {
+        assert_eq!(balanced_deposit(100, 500, 1000, 2000), Ok((100, 200)));
+        assert_eq!(balanced_deposit(500, 100, 1000, 2000), Ok((50, 100)));
+        // The derived side rounds up.
+        assert_eq!(balanced_deposit(1, 10, 3, 1), Ok((1, 1)));
+        assert_eq!(balanced_deposit(MAX, MAX, MAX, MAX), Ok((MAX, MAX)));
+        assert_eq!(balanced_deposit(1, 1, 0, 1), Err(MathError::ZeroReserves));
+    }
+
+    #[test]
+    fn zap_leaves_a_balanced_remainder() {
+        let (reserve_in, reserve_out) = (1_000_000u64, 2_000_000u64);
+        for (amount_in, fee_rate) in [(100_000u64, 0), (100_000, 3), (100_000, 250), (5_000_000, 3)] {
+            let swap_in = zap_swap_amount(amount_in, reserve_in, fee_rate).unwrap();
+            let (swap_out, _) = quote_exact_in(swap_in, reserve_in, reserve_out, fee_rate).unwrap();
+            let remainder_ratio = (amount_in - swap_in) as f64 / (reserve_in + swap_in) as f64;
+            let output_ratio = swap_out as f64 / (reserve_out - swap_out) as f64;
+            assert!((remainder_ratio - output_ratio).abs() < 1e-5, "{amount_in} at {fee_rate}");
+        }
+    }
+
+    #[test]
+    fn zap_at_u64_boundaries() {
+        assert_eq!(zap_swap_amount(0, MAX, 3), Ok(0));
+        for fee_rate in [0, 3, 999] {
+            let swap_in = zap_swap_amount(MAX, MAX, fee_rate).unwrap();
+            assert!(swap_in > 0 && swap_in < MAX);
+        }
+        // A swap that keeps none of its output would have to take the whole deposit.
+        assert_eq!(zap_swap_amount(MAX, MAX, FEE_DENOMINATOR), Ok(MAX));
+        assert_eq!(zap_swap_amount(1_000, 1_000_000, FEE_DENOMINATOR), Ok(1_000));
+        assert_eq!(zap_swap_amount(1, 0, 3), Err(MathError::ZeroReserves));
+        assert_eq!(zap_swap_amount(1, 1, FEE_DENOMINATOR + 1), Err(MathError::Overflow));
+    }
+
+    #[test]
+    fn quote_exact_in_takes_the_protocol_fee_from_the_output() {
+        assert_eq!(quote_exact_in(10_000, 1_000_000, 1_000_000, 0), Ok((9_871, 0)));
+        assert_eq!(quote_exact_in(10_000, 1_000_000, 1_000_000, 3), Ok((9_841, 30)));
+        assert_eq!(quote_exact_in(0, 1_000_000, 1_000_000, 3), Ok((0, 0)));
+        assert_eq!(quote_exact_in(1, 1_000_000, 1_000_000, 3), Err(MathError::ZeroOutput));
+    }
+
+    #[test]
+    fn match_amounts_fills_at_maker_price() {
+        // Maker sells B for at least 0.5 A each; the taker sells A for at least 1 B each.
+        assert_eq!(match_amounts(100, PRICE_SCALE, 150, PRICE_SCALE / 2), Ok(Some((75, 150))));
+        assert_eq!(match_amounts(100, PRICE_SCALE, 1_000, PRICE_SCALE / 2), Ok(Some((100, 200))));
+        // The taker's payment rounds up: 3 units at 1/3 A each cost a full unit.
+        assert_eq!(match_amounts(10, 0, 3, PRICE_SCALE / 3), Ok(Some((1, 3))));
+        assert_eq!(match_amounts(MAX, 0, MAX, 0), Ok(Some((0, MAX))));
+        assert_eq!(match_amounts(MAX, PRICE_SCALE, MAX, PRICE_SCALE), Ok(Some((MAX, MAX))));
+    }
+
+    #[test]
+    fn match_amounts_rejects_prices_that_do_not_cross() {
+        // Maker wants 2 A per B (0.5 B per A) but the taker needs 1 B per A.
+        assert_eq!(match_amounts(100, PRICE_SCALE, 100, 2 * PRICE_SCALE), Ok(None));
+        // Exactly reciprocal prices cross.
+        assert_eq!(match_amounts(100, 2 * PRICE_SCALE, 100, PRICE_SCALE / 2), Ok(Some((50, 100))));
+        assert_eq!(match_amounts(0, 0, 100, PRICE_SCALE), Ok(None));
+        assert_eq!(match_amounts(100, 0, 0, PRICE_SCALE), Ok(None));
+        assert_eq!(match_amounts(1, 0, 100, MAX), Ok(None));
+    }
+
+    #[test]
+    fn integer_sqrt_is_floor() {
+        assert_eq!(integer_sqrt(0), 0);
+        assert_eq!(integer_sqrt(1), 1);
+        assert_eq!(integer_sqrt(2), 1);
+        assert_eq!(integer_sqrt(3), 1);
+        assert_eq!(integer_sqrt(4), 2);
+        assert_eq!(integer_sqrt(99), 9);
+        assert_eq!(integer_sqrt(MAX as u128 * MAX as u128), MAX as u128);
+        assert_eq!(integer_sqrt(u128::MAX), MAX as u128);
+    }
+
+    #[test]
+    fn sqrt_product_handles_256_bit_products() {
+        assert_eq!(sqrt_product(3, 12), 6);
+        assert_eq!(sqrt_product(u128::MAX, u128::MAX), u128::MAX);
+        assert_eq!(sqrt_product(1 << 100, 1 << 100), 1 << 100);
+        assert_eq!(sqrt_product(u128::MAX, 4), integer_sqrt(u128::MAX) * 2 + 1);
+        let (a, b) = (MAX as u128 * 3_988_009, MAX as u128);
+        let root = sqrt_product(a, b);
+        assert!(widening_mul(root, root) <= widening_mul(a, b));
+        assert!(widening_mul(root + 1, root + 1) > widening_mul(a, b));
+    }
+
+    #[test]
+    fn mul_div_wide_uses_a_256_bit_product() {
+        assert_eq!(mul_div_wide(u128::MAX, u128::MAX, u128::MAX), Ok(u128::MAX));
+        assert_eq!(mul_div_wide(u128::MAX, 2, 4), Ok(u128::MAX / 2));
+        assert_eq!(mul_div_wide(1 << 100, 1 << 100, 1 << 90), Ok(1 << 110));
+        assert_eq!(mul_div_wide(7, 3, 2), Ok(10));
+        assert_eq!(mul_div_wide(u128::MAX, u128::MAX, 1), Err(MathError::Overflow));
+        assert_eq!(mul_div_wide(1, 1, 0), Err(MathError::ZeroReserves));
+    }
+
+    #[test]
+    fn exp_wad_matches_float_exp() {
+        assert_eq!(exp_wad(0), Ok(WAD));
+        for exponent in [WAD / 3, WAD, 5 * WAD / 2, 17 * WAD, MAX_EXPONENT_WAD] {
+            let expected = (exponent as f64 / WAD as f64).exp() * WAD as f64;
+            let actual = exp_wad(exponent).unwrap() as f64;
+            assert!((actual - expected).abs() / expected < 1e-12, "{exponent}: {actual} vs {expected}");
+        }
+    }
+
+    #[test]
+    fn twamm_single_stream_is_a_plain_swap() {
+        assert_eq!(twamm_amounts_out(10_000, 0, 1_000_000, 1_000_000), Ok((9_871, 0)));
+        assert_eq!(twamm_amounts_out(0, 10_000, 1_000_000, 2_000_000), Ok((0, 4_960)));
+        // Dust that buys nothing is absorbed instead of failing the pool interaction.
+        assert_eq!(twamm_amounts_out(1, 1, 1_000_000, 1_000_000), Ok((0, 0)));
+        assert_eq!(twamm_amounts_out(0, 0, 1_000_000, 1_000_000), Ok((0, 0)));
+    }
+
+    #[test]
+    fn twamm_balanced_streams_trade_at_spot() {
+        // Equal value both ways leaves the price alone; each side gets its input after fees.
+        assert_eq!(twamm_amounts_out(1_000, 2_000, 1_000_000, 2_000_000), Ok((1_994, 997)));
+    }
+
+    #[test]
+    fn twamm_matches_a_fine_grained_simulation() {
+        for (amount_a, amount_b, reserve_a, reserve_b) in [
+            (300_000u64, 50_000u64, 1_000_000u64, 2_000_000u64),
+            (5_000, 900_000, 1_000_000, 1_000_000),
+            (2_000_000, 2_000_000, 1_000_000, 3_000_000),
+        ] {
+            let (b_out, a_out) = twamm_amounts_out(amount_a, amount_b, reserve_a, reserve_b).unwrap();
+
+            // Trade both streams in many tiny, simultaneous, fee-free slices at the spot price.
+            let steps = 200_000;
+            let (mut x, mut y) = (reserve_a as f64, reserve_b as f64);
+            let (mut paid_b, mut paid_a) = (0.0, 0.0);
+            let slice_a = (amount_a * 997 / 1000) as f64 / steps as f64;
+            let slice_b = (amount_b * 997 / 1000) as f64 / steps as f64;
+            for _ in 0..steps {
+                let k = x * y;
+                let (to_a_sellers, to_b_sellers) = (y - k / (x + slice_a), x - k / (y + slice_b));
+                x += slice_a - to_b_sellers;
+                y += slice_b - to_a_sellers;
+                paid_b += to_a_sellers;
+                paid_a += to_b_sellers;
+            }
+            assert!((b_out as f64 - paid_b).abs() <= paid_b * 1e-3 + 2.0, "{b_out} vs {paid_b}");
+            assert!((a_out as f64 - paid_a).abs() <= paid_a * 1e-3 + 2.0, "{a_out} vs {paid_a}");
+
+            // Rounding never lets the fee-free product of the reserves shrink.
+            let final_a = (reserve_a + amount_a * 997 / 1000 - a_out) as u128;
+            let final_b = (reserve_b + amount_b * 997 / 1000 - b_out) as u128;
+            assert!(final_a * final_b >= reserve_a as u128 * reserve_b as u128);
+        }
+    }
+
+    #[test]
+    fn ln_wad_matches_float_ln() {
+        assert_eq!(ln_wad(WAD), 0);
+        for value in [WAD + 1, 3 * WAD / 2, E_WAD, 10 * WAD, 1_000_000 * WAD, MAX as u128 * WAD] {
+            let expected = (value as f64 / WAD as f64).ln() * WAD as f64;
+            let actual = ln_wad(value) as f64;
+            assert!((actual - expected).abs() <= expected * 1e-12 + 1e3, "{value}: {actual} vs {expected}");
+        }
+    }
+
+    #[test]
+    fn dutch_auction_price_decays_between_its_endpoints() {
+        let price = |now, exponential| dutch_auction_price(4_000, 1_000, 100, 200, now, exponential);
+        for exponential in [false, true] {
+            assert_eq!(price(0, exponential), Ok(4_000));
+            assert_eq!(price(100, exponential), Ok(4_000));
+            assert_eq!(price(200, exponential), Ok(1_000));
+            assert_eq!(price(i64::MAX, exponential), Ok(1_000));
+        }
+        assert_eq!(price(150, false), Ok(2_500));
+        assert_eq!(price(175, false), Ok(1_750));
+        // Halfway through, a geometric decay from 4_000 to 1_000 sits at sqrt(4_000 * 1_000).
+        // Rounding up may add one to the exact value.
+        let halfway = price(150, true).unwrap();
+        assert!((2_000..=2_001).contains(&halfway), "{halfway}");
+        let three_quarters = price(175, true).unwrap();
+        assert!((1_415..=1_416).contains(&three_quarters), "{three_quarters}");
+        // Rounding up never undercuts the line.
+        assert_eq!(dutch_auction_price(10, 0, 0, 3, 1, false), Ok(7));
+        assert_eq!(dutch_auction_price(10, 0, 0, 3, 1, true), Err(MathError::ZeroReserves));
+        assert_eq!(dutch_auction_price(MAX, 1, 0, i64::MAX, i64::MAX - 1, true), Ok(2));
+    }
+
+    #[test]
+    fn fills_take_the_remainder_or_at_least_the_minimum() {
+        // The whole remainder is always fillable, even below the minimum.
+        assert!(is_valid_fill(100, 100, false, 0));
+        assert!(is_valid_fill(5, 5, true, 10));
+        assert!(is_valid_fill(10, 100, true, 10));
+        assert!(!is_valid_fill(9, 100, true, 10));
+        assert!(!is_valid_fill(50, 100, false, 0));
+        assert!(!is_valid_fill(0, 100, true, 0));
+        assert!(!is_valid_fill(101, 100, true, 0));
+        assert!(!is_valid_fill(0, 0, true, 0));
+    }
+
+    #[test]
+    fn fixed_keeper_fee_shares_add_up_to_the_fee() {
+        let (fee, order_amount) = (25, 10_000);
+        let mut remaining = order_amount;
+        let mut fee_left = fee;
+        let mut shares = Vec::new();
+        for fill_amount in [3_333, 3_333, 3_334] {
+            let share = fixed_keeper_fee_share(fee, fee_left, fill_amount, remaining, order_amount).unwrap();
+            shares.push(share);
+            remaining -= fill_amount;
+            fee_left -= share;
+        }
+        assert_eq!(shares, [8, 8, 9]);
+        assert_eq!(fee_left, 0);
+        // A single fill of the whole order takes the whole fee.
+        assert_eq!(fixed_keeper_fee_share(fee, fee, order_amount, order_amount, order_amount), Ok(25));
+        assert_eq!(fixed_keeper_fee_share(MAX, MAX, MAX - 1, MAX, MAX), Ok(MAX - 1));
+    }
+
+    #[test]
+    fn output_keeper_fee_rounds_down() {
+        assert_eq!(output_keeper_fee(9_841, 50), Ok(49));
+        assert_eq!(output_keeper_fee(199, 50), Ok(0));
+        assert_eq!(output_keeper_fee(MAX, BPS_DENOMINATOR), Ok(MAX));
+    }
+}
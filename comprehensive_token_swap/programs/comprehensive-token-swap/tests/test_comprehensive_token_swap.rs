@@ -0,0 +1,2782 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::system_instruction;
+use anchor_lang::solana_program::sysvar;
+use anchor_lang::system_program;
+use anchor_lang::{InstructionData, ToAccountMetas};
+use comprehensive_token_swap::{
+    accounts, instruction, math, DcaOrder, DcaOrderParams, ErrorCode, FlashSwapCallback, KeeperFee, LimitOrder,
+    LimitOrderParams, LiquidityPool, LongTermOrder, OrderBook, OrderType, PriceDecay, SwapDirection, SwapMode,
+    UserOrderBook, DCA_SEED, ESCROW_SEED, FLASH_SWAP_CALLBACK_DISCRIMINATOR, LONG_TERM_ORDER_SEED, LP_MINT_SEED,
+    MINIMUM_LIQUIDITY, ORDER_BOOK_SEED, ORDER_SEED, PAUSE_ALL, PAUSE_DEPOSITS, PAUSE_FLASH_SWAPS, PAUSE_LIMIT_ORDERS,
+    PAUSE_SWAPS, PAUSE_WITHDRAWALS, POOL_SEED, TWAMM_INTERVAL, USER_ORDER_BOOK_SEED, VAULT_AUTHORITY_SEED, VAULT_SEED,
+};
+use solana_program::entrypoint::ProgramResult;
+use solana_program::instruction::{AccountMeta, Instruction};
+use solana_program::program::invoke;
+use solana_program::program_pack::Pack;
+use solana_program_test::*;
+use solana_sdk::signature::Keypair;
+use solana_sdk::signer::Signer;
+use solana_sdk::instruction::InstructionError;
+use solana_sdk::transaction::{Transaction, TransactionError};
+
+#[tokio::test]
+async fn test_add_liquidity() {
+    let mut fixture = setup_pool(program_test(), 10_000).await;
+
+    // The fixture's first deposit moves both amounts into the vaults and mints
+    // sqrt(10_000 * 10_000) LP tokens, of which MINIMUM_LIQUIDITY stay locked in the
+    // pool's supply and are never minted to anyone.
+    assert_eq!(fixture.token_balance(fixture.pool_token_a_account).await, 10_000);
+    assert_eq!(fixture.token_balance(fixture.pool_token_b_account).await, 10_000);
+    assert_eq!(fixture.token_balance(fixture.user_lp_account).await, 10_000 - MINIMUM_LIQUIDITY);
+    assert_eq!(fixture.lp_mint_supply().await, 10_000 - MINIMUM_LIQUIDITY);
+    assert_eq!(fixture.pool_state().await.lp_supply, 10_000);
+
+    // Later deposits mint their share of the supply: half the reserves, half the supply.
+    let add_liquidity_ix = fixture.add_liquidity_ix(5_000, 5_000, 5_000);
+    send(&mut fixture.context, &[add_liquidity_ix], &[]).await.unwrap();
+    let pool = fixture.pool_state().await;
+    assert_eq!((pool.token_a_reserve, pool.token_b_reserve, pool.lp_supply), (15_000, 15_000, 15_000));
+    assert_eq!(fixture.token_balance(fixture.pool_token_a_account).await, 15_000);
+    assert_eq!(fixture.token_balance(fixture.user_lp_account).await, 14_000);
+
+    // An unbalanced deposit only takes the A that matches its B; the excess A stays in
+    // the depositor's wallet, and asking for more LP than the matched part mints fails.
+    let user_a_before = fixture.token_balance(fixture.user_token_a_account).await;
+    let user_b_before = fixture.token_balance(fixture.user_token_b_account).await;
+    let add_liquidity_ix = fixture.add_liquidity_ix(6_000, 3_000, 3_001);
+    let result = send(&mut fixture.context, &[add_liquidity_ix], &[]).await;
+    assert_program_error(result, ErrorCode::SlippageExceeded);
+    let add_liquidity_ix = fixture.add_liquidity_ix(6_000, 3_000, 3_000);
+    send(&mut fixture.context, &[add_liquidity_ix], &[]).await.unwrap();
+    assert_eq!(fixture.token_balance(fixture.user_token_a_account).await, user_a_before - 3_000);
+    assert_eq!(fixture.token_balance(fixture.user_token_b_account).await, user_b_before - 3_000);
+    let pool = fixture.pool_state().await;
+    assert_eq!((pool.token_a_reserve, pool.token_b_reserve, pool.lp_supply), (18_000, 18_000, 18_000));
+    assert_eq!(fixture.token_balance(fixture.user_lp_account).await, 17_000);
+    assert_eq!(fixture.lp_mint_supply().await, pool.lp_supply - MINIMUM_LIQUIDITY);
+}
+
+#[tokio::test]
+async fn test_remove_liquidity() {
+    let mut fixture = setup_pool(program_test(), 10_000).await;
+    // A swap first leaves the reserves uneven, so each side is paid its own share.
+    let swap_ix = fixture.swap_ix(SwapDirection::AToB, 1_000, 0);
+    send(&mut fixture.context, &[swap_ix], &[]).await.unwrap();
+    let before = fixture.pool_state().await;
+    let user_a_before = fixture.token_balance(fixture.user_token_a_account).await;
+    let user_b_before = fixture.token_balance(fixture.user_token_b_account).await;
+
+    // Burning a quarter of the supply pays a quarter of each reserve, rounded down.
+    let amount_a = before.token_a_reserve * 2_500 / before.lp_supply;
+    let amount_b = before.token_b_reserve * 2_500 / before.lp_supply;
+    for (min_amount_a, min_amount_b) in [(amount_a + 1, 0), (0, amount_b + 1)] {
+        let remove_ix = fixture.remove_liquidity_ix(2_500, min_amount_a, min_amount_b);
+        assert_program_error(send(&mut fixture.context, &[remove_ix], &[]).await, ErrorCode::SlippageExceeded);
+    }
+    let remove_ix = fixture.remove_liquidity_ix(2_500, amount_a, amount_b);
+    send(&mut fixture.context, &[remove_ix], &[]).await.unwrap();
+
+    assert_eq!(fixture.token_balance(fixture.user_token_a_account).await, user_a_before + amount_a);
+    assert_eq!(fixture.token_balance(fixture.user_token_b_account).await, user_b_before + amount_b);
+    assert_eq!(fixture.token_balance(fixture.user_lp_account).await, 9_000 - 2_500);
+    let pool = fixture.pool_state().await;
+    assert_eq!(pool.token_a_reserve, before.token_a_reserve - amount_a);
+    assert_eq!(pool.token_b_reserve, before.token_b_reserve - amount_b);
+    assert_eq!(pool.lp_supply, 10_000 - 2_500);
+    assert_eq!(fixture.token_balance(fixture.pool_token_a_account).await, pool.token_a_reserve);
+    assert_eq!(fixture.lp_mint_supply().await, pool.lp_supply - MINIMUM_LIQUIDITY);
+}
+
+#[tokio::test]
+async fn test_pools_are_bound_to_their_swap() {
+    let mut fixture = setup_pool(program_test(), 10_000).await;
+    let outsider = Keypair::new();
+    let fund_ix = system_instruction::transfer(&fixture.user(), &outsider.pubkey(), 1_000_000_000);
+    send(&mut fixture.context, &[fund_ix], &[]).await.unwrap();
+    let mints = [fixture.token_a_mint, fixture.token_b_mint];
+
+    // Only the admin opens pools under a swap state.
+    let payer = fixture.context.payer.insecure_clone();
+    let token_c_mint = Keypair::new();
+    create_mint(&mut fixture.context.banks_client, &payer, &token_c_mint, &payer.pubkey()).await.unwrap();
+    let mut new_mints = [fixture.token_a_mint, token_c_mint.pubkey()];
+    new_mints.sort();
+    let (_, initialize_pool_ix) = fixture.initialize_pool_ix(outsider.pubkey(), fixture.swap, new_mints);
+    let result = send(&mut fixture.context, &[initialize_pool_ix], &[&outsider]).await;
+    assert_program_error(result, ErrorCode::Unauthorized);
+
+    // Under its own swap state the outsider gets a separate pool for the same pair.
+    let outsider_swap = Keypair::new();
+    let initialize_ix = fixture.initialize_ix(outsider.pubkey(), outsider_swap.pubkey(), 3);
+    let (pool, initialize_pool_ix) = fixture.initialize_pool_ix(outsider.pubkey(), outsider_swap.pubkey(), mints);
+    send(&mut fixture.context, &[initialize_ix, initialize_pool_ix], &[&outsider, &outsider_swap]).await.unwrap();
+    assert_ne!(pool, fixture.pool);
+    let account = fixture.context.banks_client.get_account(pool).await.unwrap().unwrap();
+    assert_eq!(LiquidityPool::try_deserialize(&mut account.data.as_slice()).unwrap().swap, outsider_swap.pubkey());
+
+    // Fee rates are thousandths of the output, so anything above 1000 is refused.
+    let swap = Keypair::new();
+    let initialize_ix = fixture.initialize_ix(fixture.user(), swap.pubkey(), 1_001);
+    assert_program_error(send(&mut fixture.context, &[initialize_ix], &[&swap]).await, ErrorCode::InvalidFeeRate);
+}
+
+#[tokio::test]
+async fn test_simple_swap() {
+    let mut fixture = setup_pool(program_test(), 10_000).await;
+
+    // 500 A into 10_000/10_000 yields 474 B gross; the 3/1000 fee, rounded up, leaves 472.
+    let simple_swap_ix = fixture.simple_swap_ix(500, 473);
+    assert!(send(&mut fixture.context, &[simple_swap_ix], &[]).await.is_err());
+    let simple_swap_ix = fixture.simple_swap_ix(500, 472);
+    send(&mut fixture.context, &[simple_swap_ix], &[]).await.unwrap();
+
+    assert_eq!(fixture.token_balance(fixture.pool_token_a_account).await, 10_500);
+    assert_eq!(fixture.token_balance(fixture.pool_token_b_account).await, 9_528);
+}
+
+#[tokio::test]
+async fn test_exact_out_swaps_in_both_directions() {
+    for direction in [SwapDirection::AToB, SwapDirection::BToA] {
+        let mut fixture = setup_pool(program_test(), 10_000).await;
+        let (user_in, user_out) = fixture.user_token_accounts(direction);
+        let (vault_in, vault_out) = fixture.pool_vaults(direction);
+        let user_in_before = fixture.token_balance(user_in).await;
+        let user_out_before = fixture.token_balance(user_out).await;
+
+        // 500 out is 502 gross of the 3/1000 fee, rounded up, and the curve needs 531 in
+        // to release 502; a maximum input of 530 is too tight.
+        let swap_ix = fixture.swap_with_mode_ix(direction, SwapMode::ExactOut, 500, 530);
+        assert_program_error(send(&mut fixture.context, &[swap_ix], &[]).await, ErrorCode::SlippageExceeded);
+        let swap_ix = fixture.swap_with_mode_ix(direction, SwapMode::ExactOut, 500, 531);
+        send(&mut fixture.context, &[swap_ix], &[]).await.unwrap();
+
+        assert_eq!(fixture.token_balance(user_out).await, user_out_before + 500);
+        assert_eq!(fixture.token_balance(user_in).await, user_in_before - 531);
+        // Only the net 500 leaves the pool; the 2 fee stays in the output reserve.
+        assert_eq!((fixture.token_balance(vault_in).await, fixture.token_balance(vault_out).await), (10_531, 9_500));
+        let pool = fixture.pool_state().await;
+        let reserves = match direction {
+            SwapDirection::AToB => (pool.token_a_reserve, pool.token_b_reserve),
+            SwapDirection::BToA => (pool.token_b_reserve, pool.token_a_reserve),
+        };
+        assert_eq!(reserves, (10_531, 9_500));
+    }
+}
+
+#[tokio::test]
+async fn test_unfunded_swap_cannot_trip_the_breaker() {
+    let mut fixture = setup_pool(program_test(), 10_000).await;
+    let configure_ix = fixture.configure_circuit_breaker_ix(fixture.pool, 100, 0, 0, true);
+    send(&mut fixture.context, &[configure_ix], &[]).await.unwrap();
+
+    // The keeper holds no token A, so its swap fails before the breaker sees it.
+    let keeper = fixture.keeper.insecure_clone();
+    let mut swap_ix = fixture.swap_ix(SwapDirection::AToB, 5_000, 0);
+    swap_ix.accounts = accounts::SimpleSwapCtx {
+        user: keeper.pubkey(),
+        pool: fixture.pool,
+        swap: fixture.swap,
+        pool_authority: fixture.pool_authority,
+        user_token_a_account: fixture.keeper_token_a_account,
+        user_token_b_account: fixture.keeper_token_b_account,
+        pool_token_a_account: fixture.pool_token_a_account,
+        pool_token_b_account: fixture.pool_token_b_account,
+        token_program: spl_token::id(),
+    }
+    .to_account_metas(None);
+    assert!(send(&mut fixture.context, &[swap_ix], &[&keeper]).await.is_err());
+    assert!(!fixture.pool_state().await.breaker_tripped);
+
+    // A funded swap of the same size trips it, pauses the pool and gets its input back.
+    let user_a_before = fixture.token_balance(fixture.user_token_a_account).await;
+    let swap_ix = fixture.swap_ix(SwapDirection::AToB, 5_000, 0);
+    send(&mut fixture.context, &[swap_ix], &[]).await.unwrap();
+    let pool = fixture.pool_state().await;
+    assert!(pool.breaker_tripped);
+    assert_eq!((pool.token_a_reserve, pool.token_b_reserve), (10_000, 10_000));
+    assert_eq!(fixture.token_balance(fixture.user_token_a_account).await, user_a_before);
+    assert_eq!(fixture.token_balance(fixture.pool_token_a_account).await, 10_000);
+
+    let swap_ix = fixture.swap_ix(SwapDirection::AToB, 10, 0);
+    assert_program_error(send(&mut fixture.context, &[swap_ix], &[]).await, ErrorCode::CircuitBreakerActive);
+}
+
+#[tokio::test]
+async fn test_tripped_route_refunds_its_input() {
+    let mut fixture = setup_pool(program_test(), 1_000_000).await;
+    let route_pool = fixture.setup_route_pool(1_000_000).await;
+    let configure_ix = fixture.configure_circuit_breaker_ix(route_pool.pool, 100, 0, 0, true);
+    send(&mut fixture.context, &[configure_ix], &[]).await.unwrap();
+    let hops = [
+        (fixture.pool, fixture.pool_authority, fixture.pool_token_a_account, fixture.pool_token_b_account),
+        (route_pool.pool, route_pool.pool_authority, route_pool.pool_token_b_account, route_pool.pool_token_c_account),
+    ];
+
+    // Selling 9_841 B moves the B/C price by about 2%, so the second hop trips.
+    let user_a_before = fixture.token_balance(fixture.user_token_a_account).await;
+    let user_c_before = fixture.token_balance(route_pool.user_token_c_account).await;
+    let swap_ix = fixture.multi_token_swap_ix(fixture.user_token_a_account, route_pool.user_token_c_account, 10_000, 1, &hops);
+    send(&mut fixture.context, &[swap_ix], &[]).await.unwrap();
+
+    assert_eq!(fixture.token_balance(fixture.user_token_a_account).await, user_a_before);
+    assert_eq!(fixture.token_balance(route_pool.user_token_c_account).await, user_c_before);
+    assert_eq!(fixture.token_balance(fixture.pool_token_a_account).await, 1_000_000);
+    let pool = fixture.pool_state().await;
+    assert_eq!((pool.token_a_reserve, pool.token_b_reserve), (1_000_000, 1_000_000));
+    assert!(!pool.breaker_tripped);
+    let account = fixture.context.banks_client.get_account(route_pool.pool).await.unwrap().unwrap();
+    assert!(LiquidityPool::try_deserialize(&mut account.data.as_slice()).unwrap().breaker_tripped);
+}
+
+#[tokio::test]
+async fn test_single_sided_liquidity_swaps_through_the_breaker() {
+    let mut fixture = setup_pool(program_test(), 10_000).await;
+    let configure_ix = fixture.configure_circuit_breaker_ix(fixture.pool, 100, 0, 0, true);
+    send(&mut fixture.context, &[configure_ix], &[]).await.unwrap();
+    let user_a_before = fixture.token_balance(fixture.user_token_a_account).await;
+    let user_b_before = fixture.token_balance(fixture.user_token_b_account).await;
+    let user_lp_before = fixture.token_balance(fixture.user_lp_account).await;
+
+    // Swapping the 1_000 A of a 10% withdrawal back into the rest of the pool moves the
+    // price by about 19%: the burned LP tokens are handed back and nothing is paid out.
+    let remove_ix = fixture.remove_liquidity_single_ix(SwapDirection::AToB, 1_000, 0);
+    send(&mut fixture.context, &[remove_ix], &[]).await.unwrap();
+    assert_eq!(fixture.token_balance(fixture.user_lp_account).await, user_lp_before);
+    assert_eq!(fixture.token_balance(fixture.user_token_b_account).await, user_b_before);
+    let pool = fixture.pool_state().await;
+    assert!(pool.breaker_tripped);
+    assert_eq!((pool.token_a_reserve, pool.token_b_reserve, pool.lp_supply), (10_000, 10_000, 10_000));
+
+    // A tripped pool takes no single-sided deposits either.
+    let add_ix = fixture.add_liquidity_single_ix(SwapDirection::AToB, 10, 0);
+    assert_program_error(send(&mut fixture.context, &[add_ix], &[]).await, ErrorCode::CircuitBreakerActive);
+
+    let reset_ix = fixture.reset_circuit_breaker_ix();
+    send(&mut fixture.context, &[reset_ix], &[]).await.unwrap();
+    // The swap leg of a 2_000 A deposit moves the price by far more than 1%, so the
+    // deposit is refunded instead.
+    let add_ix = fixture.add_liquidity_single_ix(SwapDirection::AToB, 2_000, 0);
+    send(&mut fixture.context, &[add_ix], &[]).await.unwrap();
+    assert_eq!(fixture.token_balance(fixture.user_token_a_account).await, user_a_before);
+    assert_eq!(fixture.token_balance(fixture.user_lp_account).await, user_lp_before);
+    assert_eq!(fixture.token_balance(fixture.pool_token_a_account).await, 10_000);
+    let pool = fixture.pool_state().await;
+    assert!(pool.breaker_tripped);
+    assert_eq!((pool.token_a_reserve, pool.token_b_reserve, pool.lp_supply), (10_000, 10_000, 10_000));
+}
+
+#[tokio::test]
+async fn test_breaker_trips_on_the_move_across_its_window() {
+    let mut fixture = setup_pool(program_test(), 1_000_000).await;
+    let configure_ix = fixture.configure_circuit_breaker_ix(fixture.pool, 0, 300, 1_000, true);
+    send(&mut fixture.context, &[configure_ix], &[]).await.unwrap();
+
+    // Each 10_000 A swap moves the price by about 2%: the first stays within the 3%
+    // window, the second takes the window past it and is refunded.
+    let swap_ix = fixture.swap_ix(SwapDirection::AToB, 10_000, 0);
+    send(&mut fixture.context, &[swap_ix], &[]).await.unwrap();
+    let after_first = fixture.pool_state().await;
+    assert!(!after_first.breaker_tripped);
+    assert_eq!(after_first.token_a_reserve, 1_010_000);
+    assert_eq!(after_first.reference_price, Some(math::price_q64(1_000_000, 1_000_000).unwrap()));
+
+    let user_a_before = fixture.token_balance(fixture.user_token_a_account).await;
+    refresh_blockhash(&mut fixture.context).await;
+    let swap_ix = fixture.swap_ix(SwapDirection::AToB, 10_000, 0);
+    send(&mut fixture.context, &[swap_ix], &[]).await.unwrap();
+    let pool = fixture.pool_state().await;
+    assert!(pool.breaker_tripped);
+    let first_reserves = (after_first.token_a_reserve, after_first.token_b_reserve);
+    assert_eq!((pool.token_a_reserve, pool.token_b_reserve), first_reserves);
+    assert_eq!(fixture.token_balance(fixture.user_token_a_account).await, user_a_before);
+}
+
+#[tokio::test]
+async fn test_breaker_window_rolls_over_after_its_slots() {
+    let mut fixture = setup_pool(program_test(), 1_000_000).await;
+    let configure_ix = fixture.configure_circuit_breaker_ix(fixture.pool, 0, 300, 10, true);
+    send(&mut fixture.context, &[configure_ix], &[]).await.unwrap();
+    let swap_ix = fixture.swap_ix(SwapDirection::AToB, 10_000, 0);
+    send(&mut fixture.context, &[swap_ix], &[]).await.unwrap();
+    let after_first = fixture.pool_state().await;
+
+    // Once the window's slots have passed, the next swap opens a new window at its own
+    // starting price, so the two 2% moves are never added up.
+    let slot = fixture.context.banks_client.get_sysvar::<Clock>().await.unwrap().slot;
+    fixture.context.warp_to_slot(slot + 20).unwrap();
+    let swap_ix = fixture.swap_ix(SwapDirection::AToB, 10_000, 0);
+    send(&mut fixture.context, &[swap_ix], &[]).await.unwrap();
+    let pool = fixture.pool_state().await;
+    assert!(!pool.breaker_tripped);
+    assert!(pool.token_a_reserve > after_first.token_a_reserve);
+    assert!(pool.window_start_slot >= slot + 20);
+    let reference_price = math::price_q64(after_first.token_a_reserve, after_first.token_b_reserve).unwrap();
+    assert_eq!(pool.reference_price, Some(reference_price));
+}
+
+#[tokio::test]
+async fn test_breaker_without_auto_pause_rejects_large_moves() {
+    let mut fixture = setup_pool(program_test(), 1_000_000).await;
+    let configure_ix = fixture.configure_circuit_breaker_ix(fixture.pool, 100, 0, 0, false);
+    send(&mut fixture.context, &[configure_ix], &[]).await.unwrap();
+
+    // A 10% swap fails outright and leaves the pool open; small swaps still go through.
+    let swap_ix = fixture.swap_ix(SwapDirection::AToB, 100_000, 0);
+    assert_program_error(send(&mut fixture.context, &[swap_ix], &[]).await, ErrorCode::PriceMoveTooLarge);
+    let pool = fixture.pool_state().await;
+    assert!(!pool.breaker_tripped);
+    assert_eq!((pool.token_a_reserve, pool.token_b_reserve), (1_000_000, 1_000_000));
+
+    let swap_ix = fixture.swap_ix(SwapDirection::AToB, 1_000, 0);
+    send(&mut fixture.context, &[swap_ix], &[]).await.unwrap();
+    assert_eq!(fixture.pool_state().await.token_a_reserve, 1_001_000);
+}
+
+#[tokio::test]
+async fn test_single_sided_withdrawal_keeps_dust_that_buys_nothing() {
+    let mut fixture = setup_pool(program_test(), 1_000_000).await;
+    let user_b_before = fixture.token_balance(fixture.user_token_b_account).await;
+
+    // One LP token withdraws 1 A and 1 B; the 1 A buys nothing, so only the B is paid.
+    let remove_ix = fixture.remove_liquidity_single_ix(SwapDirection::AToB, 1, 1);
+    send(&mut fixture.context, &[remove_ix], &[]).await.unwrap();
+    assert_eq!(fixture.token_balance(fixture.user_token_b_account).await, user_b_before + 1);
+    let pool = fixture.pool_state().await;
+    assert_eq!((pool.token_a_reserve, pool.token_b_reserve, pool.lp_supply), (1_000_000, 999_999, 999_999));
+}
+
+#[tokio::test]
+async fn test_single_sided_deposit_pays_the_protocol_fee() {
+    let mut fixture = setup_pool(program_test(), 1_000_000).await;
+    let user_lp_before = fixture.token_balance(fixture.user_lp_account).await;
+
+    // The swap leg pays the 0.3% curve fee and the 0.3% protocol fee, both of which stay
+    // in the pool, so the deposit mints a little less than the 48_809 of a fee-free zap.
+    let add_ix = fixture.add_liquidity_single_ix(SwapDirection::AToB, 100_000, 0);
+    send(&mut fixture.context, &[add_ix], &[]).await.unwrap();
+    let pool = fixture.pool_state().await;
+    let lp_out = fixture.token_balance(fixture.user_lp_account).await - user_lp_before;
+    assert_eq!((pool.token_a_reserve, pool.token_b_reserve), (1_100_000, 1_000_000));
+    assert_eq!(pool.lp_supply, 1_000_000 + lp_out);
+    assert!(lp_out < 48_809 && lp_out > 48_500, "{lp_out}");
+}
+
+#[tokio::test]
+async fn test_each_pause_flag_halts_only_its_operations() {
+    let mut fixture = setup_pool(program_test(), 1_000_000).await;
+    for operations in [PAUSE_SWAPS, PAUSE_DEPOSITS, PAUSE_WITHDRAWALS, PAUSE_LIMIT_ORDERS, PAUSE_FLASH_SWAPS] {
+        // Pausing every other group leaves this one open.
+        let pause_ix = fixture.pause_ix(PAUSE_ALL & !operations);
+        send(&mut fixture.context, &[pause_ix], &[]).await.unwrap();
+        let order = fixture.next_order().await;
+        let instructions = fixture.paused_operation_ixs(operations, order, 1_000);
+        send(&mut fixture.context, &instructions, &[]).await.unwrap();
+        let unpause_ix = fixture.unpause_ix(PAUSE_ALL & !operations);
+        send(&mut fixture.context, &[unpause_ix], &[]).await.unwrap();
+
+        let pause_ix = fixture.pause_ix(operations);
+        send(&mut fixture.context, &[pause_ix], &[]).await.unwrap();
+        let order = fixture.next_order().await;
+        let instructions = fixture.paused_operation_ixs(operations, order, 2_000);
+        assert_program_error(send(&mut fixture.context, &instructions, &[]).await, ErrorCode::ProgramPaused);
+        let unpause_ix = fixture.unpause_ix(operations);
+        send(&mut fixture.context, &[unpause_ix], &[]).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_flash_swap_repaid_with_fee() {
+    let borrower = Pubkey::new_unique();
+    let mut pt = program_test();
+    pt.add_program("repaying_borrower", borrower, processor!(repaying_borrower));
+    let mut fixture = setup_pool(pt, 1_000_000).await;
+    let pool_before = fixture.pool_state().await;
+
+    let callback_accounts = fixture.repayment_accounts(borrower);
+    let flash_swap_ix = fixture.flash_swap_ix(100_000, borrower, callback_accounts);
+    send(&mut fixture.context, &[flash_swap_ix], &[]).await.unwrap();
+
+    // 9 bps of 100_000, rounded up, stays in the pool.
+    let pool_after = fixture.pool_state().await;
+    assert!(!pool_after.locked);
+    assert_eq!(pool_after.token_a_reserve, pool_before.token_a_reserve + 90);
+    assert_eq!(pool_after.token_b_reserve, pool_before.token_b_reserve);
+}
+
+#[tokio::test]
+async fn test_flash_swap_fails_without_fee() {
+    let borrower = Pubkey::new_unique();
+    let mut pt = program_test();
+    pt.add_program("principal_only_borrower", borrower, processor!(principal_only_borrower));
+    let mut fixture = setup_pool(pt, 1_000_000).await;
+    let pool_before = fixture.pool_state().await;
+
+    let callback_accounts = fixture.repayment_accounts(borrower);
+    let flash_swap_ix = fixture.flash_swap_ix(100_000, borrower, callback_accounts);
+    assert!(send(&mut fixture.context, &[flash_swap_ix], &[]).await.is_err());
+
+    let pool_after = fixture.pool_state().await;
+    assert!(!pool_after.locked);
+    assert_eq!(pool_after.token_a_reserve, pool_before.token_a_reserve);
+}
+
+#[tokio::test]
+async fn test_flash_borrow_and_repay_in_one_transaction() {
+    let mut fixture = setup_pool(program_test(), 1_000_000).await;
+    let pool_before = fixture.pool_state().await;
+
+    // Arbitrary instructions can sit between the pair; a plain token transfer stands in here.
+    let filler_ix = spl_token::instruction::transfer(
+        &spl_token::id(),
+        &fixture.user_token_b_account,
+        &fixture.user_token_b_account,
+        &fixture.user(),
+        &[],
+        1,
+    )
+    .unwrap();
+    let instructions = [fixture.flash_borrow_ix(100_000), filler_ix, fixture.flash_repay_ix(100_000)];
+    send(&mut fixture.context, &instructions, &[]).await.unwrap();
+
+    let pool_after = fixture.pool_state().await;
+    assert!(!pool_after.locked);
+    assert_eq!(pool_after.flash_loan_amount, 0);
+    assert_eq!(pool_after.token_a_reserve, pool_before.token_a_reserve + 90);
+}
+
+#[tokio::test]
+async fn test_flash_borrow_requires_matching_repay() {
+    let mut fixture = setup_pool(program_test(), 1_000_000).await;
+
+    let borrow_only = [fixture.flash_borrow_ix(100_000)];
+    assert!(send(&mut fixture.context, &borrow_only, &[]).await.is_err());
+
+    let wrong_amount = [fixture.flash_borrow_ix(100_000), fixture.flash_repay_ix(50_000)];
+    assert!(send(&mut fixture.context, &wrong_amount, &[]).await.is_err());
+
+    // A repay placed before the borrow does not count.
+    let repay_first = [fixture.flash_repay_ix(100_000), fixture.flash_borrow_ix(100_000)];
+    assert!(send(&mut fixture.context, &repay_first, &[]).await.is_err());
+
+    assert!(!fixture.pool_state().await.locked);
+}
+
+#[tokio::test]
+async fn test_flash_fee_accrues_to_reserves_and_totals() {
+    let mut fixture = setup_pool(program_test(), 1_000_000).await;
+
+    let too_high = [fixture.set_flash_fee_ix(fixture.user(), 1_001)];
+    assert!(send(&mut fixture.context, &too_high, &[]).await.is_err());
+
+    let outsider = Keypair::new();
+    let not_admin = [fixture.set_flash_fee_ix(outsider.pubkey(), 30)];
+    assert!(send(&mut fixture.context, &not_admin, &[&outsider]).await.is_err());
+
+    let set_fee = [fixture.set_flash_fee_ix(fixture.user(), 30)];
+    send(&mut fixture.context, &set_fee, &[]).await.unwrap();
+    let pool_before = fixture.pool_state().await;
+    assert_eq!(pool_before.flash_fee_bps, 30);
+
+    let loans = [fixture.flash_borrow_ix(100_000), fixture.flash_repay_ix(100_000)];
+    send(&mut fixture.context, &loans, &[]).await.unwrap();
+    let loans = [fixture.flash_borrow_ix(33_333), fixture.flash_repay_ix(33_333)];
+    send(&mut fixture.context, &loans, &[]).await.unwrap();
+
+    // 30 bps of 100_000 is 300; of 33_333 it is 99.999, rounded up to 100.
+    let pool_after = fixture.pool_state().await;
+    assert_eq!(pool_after.token_a_reserve, pool_before.token_a_reserve + 400);
+    assert_eq!(pool_after.total_flash_volume, 133_333);
+    assert_eq!(pool_after.total_flash_fees, 400);
+}
+
+// The lock is what keeps the pool closed in the window a flash_borrow leaves open until
+// its flash_repay.
+#[tokio::test]
+async fn test_locked_pool_refuses_everything_until_repaid() {
+    let borrower = Pubkey::new_unique();
+    let mut pt = program_test();
+    pt.add_program("repaying_borrower", borrower, processor!(repaying_borrower));
+    let mut fixture = setup_pool(pt, 1_000_000).await;
+    let pool_before = fixture.pool_state().await;
+
+    let callback_accounts = fixture.repayment_accounts(borrower);
+    for reentry_ix in [
+        fixture.swap_ix(SwapDirection::AToB, 10_000, 1),
+        fixture.add_liquidity_ix(1_000, 1_000, 0),
+        fixture.flash_swap_ix(1_000, borrower, callback_accounts),
+        fixture.flash_borrow_ix(1_000),
+    ] {
+        let instructions = [fixture.flash_borrow_ix(100_000), reentry_ix, fixture.flash_repay_ix(100_000)];
+        let result = send(&mut fixture.context, &instructions, &[]).await;
+        assert_program_error(result, ErrorCode::PoolLocked);
+    }
+
+    let pool_after = fixture.pool_state().await;
+    assert!(!pool_after.locked);
+    assert_eq!(pool_after.token_a_reserve, pool_before.token_a_reserve);
+    assert_eq!(pool_after.token_b_reserve, pool_before.token_b_reserve);
+}
+
+#[tokio::test]
+async fn test_flash_swap_callback_cannot_reenter_the_pool() {
+    let borrower_ids = [Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique()];
+    let mut pt = program_test();
+    pt.add_program("reentrant_swap_borrower", borrower_ids[0], processor!(reentrant_swap_borrower));
+    pt.add_program("reentrant_deposit_borrower", borrower_ids[1], processor!(reentrant_deposit_borrower));
+    pt.add_program("reentrant_flash_borrower", borrower_ids[2], processor!(reentrant_flash_borrower));
+    let mut fixture = setup_pool(pt, 1_000_000).await;
+    let pool_before = fixture.pool_state().await;
+    let vault_a_before = fixture.token_balance(fixture.pool_token_a_account).await;
+
+    let reentry_accounts = [
+        fixture.swap_ix(SwapDirection::AToB, 10, 0).accounts,
+        fixture.add_liquidity_ix(10, 10, 0).accounts,
+        fixture.flash_swap_ix(1_000, borrower_ids[2], vec![]).accounts,
+    ];
+    for (borrower, accounts) in borrower_ids.into_iter().zip(reentry_accounts) {
+        // The callback receives itself, the swap program and then every account the
+        // instruction it re-enters with needs.
+        let mut callback_accounts = vec![
+            AccountMeta::new_readonly(borrower, false),
+            AccountMeta::new_readonly(comprehensive_token_swap::id(), false),
+        ];
+        callback_accounts.extend(accounts);
+        let flash_swap_ix = fixture.flash_swap_ix(1_000, borrower, callback_accounts);
+        assert!(send(&mut fixture.context, &[flash_swap_ix], &[]).await.is_err());
+
+        let pool_after = fixture.pool_state().await;
+        assert!(!pool_after.locked);
+        assert_eq!(pool_after.token_a_reserve, pool_before.token_a_reserve);
+        assert_eq!(pool_after.token_b_reserve, pool_before.token_b_reserve);
+        assert_eq!(pool_after.lp_supply, pool_before.lp_supply);
+        assert_eq!(fixture.token_balance(fixture.pool_token_a_account).await, vault_a_before);
+    }
+
+    // The failed flash swaps must not leave the pool locked for everyone else.
+    let swap_ix = fixture.swap_ix(SwapDirection::AToB, 10_000, 1);
+    send(&mut fixture.context, &[swap_ix], &[]).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_limit_order_escrows_input_and_pays_destination() {
+    let mut fixture = setup_pool(program_test(), 1_000_000).await;
+    let order = fixture.next_order().await;
+    let escrow = fixture.escrow(order);
+    let user_a_before = fixture.token_balance(fixture.user_token_a_account).await;
+
+    // 10_000 into 1_000_000/1_000_000 yields 9_871 gross; the 3/1000 fee leaves 9_841,
+    // which exactly meets a limit of 0.9841 B per A.
+    let place_ix = fixture.place_limit_order_ix(order, limit_order(10_000, 984_100_000));
+    send(&mut fixture.context, &[place_ix], &[]).await.unwrap();
+    assert_eq!(fixture.token_balance(escrow).await, 10_000);
+    assert_eq!(fixture.token_balance(fixture.user_token_a_account).await, user_a_before - 10_000);
+
+    let pool_before = fixture.pool_state().await;
+    let user_b_before = fixture.token_balance(fixture.user_token_b_account).await;
+    let execute_ix = fixture.execute_limit_order_ix(order, 10_000, fixture.keeper_token_a_account);
+    send(&mut fixture.context, &[execute_ix], &[&fixture.keeper]).await.unwrap();
+
+    // A fully filled order is closed along with its escrow.
+    assert!(fixture.context.banks_client.get_account(escrow).await.unwrap().is_none());
+    assert_eq!(fixture.token_balance(fixture.user_token_b_account).await, user_b_before + 9_841);
+    let pool_after = fixture.pool_state().await;
+    assert_eq!(pool_after.token_a_reserve, pool_before.token_a_reserve + 10_000);
+    assert_eq!(pool_after.token_b_reserve, pool_before.token_b_reserve - 9_841);
+}
+
+#[tokio::test]
+async fn test_limit_order_refuses_fill_below_limit() {
+    let mut fixture = setup_pool(program_test(), 1_000_000).await;
+    let order = fixture.next_order().await;
+
+    // One unit past what the pool can pay for 10_000 A.
+    let place_ix = fixture.place_limit_order_ix(order, limit_order(10_000, 984_100_001));
+    send(&mut fixture.context, &[place_ix], &[]).await.unwrap();
+    let pool_before = fixture.pool_state().await;
+
+    let execute_ix = fixture.execute_limit_order_ix(order, 10_000, fixture.keeper_token_a_account);
+    assert!(send(&mut fixture.context, &[execute_ix], &[&fixture.keeper]).await.is_err());
+
+    let escrow = fixture.escrow(order);
+    assert_eq!(fixture.token_balance(escrow).await, 10_000);
+    assert_eq!(fixture.pool_state().await.token_b_reserve, pool_before.token_b_reserve);
+}
+
+#[tokio::test]
+async fn test_keeper_collects_fixed_fee_from_escrow() {
+    let mut fixture = setup_pool(program_test(), 1_000_000).await;
+    let order = fixture.next_order().await;
+    let params = LimitOrderParams {
+        keeper_fee: KeeperFee::Fixed { amount: 25 },
+        ..limit_order(10_000, 984_100_000)
+    };
+
+    let place_ix = fixture.place_limit_order_ix(order, params);
+    send(&mut fixture.context, &[place_ix], &[]).await.unwrap();
+    assert_eq!(fixture.token_balance(fixture.escrow(order)).await, 10_025);
+
+    // The fee is paid in the input token, so a token B keeper account is refused.
+    let execute_ix = fixture.execute_limit_order_ix(order, 10_000, fixture.keeper_token_b_account);
+    assert!(send(&mut fixture.context, &[execute_ix], &[&fixture.keeper]).await.is_err());
+
+    let user_b_before = fixture.token_balance(fixture.user_token_b_account).await;
+    let execute_ix = fixture.execute_limit_order_ix(order, 10_000, fixture.keeper_token_a_account);
+    send(&mut fixture.context, &[execute_ix], &[&fixture.keeper]).await.unwrap();
+
+    assert_eq!(fixture.token_balance(fixture.keeper_token_a_account).await, 25);
+    assert!(fixture.context.banks_client.get_account(order).await.unwrap().is_none());
+    assert_eq!(fixture.token_balance(fixture.user_token_b_account).await, user_b_before + 9_841);
+}
+
+#[tokio::test]
+async fn test_keeper_collects_bps_of_output() {
+    let mut fixture = setup_pool(program_test(), 1_000_000).await;
+    let order = fixture.next_order().await;
+    let params = LimitOrderParams {
+        keeper_fee: KeeperFee::OutputBps { bps: 50 },
+        ..limit_order(10_000, 950_000_000)
+    };
+
+    let place_ix = fixture.place_limit_order_ix(order, params);
+    send(&mut fixture.context, &[place_ix], &[]).await.unwrap();
+
+    let user_b_before = fixture.token_balance(fixture.user_token_b_account).await;
+    let pool_before = fixture.pool_state().await;
+    let execute_ix = fixture.execute_limit_order_ix(order, 10_000, fixture.keeper_token_b_account);
+    send(&mut fixture.context, &[execute_ix], &[&fixture.keeper]).await.unwrap();
+
+    // 50 bps of the 9_841 output, rounded down, goes to the keeper; the owner gets the rest.
+    assert_eq!(fixture.token_balance(fixture.keeper_token_b_account).await, 49);
+    assert_eq!(fixture.token_balance(fixture.user_token_b_account).await, user_b_before + 9_792);
+    assert_eq!(fixture.pool_state().await.token_b_reserve, pool_before.token_b_reserve - 9_841);
+}
+
+#[tokio::test]
+async fn test_limit_order_partial_fills() {
+    let mut fixture = setup_pool(program_test(), 1_000_000).await;
+    let order = fixture.next_order().await;
+    let escrow = fixture.escrow(order);
+    let params = LimitOrderParams {
+        partial_fill: true,
+        min_fill_amount: 5_000,
+        keeper_fee: KeeperFee::Fixed { amount: 30 },
+        ..limit_order(30_000, 900_000_000)
+    };
+    let place_ix = fixture.place_limit_order_ix(order, params);
+    send(&mut fixture.context, &[place_ix], &[]).await.unwrap();
+    let user_b_before = fixture.token_balance(fixture.user_token_b_account).await;
+
+    let below_minimum = fixture.execute_limit_order_ix(order, 4_999, fixture.keeper_token_a_account);
+    assert!(send(&mut fixture.context, &[below_minimum], &[&fixture.keeper]).await.is_err());
+
+    // Each fill pays its pro-rata share of the fixed keeper fee.
+    let mut keeper_paid = 0;
+    for (sequence, fill_amount, keeper_fee) in [(1, 10_000, 10), (2, 15_000, 15)] {
+        let execute_ix = fixture.execute_limit_order_ix(order, fill_amount, fixture.keeper_token_a_account);
+        send(&mut fixture.context, &[execute_ix], &[&fixture.keeper]).await.unwrap();
+        keeper_paid += keeper_fee;
+
+        let state = fixture.order_state(order).await;
+        assert_eq!(state.fill_count, sequence);
+        assert_eq!(state.filled_amount + state.remaining_amount, 30_000);
+        assert_eq!(fixture.token_balance(fixture.keeper_token_a_account).await, keeper_paid);
+        assert_eq!(fixture.token_balance(escrow).await, state.remaining_amount + 30 - keeper_paid);
+    }
+    let state = fixture.order_state(order).await;
+    assert_eq!(state.filled_amount, 25_000);
+    assert_eq!(state.remaining_amount, 5_000);
+    assert_eq!(fixture.token_balance(fixture.user_token_b_account).await, user_b_before + state.received_amount);
+
+    let overfill = fixture.execute_limit_order_ix(order, 5_001, fixture.keeper_token_a_account);
+    assert!(send(&mut fixture.context, &[overfill], &[&fixture.keeper]).await.is_err());
+
+    // The final fill completes the order, pays the rest of the fee and closes everything.
+    let execute_ix = fixture.execute_limit_order_ix(order, 5_000, fixture.keeper_token_a_account);
+    send(&mut fixture.context, &[execute_ix], &[&fixture.keeper]).await.unwrap();
+    assert_eq!(fixture.token_balance(fixture.keeper_token_a_account).await, 30);
+    for closed in [order, escrow] {
+        assert!(fixture.context.banks_client.get_account(closed).await.unwrap().is_none());
+    }
+}
+
+#[tokio::test]
+async fn test_all_or_nothing_order_rejects_partial_fill() {
+    let mut fixture = setup_pool(program_test(), 1_000_000).await;
+    let order = fixture.next_order().await;
+    let place_ix = fixture.place_limit_order_ix(order, limit_order(10_000, 900_000_000));
+    send(&mut fixture.context, &[place_ix], &[]).await.unwrap();
+
+    let execute_ix = fixture.execute_limit_order_ix(order, 9_999, fixture.keeper_token_a_account);
+    assert!(send(&mut fixture.context, &[execute_ix], &[&fixture.keeper]).await.is_err());
+    assert_eq!(fixture.order_state(order).await.fill_count, 0);
+}
+
+#[tokio::test]
+async fn test_stop_loss_and_take_profit_wait_for_trigger() {
+    let mut fixture = setup_pool(program_test(), 1_000_000).await;
+
+    // Both sell A from a 1:1 pool with a worst-case limit of 0.9 B per A.
+    let stop_loss = fixture.next_order().await;
+    let params = LimitOrderParams {
+        order_type: OrderType::StopLoss,
+        trigger_price: 990_000_000,
+        ..limit_order(1_000, 900_000_000)
+    };
+    let place_ix = fixture.place_limit_order_ix(stop_loss, params);
+    send(&mut fixture.context, &[place_ix], &[]).await.unwrap();
+
+    let take_profit = fixture.next_order().await;
+    let params = LimitOrderParams {
+        order_type: OrderType::TakeProfit,
+        trigger_price: 1_010_000_000,
+        ..limit_order(1_000, 900_000_000)
+    };
+    let place_ix = fixture.place_limit_order_ix(take_profit, params);
+    send(&mut fixture.context, &[place_ix], &[]).await.unwrap();
+
+    for order in [stop_loss, take_profit] {
+        let execute_ix = fixture.execute_limit_order_ix(order, 1_000, fixture.keeper_token_a_account);
+        assert!(send(&mut fixture.context, &[execute_ix], &[&fixture.keeper]).await.is_err());
+    }
+
+    // Selling A drops the price to about 0.98 B per A, arming the stop-loss only.
+    let swap_ix = fixture.swap_ix(SwapDirection::AToB, 10_000, 1);
+    send(&mut fixture.context, &[swap_ix], &[]).await.unwrap();
+    let execute_ix = fixture.execute_limit_order_ix(take_profit, 1_000, fixture.keeper_token_a_account);
+    assert!(send(&mut fixture.context, &[execute_ix], &[&fixture.keeper]).await.is_err());
+    let execute_ix = fixture.execute_limit_order_ix(stop_loss, 1_000, fixture.keeper_token_a_account);
+    send(&mut fixture.context, &[execute_ix], &[&fixture.keeper]).await.unwrap();
+
+    // Buying A back past 1.01 B per A arms the take-profit.
+    let swap_ix = fixture.swap_ix(SwapDirection::BToA, 30_000, 1);
+    send(&mut fixture.context, &[swap_ix], &[]).await.unwrap();
+    // The same execution was refused twice above; a fresh blockhash keeps the status
+    // cache from answering it again.
+    refresh_blockhash(&mut fixture.context).await;
+    let execute_ix = fixture.execute_limit_order_ix(take_profit, 1_000, fixture.keeper_token_a_account);
+    send(&mut fixture.context, &[execute_ix], &[&fixture.keeper]).await.unwrap();
+
+    assert!(fixture.user_order_book_state().await.open_orders.is_empty());
+}
+
+#[tokio::test]
+async fn test_multi_token_swap_routes_through_each_pool() {
+    let mut fixture = setup_pool(program_test(), 1_000_000).await;
+    let route_pool = fixture.setup_route_pool(1_000_000).await;
+    let hops = [
+        (fixture.pool, fixture.pool_authority, fixture.pool_token_a_account, fixture.pool_token_b_account),
+        (route_pool.pool, route_pool.pool_authority, route_pool.pool_token_b_account, route_pool.pool_token_c_account),
+    ];
+
+    // A to B pays 9_841 as in a plain swap; selling that into the B/C pool yields 9_716
+    // gross, of which 30 is the fee.
+    let swap_ix = fixture.multi_token_swap_ix(fixture.user_token_a_account, route_pool.user_token_c_account, 10_000, 9_687, &hops);
+    assert!(send(&mut fixture.context, &[swap_ix], &[]).await.is_err());
+
+    let user_a_before = fixture.token_balance(fixture.user_token_a_account).await;
+    let user_b_before = fixture.token_balance(fixture.user_token_b_account).await;
+    let user_c_before = fixture.token_balance(route_pool.user_token_c_account).await;
+    let swap_ix = fixture.multi_token_swap_ix(fixture.user_token_a_account, route_pool.user_token_c_account, 10_000, 9_686, &hops);
+    send(&mut fixture.context, &[swap_ix], &[]).await.unwrap();
+
+    assert_eq!(fixture.token_balance(fixture.user_token_a_account).await, user_a_before - 10_000);
+    assert_eq!(fixture.token_balance(fixture.user_token_b_account).await, user_b_before);
+    assert_eq!(fixture.token_balance(route_pool.user_token_c_account).await, user_c_before + 9_686);
+    let pool = fixture.pool_state().await;
+    assert_eq!((pool.token_a_reserve, pool.token_b_reserve), (1_010_000, 990_159));
+    assert_eq!(fixture.token_balance(route_pool.pool_token_b_account).await, 1_009_841);
+    assert_eq!(fixture.token_balance(route_pool.pool_token_c_account).await, 990_314);
+}
+
+#[tokio::test]
+async fn test_multi_token_swap_rejects_broken_routes() {
+    let mut fixture = setup_pool(program_test(), 1_000_000).await;
+    let route_pool = fixture.setup_route_pool(1_000_000).await;
+    let first_hop = (fixture.pool, fixture.pool_authority, fixture.pool_token_a_account, fixture.pool_token_b_account);
+    let second_hop =
+        (route_pool.pool, route_pool.pool_authority, route_pool.pool_token_b_account, route_pool.pool_token_c_account);
+
+    // Token A is not traded by the B/C pool.
+    let swap_ix = fixture.multi_token_swap_ix(fixture.user_token_a_account, route_pool.user_token_c_account, 10_000, 1, &[second_hop]);
+    assert!(send(&mut fixture.context, &[swap_ix], &[]).await.is_err());
+    // The route ends in token B, not the destination's token C.
+    let swap_ix = fixture.multi_token_swap_ix(fixture.user_token_a_account, route_pool.user_token_c_account, 10_000, 1, &[first_hop]);
+    assert!(send(&mut fixture.context, &[swap_ix], &[]).await.is_err());
+    // Vaults must be the hop's input and output vaults, in that order.
+    let reversed_hop = (fixture.pool, fixture.pool_authority, fixture.pool_token_b_account, fixture.pool_token_a_account);
+    let swap_ix = fixture.multi_token_swap_ix(fixture.user_token_a_account, fixture.user_token_b_account, 10_000, 1, &[reversed_hop]);
+    assert!(send(&mut fixture.context, &[swap_ix], &[]).await.is_err());
+
+    let pool = fixture.pool_state().await;
+    assert_eq!((pool.token_a_reserve, pool.token_b_reserve), (1_000_000, 1_000_000));
+}
+
+#[tokio::test]
+async fn test_dutch_auction_fills_once_its_price_decays_to_the_pool() {
+    let mut fixture = setup_pool(program_test(), 1_000_000).await;
+    let order = fixture.next_order().await;
+    let now = fixture.now().await;
+
+    // The pool pays 0.9841 B per A for 10_000 A; the auction falls from 1.2 to 0.9 over 1_000s.
+    let auction = OrderType::DutchAuction {
+        start_price: 1_200_000_000,
+        end_price: 900_000_000,
+        start_time: now,
+        end_time: now + 1_000,
+        decay: PriceDecay::Linear,
+    };
+    let params = LimitOrderParams { order_type: auction, ..limit_order(10_000, 0) };
+    let place_ix = fixture.place_limit_order_ix(order, params);
+    send(&mut fixture.context, &[place_ix], &[]).await.unwrap();
+    assert_eq!(fixture.order_state(order).await.target_price, 900_000_000);
+
+    // Halfway through the auction still asks for 1.05 B per A.
+    fixture.advance_clock(500).await;
+    let execute_ix = fixture.execute_limit_order_ix(order, 10_000, fixture.keeper_token_a_account);
+    assert!(send(&mut fixture.context, &[execute_ix], &[&fixture.keeper]).await.is_err());
+
+    // At 800s it asks for 0.96, which the pool beats. The retry is the same transaction,
+    // so it needs a fresh blockhash to run instead of hitting the status cache.
+    fixture.advance_clock(300).await;
+    refresh_blockhash(&mut fixture.context).await;
+    let user_b_before = fixture.token_balance(fixture.user_token_b_account).await;
+    let execute_ix = fixture.execute_limit_order_ix(order, 10_000, fixture.keeper_token_a_account);
+    send(&mut fixture.context, &[execute_ix], &[&fixture.keeper]).await.unwrap();
+    assert_eq!(fixture.token_balance(fixture.user_token_b_account).await, user_b_before + 9_841);
+    assert!(fixture.context.banks_client.get_account(order).await.unwrap().is_none());
+
+    // Auction prices may not rise.
+    let order = fixture.next_order().await;
+    let auction = OrderType::DutchAuction {
+        start_price: 900_000_000,
+        end_price: 1_200_000_000,
+        start_time: now,
+        end_time: now + 1_000,
+        decay: PriceDecay::Linear,
+    };
+    let params = LimitOrderParams { order_type: auction, ..limit_order(10_000, 0) };
+    let place_ix = fixture.place_limit_order_ix(order, params);
+    assert!(send(&mut fixture.context, &[place_ix], &[]).await.is_err());
+}
+
+#[tokio::test]
+async fn test_keeper_fills_dutch_auction_from_inventory() {
+    let mut fixture = setup_pool(program_test(), 1_000_000).await;
+    let payer = fixture.context.payer.insecure_clone();
+    let token_b_mint = fixture.token_b_mint;
+    let keeper_token_b_account = fixture.keeper_token_b_account;
+    mint_tokens(&mut fixture.context.banks_client, &payer, &token_b_mint, &keeper_token_b_account, 10_000).await.unwrap();
+
+    let order = fixture.next_order().await;
+    let now = fixture.now().await;
+    // Falls geometrically from 2 to 0.5 B per A, passing 1 B per A halfway through.
+    let params = LimitOrderParams {
+        order_type: OrderType::DutchAuction {
+            start_price: 2_000_000_000,
+            end_price: 500_000_000,
+            start_time: now,
+            end_time: now + 1_000,
+            decay: PriceDecay::Exponential,
+        },
+        keeper_fee: KeeperFee::Fixed { amount: 10 },
+        ..limit_order(1_000, 0)
+    };
+    let place_ix = fixture.place_limit_order_ix(order, params);
+    send(&mut fixture.context, &[place_ix], &[]).await.unwrap();
+
+    fixture.advance_clock(500).await;
+    let fill_ix = fixture.fill_limit_order_ix(order, 1_000, 999);
+    assert!(send(&mut fixture.context, &[fill_ix], &[&fixture.keeper]).await.is_err());
+
+    let pool_before = fixture.pool_state().await;
+    let user_b_before = fixture.token_balance(fixture.user_token_b_account).await;
+    let fill_ix = fixture.fill_limit_order_ix(order, 1_000, 1_001);
+    send(&mut fixture.context, &[fill_ix], &[&fixture.keeper]).await.unwrap();
+
+    // The keeper swaps 1_001 of its own B for the escrowed A plus the fee; the pool is untouched.
+    assert_eq!(fixture.token_balance(fixture.user_token_b_account).await, user_b_before + 1_001);
+    assert_eq!(fixture.token_balance(keeper_token_b_account).await, 10_000 - 1_001);
+    assert_eq!(fixture.token_balance(fixture.keeper_token_a_account).await, 1_010);
+    let pool_after = fixture.pool_state().await;
+    assert_eq!(pool_after.token_a_reserve, pool_before.token_a_reserve);
+    assert_eq!(pool_after.token_b_reserve, pool_before.token_b_reserve);
+    assert!(fixture.context.banks_client.get_account(order).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_user_order_book_lists_open_orders() {
+    let mut fixture = setup_pool(program_test(), 1_000_000).await;
+
+    let mut orders = Vec::new();
+    for _ in 0..3 {
+        let order = fixture.next_order().await;
+        let place_ix = fixture.place_limit_order_ix(order, limit_order(10_000, 900_000_000));
+        send(&mut fixture.context, &[place_ix], &[]).await.unwrap();
+        assert_eq!(fixture.order_state(order).await.nonce, orders.len() as u64);
+        orders.push(order);
+    }
+    assert_eq!(fixture.user_order_book_state().await.open_orders, orders);
+
+    // Filled and cancelled orders drop out of the index; nonces are never reused.
+    let execute_ix = fixture.execute_limit_order_ix(orders[0], 10_000, fixture.keeper_token_a_account);
+    send(&mut fixture.context, &[execute_ix], &[&fixture.keeper]).await.unwrap();
+    let cancel_ix = fixture.cancel_limit_order_ix(orders[2]);
+    send(&mut fixture.context, &[cancel_ix], &[]).await.unwrap();
+
+    let book = fixture.user_order_book_state().await;
+    assert_eq!(book.open_orders, vec![orders[1]]);
+    assert_eq!(book.next_nonce, 3);
+}
+
+#[tokio::test]
+async fn test_cancel_limit_order_refunds_and_closes() {
+    let mut fixture = setup_pool(program_test(), 1_000_000).await;
+    let order = fixture.next_order().await;
+    let user_a_before = fixture.token_balance(fixture.user_token_a_account).await;
+    let params = LimitOrderParams {
+        keeper_fee: KeeperFee::Fixed { amount: 25 },
+        ..limit_order(10_000, 984_100_000)
+    };
+
+    let place_ix = fixture.place_limit_order_ix(order, params);
+    send(&mut fixture.context, &[place_ix], &[]).await.unwrap();
+
+    // Only the owner can cancel.
+    let mut not_owner = fixture.cancel_limit_order_ix(order);
+    not_owner.accounts[0] = AccountMeta::new(fixture.keeper.pubkey(), true);
+    assert!(send(&mut fixture.context, &[not_owner], &[&fixture.keeper]).await.is_err());
+
+    let cancel_ix = fixture.cancel_limit_order_ix(order);
+    send(&mut fixture.context, &[cancel_ix], &[]).await.unwrap();
+
+    assert_eq!(fixture.token_balance(fixture.user_token_a_account).await, user_a_before);
+    let escrow = fixture.escrow(order);
+    for closed in [order, escrow] {
+        assert!(fixture.context.banks_client.get_account(closed).await.unwrap().is_none());
+    }
+}
+
+#[tokio::test]
+async fn test_close_expired_order_pays_cleanup_reward() {
+    let mut fixture = setup_pool(program_test(), 1_000_000).await;
+    let user_a_before = fixture.token_balance(fixture.user_token_a_account).await;
+
+    let live_order = fixture.next_order().await;
+    let place_ix = fixture.place_limit_order_ix(live_order, limit_order(10_000, 984_100_000));
+    send(&mut fixture.context, &[place_ix], &[]).await.unwrap();
+    let close_ix = fixture.close_expired_order_ix(live_order, Some(fixture.keeper_token_a_account));
+    assert!(send(&mut fixture.context, &[close_ix], &[&fixture.keeper]).await.is_err());
+
+    let expired_order = fixture.next_order().await;
+    let params = LimitOrderParams {
+        expiration: 0,
+        keeper_fee: KeeperFee::Fixed { amount: 25 },
+        ..limit_order(10_000, 984_100_000)
+    };
+    let place_ix = fixture.place_limit_order_ix(expired_order, params);
+    send(&mut fixture.context, &[place_ix], &[]).await.unwrap();
+
+    let execute_ix = fixture.execute_limit_order_ix(expired_order, 10_000, fixture.keeper_token_a_account);
+    assert!(send(&mut fixture.context, &[execute_ix], &[&fixture.keeper]).await.is_err());
+
+    let close_ix = fixture.close_expired_order_ix(expired_order, Some(fixture.keeper_token_a_account));
+    send(&mut fixture.context, &[close_ix], &[&fixture.keeper]).await.unwrap();
+
+    // The live order still holds its 10_000; the expired one refunded all but the 25 reward.
+    assert_eq!(fixture.token_balance(fixture.keeper_token_a_account).await, 25);
+    assert_eq!(fixture.token_balance(fixture.user_token_a_account).await, user_a_before - 10_025);
+    assert!(fixture.context.banks_client.get_account(expired_order).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_crossing_orders_match_before_pool() {
+    let mut fixture = setup_pool(program_test(), 1_000_000).await;
+
+    // A maker selling 5_000 B for at least 1 A each rests on the book.
+    let maker = fixture.next_order().await;
+    let maker_params = LimitOrderParams {
+        direction: SwapDirection::BToA,
+        partial_fill: true,
+        ..limit_order(5_000, 1_000_000_000)
+    };
+    let place_ix = fixture.place_book_order_ix(maker, maker_params);
+    send(&mut fixture.context, &[place_ix], &[]).await.unwrap();
+    assert_eq!(fixture.order_book_state().await.bid_count, 1);
+
+    let taker = fixture.next_order().await;
+    let place_ix = fixture.place_limit_order_ix(taker, limit_order(10_000, 984_100_000));
+    send(&mut fixture.context, &[place_ix], &[]).await.unwrap();
+
+    let pool_before = fixture.pool_state().await;
+    let user_a_before = fixture.token_balance(fixture.user_token_a_account).await;
+    let user_b_before = fixture.token_balance(fixture.user_token_b_account).await;
+    let execute_ix = fixture.execute_against_book_ix(taker, 10_000, fixture.keeper_token_a_account, &[maker]);
+    send(&mut fixture.context, &[execute_ix], &[&fixture.keeper]).await.unwrap();
+
+    // Half the taker's A crosses with the maker at 1:1; only the rest trades with the pool.
+    let pool_after = fixture.pool_state().await;
+    assert_eq!(pool_after.token_a_reserve, pool_before.token_a_reserve + 5_000);
+    let pool_out = pool_before.token_b_reserve - pool_after.token_b_reserve;
+    assert_eq!(fixture.token_balance(fixture.user_token_b_account).await, user_b_before + 5_000 + pool_out);
+    assert_eq!(fixture.token_balance(fixture.user_token_a_account).await, user_a_before + 5_000);
+
+    // The filled maker leaves the book and is closed along with its escrow.
+    assert_eq!(fixture.order_book_state().await.bid_count, 0);
+    for closed in [maker, fixture.escrow(maker), taker] {
+        assert!(fixture.context.banks_client.get_account(closed).await.unwrap().is_none());
+    }
+    assert!(fixture.user_order_book_state().await.open_orders.is_empty());
+}
+
+#[tokio::test]
+async fn test_book_rejects_makers_that_do_not_cross() {
+    let mut fixture = setup_pool(program_test(), 1_000_000).await;
+
+    // Asks 1.1 A per B, more than the taker's 0.9841 B per A allows.
+    let maker = fixture.next_order().await;
+    let maker_params = LimitOrderParams {
+        direction: SwapDirection::BToA,
+        partial_fill: true,
+        ..limit_order(5_000, 1_100_000_000)
+    };
+    let place_ix = fixture.place_book_order_ix(maker, maker_params);
+    send(&mut fixture.context, &[place_ix], &[]).await.unwrap();
+
+    let taker = fixture.next_order().await;
+    let place_ix = fixture.place_limit_order_ix(taker, limit_order(10_000, 984_100_000));
+    send(&mut fixture.context, &[place_ix], &[]).await.unwrap();
+
+    let execute_ix = fixture.execute_against_book_ix(taker, 10_000, fixture.keeper_token_a_account, &[maker]);
+    assert!(send(&mut fixture.context, &[execute_ix], &[&fixture.keeper]).await.is_err());
+    assert_eq!(fixture.order_state(maker).await.remaining_amount, 5_000);
+}
+
+#[tokio::test]
+async fn test_keeper_cannot_skip_crossing_makers() {
+    let mut fixture = setup_pool(program_test(), 1_000_000).await;
+
+    // Two bids at 1 and 1.01 A per B, both crossing the taker's 0.9841 B per A.
+    let mut makers = Vec::new();
+    for price in [1_000_000_000, 1_010_000_000] {
+        let maker = fixture.next_order().await;
+        let maker_params = LimitOrderParams {
+            direction: SwapDirection::BToA,
+            partial_fill: true,
+            ..limit_order(2_000, price)
+        };
+        let place_ix = fixture.place_book_order_ix(maker, maker_params);
+        send(&mut fixture.context, &[place_ix], &[]).await.unwrap();
+        makers.push(maker);
+    }
+    let taker = fixture.next_order().await;
+    let place_ix = fixture.place_limit_order_ix(taker, limit_order(10_000, 984_100_000));
+    send(&mut fixture.context, &[place_ix], &[]).await.unwrap();
+
+    // Going straight to the pool, leaving out the book, or matching out of order all fail.
+    let execute_ix = fixture.execute_against_book_ix(taker, 10_000, fixture.keeper_token_a_account, &[]);
+    let result = send(&mut fixture.context, &[execute_ix], &[&fixture.keeper]).await;
+    assert_program_error(result, ErrorCode::CrossingMakerSkipped);
+    let mut execute_ix = fixture.execute_limit_order_ix(taker, 10_000, fixture.keeper_token_a_account);
+    execute_ix.accounts[7] = AccountMeta::new_readonly(comprehensive_token_swap::id(), false);
+    let result = send(&mut fixture.context, &[execute_ix], &[&fixture.keeper]).await;
+    assert_program_error(result, ErrorCode::OrderBookRequired);
+    let execute_ix = fixture.execute_against_book_ix(taker, 10_000, fixture.keeper_token_a_account, &makers[1..]);
+    let result = send(&mut fixture.context, &[execute_ix], &[&fixture.keeper]).await;
+    assert_program_error(result, ErrorCode::InvalidMaker);
+    let execute_ix = fixture.execute_against_book_ix(taker, 10_000, fixture.keeper_token_a_account, &makers[..1]);
+    let result = send(&mut fixture.context, &[execute_ix], &[&fixture.keeper]).await;
+    assert_program_error(result, ErrorCode::CrossingMakerSkipped);
+
+    let execute_ix = fixture.execute_against_book_ix(taker, 10_000, fixture.keeper_token_a_account, &makers);
+    send(&mut fixture.context, &[execute_ix], &[&fixture.keeper]).await.unwrap();
+    assert_eq!(fixture.order_book_state().await.bid_count, 0);
+}
+
+#[tokio::test]
+async fn test_book_orders_must_take_any_fill_above_the_minimum_size() {
+    let mut fixture = setup_pool(program_test(), 1_000_000).await;
+    let book_order = LimitOrderParams { partial_fill: true, ..limit_order(10_000, 984_100_000) };
+
+    let order = fixture.next_order().await;
+    let place_ix = fixture.place_book_order_ix(order, LimitOrderParams { amount_in: 999, ..book_order });
+    let result = send(&mut fixture.context, &[place_ix], &[]).await;
+    assert_program_error(result, ErrorCode::BookOrderTooSmall);
+    for params in [
+        LimitOrderParams { partial_fill: false, ..book_order },
+        LimitOrderParams { min_fill_amount: 100, ..book_order },
+        LimitOrderParams { keeper_fee: KeeperFee::Fixed { amount: 10 }, ..book_order },
+    ] {
+        let place_ix = fixture.place_book_order_ix(order, params);
+        let result = send(&mut fixture.context, &[place_ix], &[]).await;
+        assert_program_error(result, ErrorCode::InvalidBookOrder);
+    }
+
+    let place_ix = fixture.place_book_order_ix(order, LimitOrderParams { amount_in: 1_000, ..book_order });
+    send(&mut fixture.context, &[place_ix], &[]).await.unwrap();
+    assert_eq!(fixture.order_book_state().await.ask_count, 1);
+}
+
+#[tokio::test]
+async fn test_matched_makers_return_rent_to_their_owner() {
+    let mut fixture = setup_pool(program_test(), 1_000_000).await;
+    let payer = fixture.context.payer.insecure_clone();
+    let keeper = fixture.keeper.insecure_clone();
+    let (token_b_mint, keeper_token_b_account) = (fixture.token_b_mint, fixture.keeper_token_b_account);
+    mint_tokens(&mut fixture.context.banks_client, &payer, &token_b_mint, &keeper_token_b_account, 5_000).await.unwrap();
+
+    // The keeper rests its own bid of 5_000 B at 1 A each.
+    let params = LimitOrderParams {
+        direction: SwapDirection::BToA,
+        partial_fill: true,
+        ..limit_order(5_000, 1_000_000_000)
+    };
+    let (maker, keeper_book) = fixture.place_keeper_bid(params).await;
+    let keeper_lamports = fixture.context.banks_client.get_balance(keeper.pubkey()).await.unwrap();
+    let rent = fixture.context.banks_client.get_balance(maker).await.unwrap()
+        + fixture.context.banks_client.get_balance(fixture.escrow(maker)).await.unwrap();
+
+    // The user's order buys all of it.
+    let taker = fixture.next_order().await;
+    let place_ix = fixture.place_limit_order_ix(taker, limit_order(5_000, 984_100_000));
+    send(&mut fixture.context, &[place_ix], &[]).await.unwrap();
+    let maker_metas = fixture.maker_metas(maker, keeper.pubkey(), keeper_book, fixture.keeper_token_a_account);
+    let execute_ix = fixture.execute_with_maker_metas(taker, 5_000, fixture.keeper_token_a_account, maker_metas);
+    send(&mut fixture.context, &[execute_ix], &[&keeper]).await.unwrap();
+
+    assert_eq!(fixture.token_balance(fixture.keeper_token_a_account).await, 5_000);
+    assert!(fixture.context.banks_client.get_account(maker).await.unwrap().is_none());
+    assert_eq!(fixture.context.banks_client.get_balance(keeper.pubkey()).await.unwrap(), keeper_lamports + rent);
+    let account = fixture.context.banks_client.get_account(keeper_book).await.unwrap().unwrap();
+    assert!(UserOrderBook::try_deserialize(&mut account.data.as_slice()).unwrap().open_orders.is_empty());
+}
+
+#[tokio::test]
+async fn test_makers_that_cannot_be_paid_are_evicted() {
+    let mut fixture = setup_pool(program_test(), 1_000_000).await;
+    let payer = fixture.context.payer.insecure_clone();
+    let keeper = fixture.keeper.insecure_clone();
+    let (token_b_mint, keeper_token_b_account) = (fixture.token_b_mint, fixture.keeper_token_b_account);
+    mint_tokens(&mut fixture.context.banks_client, &payer, &token_b_mint, &keeper_token_b_account, 5_000).await.unwrap();
+    let params = LimitOrderParams {
+        direction: SwapDirection::BToA,
+        partial_fill: true,
+        ..limit_order(5_000, 1_000_000_000)
+    };
+    let (maker, keeper_book) = fixture.place_keeper_bid(params).await;
+
+    // The keeper closes the account its bid pays out to.
+    let close_ix = spl_token::instruction::close_account(
+        &spl_token::id(),
+        &fixture.keeper_token_a_account,
+        &keeper.pubkey(),
+        &keeper.pubkey(),
+        &[],
+    )
+    .unwrap();
+    send(&mut fixture.context, &[close_ix], &[&keeper]).await.unwrap();
+
+    // The taker's fill evicts the bid and goes to the pool instead.
+    let taker = fixture.next_order().await;
+    let place_ix = fixture.place_limit_order_ix(taker, limit_order(5_000, 984_100_000));
+    send(&mut fixture.context, &[place_ix], &[]).await.unwrap();
+    let pool_before = fixture.pool_state().await;
+    let maker_metas = fixture.maker_metas(maker, keeper.pubkey(), keeper_book, fixture.keeper_token_a_account);
+    let execute_ix = fixture.execute_with_maker_metas(taker, 5_000, fixture.user_token_a_account, maker_metas);
+    send(&mut fixture.context, &[execute_ix], &[&keeper]).await.unwrap();
+
+    assert_eq!(fixture.pool_state().await.token_a_reserve, pool_before.token_a_reserve + 5_000);
+    assert!(fixture.context.banks_client.get_account(taker).await.unwrap().is_none());
+    assert_eq!(fixture.order_book_state().await.bid_count, 0);
+    let maker_state = fixture.order_state(maker).await;
+    assert!(!maker_state.on_book);
+    assert_eq!(maker_state.remaining_amount, 5_000);
+    assert_eq!(fixture.token_balance(fixture.escrow(maker)).await, 5_000);
+}
+
+#[tokio::test]
+async fn test_cancel_removes_order_from_book() {
+    let mut fixture = setup_pool(program_test(), 1_000_000).await;
+    let order = fixture.next_order().await;
+    let params = LimitOrderParams { partial_fill: true, ..limit_order(10_000, 984_100_000) };
+    let place_ix = fixture.place_book_order_ix(order, params);
+    send(&mut fixture.context, &[place_ix], &[]).await.unwrap();
+    let book = fixture.order_book_state().await;
+    assert_eq!((book.ask_count, book.asks[0].order), (1, order));
+
+    // A resting order can only be cancelled together with its book.
+    let cancel_ix = fixture.cancel_order_ix(order, None);
+    assert!(send(&mut fixture.context, &[cancel_ix], &[]).await.is_err());
+
+    let cancel_ix = fixture.cancel_order_ix(order, Some(fixture.order_book));
+    send(&mut fixture.context, &[cancel_ix], &[]).await.unwrap();
+    assert_eq!(fixture.order_book_state().await.ask_count, 0);
+}
+
+#[tokio::test]
+async fn test_dca_order_sells_one_slice_per_interval() {
+    let mut fixture = setup_pool(program_test(), 1_000_000).await;
+    let dca_order = fixture.next_dca_order().await;
+    let user_b_before = fixture.token_balance(fixture.user_token_b_account).await;
+    let params = DcaOrderParams {
+        direction: SwapDirection::AToB,
+        total_amount: 25_000,
+        amount_per_cycle: 10_000,
+        min_amount_out_per_cycle: 9_000,
+        interval: 3_600,
+    };
+    let place_ix = fixture.place_dca_order_ix(dca_order, params);
+    send(&mut fixture.context, &[place_ix], &[]).await.unwrap();
+    assert_eq!(fixture.user_order_book_state().await.open_orders, vec![dca_order]);
+
+    // The first slice is due right away; 10_000 into 1_000_000/1_000_000 pays 9_841.
+    let execute_ix = fixture.execute_dca_cycle_ix(dca_order);
+    send(&mut fixture.context, &[execute_ix], &[&fixture.keeper]).await.unwrap();
+    let state = fixture.dca_order_state(dca_order).await;
+    assert_eq!((state.remaining_amount, state.received_amount, state.cycle_count), (15_000, 9_841, 1));
+
+    // Any keeper may execute, but not before the interval has passed.
+    let mut too_early = fixture.execute_dca_cycle_ix(dca_order);
+    too_early.accounts[0] = AccountMeta::new_readonly(fixture.user(), true);
+    assert_program_error(send(&mut fixture.context, &[too_early], &[]).await, ErrorCode::DcaCycleNotDue);
+
+    for _ in 0..2 {
+        fixture.advance_clock(3_600).await;
+        // Both cycles send the same transaction, so wait for a fresh blockhash or the
+        // second is answered from the status cache without running.
+        refresh_blockhash(&mut fixture.context).await;
+        let execute_ix = fixture.execute_dca_cycle_ix(dca_order);
+        send(&mut fixture.context, &[execute_ix], &[&fixture.keeper]).await.unwrap();
+    }
+
+    // The short final slice of 5_000 emptied the escrow and closed the order.
+    let escrow = fixture.escrow(dca_order);
+    for closed in [dca_order, escrow] {
+        assert!(fixture.context.banks_client.get_account(closed).await.unwrap().is_none());
+    }
+    assert!(fixture.user_order_book_state().await.open_orders.is_empty());
+    let pool = fixture.pool_state().await;
+    assert_eq!(pool.token_a_reserve, 1_025_000);
+    assert_eq!(
+        fixture.token_balance(fixture.user_token_b_account).await,
+        user_b_before + (1_000_000 - pool.token_b_reserve)
+    );
+}
+
+#[tokio::test]
+async fn test_dca_order_respects_minimum_and_refunds_on_cancel() {
+    let mut fixture = setup_pool(program_test(), 1_000_000).await;
+    let dca_order = fixture.next_dca_order().await;
+    let user_a_before = fixture.token_balance(fixture.user_token_a_account).await;
+    let params = DcaOrderParams {
+        direction: SwapDirection::AToB,
+        total_amount: 30_000,
+        amount_per_cycle: 10_000,
+        // One unit more than the pool pays for a slice.
+        min_amount_out_per_cycle: 9_842,
+        interval: 3_600,
+    };
+    let place_ix = fixture.place_dca_order_ix(dca_order, params);
+    send(&mut fixture.context, &[place_ix], &[]).await.unwrap();
+
+    let execute_ix = fixture.execute_dca_cycle_ix(dca_order);
+    let result = send(&mut fixture.context, &[execute_ix], &[&fixture.keeper]).await;
+    assert_program_error(result, ErrorCode::SlippageExceeded);
+
+    let cancel_ix = fixture.cancel_dca_order_ix(dca_order);
+    send(&mut fixture.context, &[cancel_ix], &[]).await.unwrap();
+    assert_eq!(fixture.token_balance(fixture.user_token_a_account).await, user_a_before);
+    assert!(fixture.context.banks_client.get_account(dca_order).await.unwrap().is_none());
+    assert!(fixture.user_order_book_state().await.open_orders.is_empty());
+}
+
+#[tokio::test]
+async fn test_opposing_long_term_orders_settle_at_expiry() {
+    let mut fixture = setup_pool(program_test(), 1_000_000).await;
+    let user_a_before = fixture.token_balance(fixture.user_token_a_account).await;
+    let user_b_before = fixture.token_balance(fixture.user_token_b_account).await;
+
+    let sell_a = fixture.next_long_term_order().await;
+    let place_ix = fixture.place_long_term_order_ix(sell_a, SwapDirection::AToB, 100_000, 2);
+    send(&mut fixture.context, &[place_ix], &[]).await.unwrap();
+    let sell_b = fixture.next_long_term_order().await;
+    let place_ix = fixture.place_long_term_order_ix(sell_b, SwapDirection::BToA, 40_000, 2);
+    send(&mut fixture.context, &[place_ix], &[]).await.unwrap();
+
+    // Both end on the same interval boundary and share one expiry entry.
+    let pool = fixture.pool_state().await;
+    assert_eq!(pool.twamm.expiries.len(), 1);
+    assert_eq!(pool.twamm.expiries[0].order_count, 2);
+    assert_eq!(pool.token_a_reserve, 1_000_000);
+
+    fixture.advance_clock(3 * TWAMM_INTERVAL).await;
+    for (order, direction) in [(sell_a, SwapDirection::AToB), (sell_b, SwapDirection::BToA)] {
+        let withdraw_ix = fixture.withdraw_long_term_proceeds_ix(order, direction);
+        send(&mut fixture.context, &[withdraw_ix], &[]).await.unwrap();
+        assert!(fixture.context.banks_client.get_account(order).await.unwrap().is_none());
+    }
+
+    // The net flow sold A, so the pool holds more A and less B, and the product never shrank.
+    let pool = fixture.pool_state().await;
+    assert!(pool.twamm.expiries.is_empty());
+    assert_eq!((pool.twamm.sell_rate_a, pool.twamm.sell_rate_b), (0, 0));
+    assert!(pool.token_a_reserve > 1_000_000 && pool.token_b_reserve < 1_000_000);
+    assert!(pool.token_a_reserve as u128 * pool.token_b_reserve as u128 >= 1_000_000u128 * 1_000_000);
+
+    // Every token the user gave up is in the reserves, give or take rounding dust.
+    let user_a_spent = user_a_before - fixture.token_balance(fixture.user_token_a_account).await;
+    let user_b_gained = fixture.token_balance(fixture.user_token_b_account).await - user_b_before;
+    assert!((pool.token_a_reserve - 1_000_000).abs_diff(user_a_spent) <= 2);
+    assert!((1_000_000 - pool.token_b_reserve).abs_diff(user_b_gained) <= 2);
+}
+
+#[tokio::test]
+async fn test_keepers_crank_streams_and_settle_expired_orders() {
+    let mut fixture = setup_pool(program_test(), 1_000_000).await;
+    let user_b_before = fixture.token_balance(fixture.user_token_b_account).await;
+    let mut orders = Vec::new();
+    for intervals in [2, 3] {
+        let order = fixture.next_long_term_order().await;
+        let place_ix = fixture.place_long_term_order_ix(order, SwapDirection::AToB, 10_000, intervals);
+        send(&mut fixture.context, &[place_ix], &[]).await.unwrap();
+        orders.push(order);
+    }
+    let expiries: Vec<i64> = fixture.pool_state().await.twamm.expiries.iter().map(|entry| entry.expiry).collect();
+    assert_eq!(expiries.len(), 2);
+
+    // An order that is still streaming cannot be settled.
+    let settle_ix = fixture.settle_long_term_order_ix(orders[0], SwapDirection::AToB);
+    let result = send(&mut fixture.context, &[settle_ix], &[&fixture.keeper]).await;
+    assert_program_error(result, ErrorCode::OrderNotExpired);
+
+    // The crank crosses at most the given number of expiries and stops at the last one.
+    fixture.advance_clock(3 * TWAMM_INTERVAL).await;
+    let crank_ix = fixture.execute_virtual_orders_ix(1);
+    send(&mut fixture.context, &[crank_ix], &[]).await.unwrap();
+    assert_eq!(fixture.pool_state().await.twamm.last_virtual_order_time, expiries[0]);
+    // Past the second expiry nothing is left to cross, so it runs on to now.
+    refresh_blockhash(&mut fixture.context).await;
+    let crank_ix = fixture.execute_virtual_orders_ix(1);
+    send(&mut fixture.context, &[crank_ix], &[]).await.unwrap();
+    assert_eq!(fixture.pool_state().await.twamm.last_virtual_order_time, fixture.now().await);
+
+    // Anyone settles expired orders for their owner, which frees the expiry slots.
+    for order in orders.iter().copied() {
+        let settle_ix = fixture.settle_long_term_order_ix(order, SwapDirection::AToB);
+        send(&mut fixture.context, &[settle_ix], &[&fixture.keeper]).await.unwrap();
+        assert!(fixture.context.banks_client.get_account(order).await.unwrap().is_none());
+    }
+    let pool = fixture.pool_state().await;
+    assert!(pool.twamm.expiries.is_empty());
+    assert!(fixture.user_order_book_state().await.open_orders.is_empty());
+    let user_b_gained = fixture.token_balance(fixture.user_token_b_account).await - user_b_before;
+    assert!((1_000_000 - pool.token_b_reserve).abs_diff(user_b_gained) <= 2);
+}
+
+#[tokio::test]
+async fn test_swaps_execute_virtual_orders_first() {
+    let mut fixture = setup_pool(program_test(), 1_000_000).await;
+    let order = fixture.next_long_term_order().await;
+    let place_ix = fixture.place_long_term_order_ix(order, SwapDirection::AToB, 100_000, 2);
+    send(&mut fixture.context, &[place_ix], &[]).await.unwrap();
+    let sell_rate = fixture.long_term_order_state(order).await.sell_rate;
+
+    fixture.advance_clock(1_800).await;
+    let swap_ix = fixture.swap_ix(SwapDirection::AToB, 10, 0);
+    send(&mut fixture.context, &[swap_ix], &[]).await.unwrap();
+
+    // The swap first sold the stream's half hour of A, then its own 10.
+    let clock = fixture.context.banks_client.get_sysvar::<Clock>().await.unwrap();
+    let pool = fixture.pool_state().await;
+    assert_eq!(pool.twamm.last_virtual_order_time, clock.unix_timestamp);
+    let streamed = (sell_rate * 1_800 / 1_000_000_000) as u64;
+    assert_eq!(pool.token_a_reserve, 1_000_000 + streamed + 10);
+}
+
+#[tokio::test]
+async fn test_cancel_long_term_order_refunds_unsold_input() {
+    let mut fixture = setup_pool(program_test(), 1_000_000).await;
+    let user_a_before = fixture.token_balance(fixture.user_token_a_account).await;
+    let user_b_before = fixture.token_balance(fixture.user_token_b_account).await;
+    let order = fixture.next_long_term_order().await;
+    let place_ix = fixture.place_long_term_order_ix(order, SwapDirection::AToB, 100_000, 2);
+    send(&mut fixture.context, &[place_ix], &[]).await.unwrap();
+
+    fixture.advance_clock(1_800).await;
+    let cancel_ix = fixture.cancel_long_term_order_ix(order, SwapDirection::AToB);
+    send(&mut fixture.context, &[cancel_ix], &[]).await.unwrap();
+
+    // Only the half hour already streamed was sold; the rest came back.
+    let pool = fixture.pool_state().await;
+    let user_a_spent = user_a_before - fixture.token_balance(fixture.user_token_a_account).await;
+    let user_b_gained = fixture.token_balance(fixture.user_token_b_account).await - user_b_before;
+    assert!(user_a_spent > 0 && user_a_spent < 100_000);
+    assert!((pool.token_a_reserve - 1_000_000).abs_diff(user_a_spent) <= 2);
+    assert!((1_000_000 - pool.token_b_reserve).abs_diff(user_b_gained) <= 1);
+    assert!(pool.twamm.expiries.is_empty());
+    assert_eq!(pool.twamm.sell_rate_a, 0);
+    assert!(fixture.context.banks_client.get_account(order).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_long_term_orders_need_a_full_interval_and_an_untripped_pool() {
+    let mut fixture = setup_pool(program_test(), 1_000_000).await;
+    // One second past a boundary, the next boundary is a second short of a full interval.
+    let now = fixture.now().await;
+    fixture.advance_clock(TWAMM_INTERVAL - now.rem_euclid(TWAMM_INTERVAL) + 1).await;
+    let order = fixture.next_long_term_order().await;
+    let place_ix = fixture.place_long_term_order_ix(order, SwapDirection::AToB, 100_000, 1);
+    assert_program_error(send(&mut fixture.context, &[place_ix], &[]).await, ErrorCode::InvalidLongTermOrder);
+
+    let configure_ix = fixture.configure_circuit_breaker_ix(fixture.pool, 100, 0, 0, true);
+    let swap_ix = fixture.swap_ix(SwapDirection::AToB, 100_000, 0);
+    send(&mut fixture.context, &[configure_ix, swap_ix], &[]).await.unwrap();
+    assert!(fixture.pool_state().await.breaker_tripped);
+    let place_ix = fixture.place_long_term_order_ix(order, SwapDirection::AToB, 100_000, 2);
+    assert_program_error(send(&mut fixture.context, &[place_ix], &[]).await, ErrorCode::CircuitBreakerActive);
+}
+
+#[tokio::test]
+async fn test_streams_stand_still_while_swaps_are_paused() {
+    let mut fixture = setup_pool(program_test(), 1_000_000).await;
+    let order = fixture.next_long_term_order().await;
+    let place_ix = fixture.place_long_term_order_ix(order, SwapDirection::AToB, 100_000, 2);
+    send(&mut fixture.context, &[place_ix], &[]).await.unwrap();
+    let placed = fixture.pool_state().await;
+
+    let pause_ix = fixture.pause_ix(PAUSE_SWAPS);
+    send(&mut fixture.context, &[pause_ix], &[]).await.unwrap();
+    fixture.advance_clock(3 * TWAMM_INTERVAL).await;
+    let crank_ix = fixture.execute_virtual_orders_ix(u8::MAX);
+    send(&mut fixture.context, &[crank_ix], &[]).await.unwrap();
+    let pool = fixture.pool_state().await;
+    assert_eq!(pool.twamm.last_virtual_order_time, placed.twamm.last_virtual_order_time);
+    assert_eq!((pool.token_a_reserve, pool.token_b_reserve), (placed.token_a_reserve, placed.token_b_reserve));
+
+    // The order is past its expiry but unsold, so it cannot be settled yet.
+    let keeper = fixture.keeper.insecure_clone();
+    let settle_ix = fixture.settle_long_term_order_ix(order, SwapDirection::AToB);
+    assert_program_error(send(&mut fixture.context, &[settle_ix], &[&keeper]).await, ErrorCode::StreamsHalted);
+
+    // Once swaps resume the stream catches up and the order settles.
+    let unpause_ix = fixture.unpause_ix(PAUSE_SWAPS);
+    let settle_ix = fixture.settle_long_term_order_ix(order, SwapDirection::AToB);
+    send(&mut fixture.context, &[unpause_ix, settle_ix], &[&keeper]).await.unwrap();
+    let pool = fixture.pool_state().await;
+    assert!(pool.token_a_reserve > placed.token_a_reserve && pool.token_b_reserve < placed.token_b_reserve);
+    assert!(fixture.context.banks_client.get_account(order).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_streams_stand_still_while_the_breaker_is_tripped() {
+    let mut fixture = setup_pool(program_test(), 1_000_000).await;
+    let user_a_before = fixture.token_balance(fixture.user_token_a_account).await;
+    let order = fixture.next_long_term_order().await;
+    let place_ix = fixture.place_long_term_order_ix(order, SwapDirection::AToB, 100_000, 2);
+    send(&mut fixture.context, &[place_ix], &[]).await.unwrap();
+    let placed = fixture.long_term_order_state(order).await;
+
+    // The tripping swap runs the stream up to its own time and is then refunded.
+    fixture.advance_clock(600).await;
+    let configure_ix = fixture.configure_circuit_breaker_ix(fixture.pool, 100, 0, 0, true);
+    let swap_ix = fixture.swap_ix(SwapDirection::AToB, 100_000, 0);
+    send(&mut fixture.context, &[configure_ix, swap_ix], &[]).await.unwrap();
+    let tripped = fixture.pool_state().await;
+    assert!(tripped.breaker_tripped);
+
+    fixture.advance_clock(1_800).await;
+    let crank_ix = fixture.execute_virtual_orders_ix(u8::MAX);
+    send(&mut fixture.context, &[crank_ix], &[]).await.unwrap();
+    let pool = fixture.pool_state().await;
+    assert_eq!(pool.twamm.last_virtual_order_time, tripped.twamm.last_virtual_order_time);
+    assert_eq!((pool.token_a_reserve, pool.token_b_reserve), (tripped.token_a_reserve, tripped.token_b_reserve));
+
+    // Cancelling refunds everything the stream had not sold when it stopped.
+    let cancel_ix = fixture.cancel_long_term_order_ix(order, SwapDirection::AToB);
+    send(&mut fixture.context, &[cancel_ix], &[]).await.unwrap();
+    let unsold = placed.sell_rate * (placed.expiry - tripped.twamm.last_virtual_order_time) as u128 / 1_000_000_000;
+    let user_a_spent = user_a_before - fixture.token_balance(fixture.user_token_a_account).await;
+    assert_eq!(user_a_spent, 100_000 - unsold as u64);
+}
+
+// Well-behaved flash swap borrowers. They expect [this program, token program, the
+// borrower's token A account, the pool's token A vault, the account owner].
+fn repaying_borrower(_program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    let args = parse_callback(data);
+    repay(accounts, args.amount + args.fee)
+}
+
+fn principal_only_borrower(_program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    let args = parse_callback(data);
+    repay(accounts, args.amount)
+}
+
+fn parse_callback(data: &[u8]) -> FlashSwapCallback {
+    assert_eq!(data[..8], FLASH_SWAP_CALLBACK_DISCRIMINATOR);
+    FlashSwapCallback::try_from_slice(&data[8..]).unwrap()
+}
+
+fn repay(accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+    let ix = spl_token::instruction::transfer(
+        accounts[1].key,
+        accounts[2].key,
+        accounts[3].key,
+        accounts[4].key,
+        &[],
+        amount,
+    )?;
+    invoke(&ix, accounts)
+}
+
+// Flash swap borrowers that try to re-enter the pool from their callback. flash_swap
+// forwards its remaining accounts verbatim: this program, the swap program, and then
+// the accounts of the instruction to re-enter with.
+fn reentrant_swap_borrower(_program_id: &Pubkey, accounts: &[AccountInfo], _data: &[u8]) -> ProgramResult {
+    let data = instruction::Swap {
+        direction: SwapDirection::AToB,
+        mode: SwapMode::ExactIn,
+        amount: 10,
+        other_amount_threshold: 0,
+    }
+    .data();
+    reenter(accounts, data)
+}
+
+fn reentrant_deposit_borrower(_program_id: &Pubkey, accounts: &[AccountInfo], _data: &[u8]) -> ProgramResult {
+    let data = instruction::AddLiquidity {
+        amount_a: 10,
+        amount_b: 10,
+        min_lp_out: 0,
+    }
+    .data();
+    reenter(accounts, data)
+}
+
+fn reentrant_flash_borrower(_program_id: &Pubkey, accounts: &[AccountInfo], _data: &[u8]) -> ProgramResult {
+    let data = instruction::FlashSwap {
+        amount_a: 1_000,
+        target_contract: *accounts[0].key,
+    }
+    .data();
+    reenter(accounts, data)
+}
+
+fn reenter(accounts: &[AccountInfo], data: Vec<u8>) -> ProgramResult {
+    let ix = Instruction {
+        program_id: *accounts[1].key,
+        accounts: accounts[2..].iter().flat_map(|account| account.to_account_metas(None)).collect(),
+        data,
+    };
+    // The runtime refuses the re-entrant call with ReentrancyNotAllowed, which the builtin
+    // harness cannot map to a ProgramError and panics on instead, so fail the callback here.
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| invoke(&ix, accounts)))
+        .unwrap_or(Err(ProgramError::Custom(u32::MAX)))
+}
+
+// A pool created and funded through the program's own instructions, with the test
+// payer acting as admin, liquidity provider and trader.
+struct PoolFixture {
+    context: ProgramTestContext,
+    swap: Pubkey,
+    pool: Pubkey,
+    token_a_mint: Pubkey,
+    token_b_mint: Pubkey,
+    pool_authority: Pubkey,
+    pool_token_a_account: Pubkey,
+    pool_token_b_account: Pubkey,
+    lp_mint: Pubkey,
+    user_token_a_account: Pubkey,
+    user_token_b_account: Pubkey,
+    user_lp_account: Pubkey,
+    // A third party with empty token accounts of its own.
+    keeper: Keypair,
+    keeper_token_a_account: Pubkey,
+    keeper_token_b_account: Pubkey,
+    order_book: Pubkey,
+}
+
+// A second pool under the same swap state, pairing token B with a fresh token C.
+struct RoutePool {
+    pool: Pubkey,
+    pool_authority: Pubkey,
+    pool_token_b_account: Pubkey,
+    pool_token_c_account: Pubkey,
+    user_token_c_account: Pubkey,
+}
+
+impl PoolFixture {
+    fn user(&self) -> Pubkey {
+        self.context.payer.pubkey()
+    }
+
+    async fn token_balance(&mut self, account: Pubkey) -> u64 {
+        let account = self.context.banks_client.get_account(account).await.unwrap().unwrap();
+        spl_token::state::Account::unpack(&account.data).unwrap().amount
+    }
+
+    async fn lp_mint_supply(&mut self) -> u64 {
+        let account = self.context.banks_client.get_account(self.lp_mint).await.unwrap().unwrap();
+        spl_token::state::Mint::unpack(&account.data).unwrap().supply
+    }
+
+    fn user_order_book(&self) -> Pubkey {
+        Pubkey::find_program_address(&[USER_ORDER_BOOK_SEED, self.user().as_ref()], &comprehensive_token_swap::id()).0
+    }
+
+    async fn user_order_book_state(&mut self) -> UserOrderBook {
+        let account = self.context.banks_client.get_account(self.user_order_book()).await.unwrap().unwrap();
+        UserOrderBook::try_deserialize(&mut account.data.as_slice()).unwrap()
+    }
+
+    // Address the user's next order will be placed at.
+    async fn next_order(&mut self) -> Pubkey {
+        let nonce = self.user_order_book_state().await.next_nonce;
+        Pubkey::find_program_address(
+            &[ORDER_SEED, self.pool.as_ref(), self.user().as_ref(), &nonce.to_le_bytes()],
+            &comprehensive_token_swap::id(),
+        )
+        .0
+    }
+
+    // Address the user's next DCA order will be placed at.
+    async fn next_dca_order(&mut self) -> Pubkey {
+        let nonce = self.user_order_book_state().await.next_nonce;
+        Pubkey::find_program_address(
+            &[DCA_SEED, self.pool.as_ref(), self.user().as_ref(), &nonce.to_le_bytes()],
+            &comprehensive_token_swap::id(),
+        )
+        .0
+    }
+
+    async fn dca_order_state(&mut self, dca_order: Pubkey) -> DcaOrder {
+        let account = self.context.banks_client.get_account(dca_order).await.unwrap().unwrap();
+        DcaOrder::try_deserialize(&mut account.data.as_slice()).unwrap()
+    }
+
+    // Address the user's next long-term order will be placed at.
+    async fn next_long_term_order(&mut self) -> Pubkey {
+        let nonce = self.user_order_book_state().await.next_nonce;
+        Pubkey::find_program_address(
+            &[LONG_TERM_ORDER_SEED, self.pool.as_ref(), self.user().as_ref(), &nonce.to_le_bytes()],
+            &comprehensive_token_swap::id(),
+        )
+        .0
+    }
+
+    async fn long_term_order_state(&mut self, order: Pubkey) -> LongTermOrder {
+        let account = self.context.banks_client.get_account(order).await.unwrap().unwrap();
+        LongTermOrder::try_deserialize(&mut account.data.as_slice()).unwrap()
+    }
+
+    async fn now(&mut self) -> i64 {
+        self.context.banks_client.get_sysvar::<Clock>().await.unwrap().unix_timestamp
+    }
+
+    async fn advance_clock(&mut self, seconds: i64) {
+        let mut clock = self.context.banks_client.get_sysvar::<Clock>().await.unwrap();
+        clock.unix_timestamp += seconds;
+        self.context.set_sysvar(&clock);
+    }
+
+    async fn order_state(&mut self, order: Pubkey) -> LimitOrder {
+        let account = self.context.banks_client.get_account(order).await.unwrap().unwrap();
+        LimitOrder::try_deserialize(&mut account.data.as_slice()).unwrap()
+    }
+
+    async fn order_book_state(&mut self) -> OrderBook {
+        let account = self.context.banks_client.get_account(self.order_book).await.unwrap().unwrap();
+        *bytemuck::from_bytes::<OrderBook>(&account.data[8..])
+    }
+
+    async fn pool_state(&mut self) -> LiquidityPool {
+        let account = self.context.banks_client.get_account(self.pool).await.unwrap().unwrap();
+        LiquidityPool::try_deserialize(&mut account.data.as_slice()).unwrap()
+    }
+
+    fn add_liquidity_ix(&self, amount_a: u64, amount_b: u64, min_lp_out: u64) -> Instruction {
+        Instruction {
+            program_id: comprehensive_token_swap::id(),
+            accounts: accounts::AddLiquidityCtx {
+                user: self.user(),
+                pool: self.pool,
+                swap: self.swap,
+                pool_authority: self.pool_authority,
+                lp_mint: self.lp_mint,
+                user_lp_account: self.user_lp_account,
+                user_token_a_account: self.user_token_a_account,
+                user_token_b_account: self.user_token_b_account,
+                pool_token_a_account: self.pool_token_a_account,
+                pool_token_b_account: self.pool_token_b_account,
+                token_program: spl_token::id(),
+            }
+            .to_account_metas(None),
+            data: instruction::AddLiquidity { amount_a, amount_b, min_lp_out }.data(),
+        }
+    }
+
+    fn remove_liquidity_ix(&self, lp_amount: u64, min_amount_a: u64, min_amount_b: u64) -> Instruction {
+        Instruction {
+            program_id: comprehensive_token_swap::id(),
+            accounts: accounts::RemoveLiquidityCtx {
+                user: self.user(),
+                pool: self.pool,
+                swap: self.swap,
+                pool_authority: self.pool_authority,
+                lp_mint: self.lp_mint,
+                user_lp_account: self.user_lp_account,
+                user_token_a_account: self.user_token_a_account,
+                user_token_b_account: self.user_token_b_account,
+                pool_token_a_account: self.pool_token_a_account,
+                pool_token_b_account: self.pool_token_b_account,
+                token_program: spl_token::id(),
+            }
+            .to_account_metas(None),
+            data: instruction::RemoveLiquidity { lp_amount, min_amount_a, min_amount_b }.data(),
+        }
+    }
+
+    fn initialize_ix(&self, user: Pubkey, swap: Pubkey, fee_rate: u64) -> Instruction {
+        Instruction {
+            program_id: comprehensive_token_swap::id(),
+            accounts: accounts::Initialize {
+                swap,
+                user,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::Initialize { fee_rate }.data(),
+        }
+    }
+
+    // Opens a pool for the canonically ordered `mints` under `swap`, returning its address.
+    fn initialize_pool_ix(&self, user: Pubkey, swap: Pubkey, mints: [Pubkey; 2]) -> (Pubkey, Instruction) {
+        let program_id = comprehensive_token_swap::id();
+        let pool = Pubkey::find_program_address(&[POOL_SEED, swap.as_ref(), mints[0].as_ref(), mints[1].as_ref()], &program_id).0;
+        let [pool_token_a_account, pool_token_b_account] =
+            mints.map(|mint| Pubkey::find_program_address(&[VAULT_SEED, pool.as_ref(), mint.as_ref()], &program_id).0);
+        let initialize_pool_ix = Instruction {
+            program_id,
+            accounts: accounts::InitializePoolCtx {
+                user,
+                swap,
+                token_a_mint: mints[0],
+                token_b_mint: mints[1],
+                pool,
+                pool_authority: Pubkey::find_program_address(&[VAULT_AUTHORITY_SEED, pool.as_ref()], &program_id).0,
+                pool_token_a_account,
+                pool_token_b_account,
+                lp_mint: Pubkey::find_program_address(&[LP_MINT_SEED, pool.as_ref()], &program_id).0,
+                token_program: spl_token::id(),
+                system_program: system_program::ID,
+                rent: sysvar::rent::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::InitializePool {}.data(),
+        };
+        (pool, initialize_pool_ix)
+    }
+
+    fn swap_ix(&self, direction: SwapDirection, amount: u64, other_amount_threshold: u64) -> Instruction {
+        self.swap_with_mode_ix(direction, SwapMode::ExactIn, amount, other_amount_threshold)
+    }
+
+    fn swap_with_mode_ix(
+        &self,
+        direction: SwapDirection,
+        mode: SwapMode,
+        amount: u64,
+        other_amount_threshold: u64,
+    ) -> Instruction {
+        Instruction {
+            program_id: comprehensive_token_swap::id(),
+            accounts: accounts::SimpleSwapCtx {
+                user: self.user(),
+                pool: self.pool,
+                swap: self.swap,
+                pool_authority: self.pool_authority,
+                user_token_a_account: self.user_token_a_account,
+                user_token_b_account: self.user_token_b_account,
+                pool_token_a_account: self.pool_token_a_account,
+                pool_token_b_account: self.pool_token_b_account,
+                token_program: spl_token::id(),
+            }
+            .to_account_metas(None),
+            data: instruction::Swap {
+                direction,
+                mode,
+                amount,
+                other_amount_threshold,
+            }
+            .data(),
+        }
+    }
+
+    fn pause_ix(&self, operations: u8) -> Instruction {
+        Instruction {
+            program_id: comprehensive_token_swap::id(),
+            accounts: accounts::AdminCtx {
+                admin: self.user(),
+                swap: self.swap,
+            }
+            .to_account_metas(None),
+            data: instruction::Pause { operations }.data(),
+        }
+    }
+
+    fn unpause_ix(&self, operations: u8) -> Instruction {
+        Instruction {
+            program_id: comprehensive_token_swap::id(),
+            accounts: accounts::AdminCtx {
+                admin: self.user(),
+                swap: self.swap,
+            }
+            .to_account_metas(None),
+            data: instruction::Unpause { operations }.data(),
+        }
+    }
+
+    // One operation from the group a PAUSE_* flag halts, sized by `amount` so repeated
+    // sends are distinct transactions.
+    fn paused_operation_ixs(&self, operations: u8, order: Pubkey, amount: u64) -> Vec<Instruction> {
+        match operations {
+            PAUSE_SWAPS => vec![self.swap_ix(SwapDirection::AToB, amount, 0)],
+            PAUSE_DEPOSITS => vec![self.add_liquidity_ix(amount, amount, 0)],
+            PAUSE_WITHDRAWALS => vec![self.remove_liquidity_single_ix(SwapDirection::AToB, amount, 0)],
+            PAUSE_LIMIT_ORDERS => vec![self.place_limit_order_ix(order, limit_order(amount, 1))],
+            PAUSE_FLASH_SWAPS => vec![self.flash_borrow_ix(amount), self.flash_repay_ix(amount)],
+            _ => unreachable!("not a single PAUSE_* flag"),
+        }
+    }
+
+    fn configure_circuit_breaker_ix(
+        &self,
+        pool: Pubkey,
+        max_trade_move_bps: u16,
+        max_window_move_bps: u16,
+        window_slots: u64,
+        auto_pause: bool,
+    ) -> Instruction {
+        Instruction {
+            program_id: comprehensive_token_swap::id(),
+            accounts: accounts::PoolAdminCtx {
+                admin: self.user(),
+                swap: self.swap,
+                pool,
+            }
+            .to_account_metas(None),
+            data: instruction::ConfigureCircuitBreaker {
+                max_trade_move_bps,
+                max_window_move_bps,
+                window_slots,
+                auto_pause,
+            }
+            .data(),
+        }
+    }
+
+    fn reset_circuit_breaker_ix(&self) -> Instruction {
+        Instruction {
+            program_id: comprehensive_token_swap::id(),
+            accounts: accounts::PoolAdminCtx {
+                admin: self.user(),
+                swap: self.swap,
+                pool: self.pool,
+            }
+            .to_account_metas(None),
+            data: instruction::ResetCircuitBreaker {}.data(),
+        }
+    }
+
+    fn add_liquidity_single_ix(&self, direction: SwapDirection, amount_in: u64, min_lp_out: u64) -> Instruction {
+        Instruction {
+            program_id: comprehensive_token_swap::id(),
+            accounts: accounts::AddLiquiditySingleCtx {
+                user: self.user(),
+                pool: self.pool,
+                swap: self.swap,
+                pool_authority: self.pool_authority,
+                lp_mint: self.lp_mint,
+                user_lp_account: self.user_lp_account,
+                user_token_account: self.user_token_accounts(direction).0,
+                pool_token_account: self.pool_vaults(direction).0,
+                token_program: spl_token::id(),
+            }
+            .to_account_metas(None),
+            data: instruction::AddLiquiditySingle { direction, amount_in, min_lp_out }.data(),
+        }
+    }
+
+    fn remove_liquidity_single_ix(&self, direction: SwapDirection, lp_amount: u64, min_amount_out: u64) -> Instruction {
+        Instruction {
+            program_id: comprehensive_token_swap::id(),
+            accounts: accounts::RemoveLiquiditySingleCtx {
+                user: self.user(),
+                pool: self.pool,
+                swap: self.swap,
+                pool_authority: self.pool_authority,
+                lp_mint: self.lp_mint,
+                user_lp_account: self.user_lp_account,
+                user_token_account: self.user_token_accounts(direction).1,
+                pool_token_account: self.pool_vaults(direction).1,
+                token_program: spl_token::id(),
+            }
+            .to_account_metas(None),
+            data: instruction::RemoveLiquiditySingle { direction, lp_amount, min_amount_out }.data(),
+        }
+    }
+
+    fn simple_swap_ix(&self, amount_a: u64, min_amount_b: u64) -> Instruction {
+        // simple_swap takes the same accounts as `swap`.
+        let mut ix = self.swap_ix(SwapDirection::AToB, amount_a, min_amount_b);
+        ix.data = instruction::SimpleSwap { amount_a, min_amount_b }.data();
+        ix
+    }
+
+    // Token accounts a trade in `direction` sells from and pays out to.
+    fn user_token_accounts(&self, direction: SwapDirection) -> (Pubkey, Pubkey) {
+        match direction {
+            SwapDirection::AToB => (self.user_token_a_account, self.user_token_b_account),
+            SwapDirection::BToA => (self.user_token_b_account, self.user_token_a_account),
+        }
+    }
+
+    // (input, output) vaults for a trade along `direction`.
+    fn pool_vaults(&self, direction: SwapDirection) -> (Pubkey, Pubkey) {
+        match direction {
+            SwapDirection::AToB => (self.pool_token_a_account, self.pool_token_b_account),
+            SwapDirection::BToA => (self.pool_token_b_account, self.pool_token_a_account),
+        }
+    }
+
+    fn escrow(&self, order: Pubkey) -> Pubkey {
+        Pubkey::find_program_address(&[ESCROW_SEED, order.as_ref()], &comprehensive_token_swap::id()).0
+    }
+
+    fn place_limit_order_ix(&self, order: Pubkey, params: LimitOrderParams) -> Instruction {
+        self.place_order_ix(order, params, None)
+    }
+
+    // Rests the order on the pool's order book.
+    fn place_book_order_ix(&self, order: Pubkey, params: LimitOrderParams) -> Instruction {
+        self.place_order_ix(order, params, Some(self.order_book))
+    }
+
+    fn place_order_ix(&self, order: Pubkey, params: LimitOrderParams, order_book: Option<Pubkey>) -> Instruction {
+        let (user_token_account, destination) = self.user_token_accounts(params.direction);
+        let input_mint = match params.direction {
+            SwapDirection::AToB => self.token_a_mint,
+            SwapDirection::BToA => self.token_b_mint,
+        };
+        Instruction {
+            program_id: comprehensive_token_swap::id(),
+            accounts: accounts::PlaceLimitOrderCtx {
+                user: self.user(),
+                pool: self.pool,
+                swap: self.swap,
+                pool_authority: self.pool_authority,
+                user_order_book: self.user_order_book(),
+                order,
+                order_book,
+                input_mint,
+                escrow: self.escrow(order),
+                user_token_account,
+                destination,
+                token_program: spl_token::id(),
+                system_program: system_program::ID,
+                rent: sysvar::rent::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::PlaceLimitOrder { params }.data(),
+        }
+    }
+
+    fn execute_limit_order_ix(&self, order: Pubkey, fill_amount: u64, keeper_token_account: Pubkey) -> Instruction {
+        self.execute_against_book_ix(order, fill_amount, keeper_token_account, &[])
+    }
+
+    // Executes an A-to-B order, matching the given B-to-A makers of the user before the pool.
+    fn execute_against_book_ix(
+        &self,
+        order: Pubkey,
+        fill_amount: u64,
+        keeper_token_account: Pubkey,
+        makers: &[Pubkey],
+    ) -> Instruction {
+        let maker_metas = makers
+            .iter()
+            .flat_map(|maker| self.maker_metas(*maker, self.user(), self.user_order_book(), self.user_token_a_account))
+            .collect();
+        self.execute_with_maker_metas(order, fill_amount, keeper_token_account, maker_metas)
+    }
+
+    // The accounts a B-to-A maker is matched with: the order, its escrow and destination,
+    // and its owner with their UserOrderBook.
+    // Rests a book order of the keeper's selling token B, paid out to its token A account,
+    // and returns the order and the keeper's UserOrderBook.
+    async fn place_keeper_bid(&mut self, params: LimitOrderParams) -> (Pubkey, Pubkey) {
+        let program_id = comprehensive_token_swap::id();
+        let keeper = self.keeper.insecure_clone();
+        let keeper_book =
+            Pubkey::find_program_address(&[USER_ORDER_BOOK_SEED, keeper.pubkey().as_ref()], &program_id).0;
+        let maker = Pubkey::find_program_address(
+            &[ORDER_SEED, self.pool.as_ref(), keeper.pubkey().as_ref(), &0u64.to_le_bytes()],
+            &program_id,
+        )
+        .0;
+        let fund_ix = system_instruction::transfer(&self.user(), &keeper.pubkey(), 1_000_000_000);
+        let initialize_book_ix = Instruction {
+            program_id,
+            accounts: accounts::InitializeUserOrderBookCtx {
+                user: keeper.pubkey(),
+                user_order_book: keeper_book,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::InitializeUserOrderBook {}.data(),
+        };
+        let place_ix = Instruction {
+            program_id,
+            accounts: accounts::PlaceLimitOrderCtx {
+                user: keeper.pubkey(),
+                pool: self.pool,
+                swap: self.swap,
+                pool_authority: self.pool_authority,
+                user_order_book: keeper_book,
+                order: maker,
+                order_book: Some(self.order_book),
+                input_mint: self.token_b_mint,
+                escrow: self.escrow(maker),
+                user_token_account: self.keeper_token_b_account,
+                destination: self.keeper_token_a_account,
+                token_program: spl_token::id(),
+                system_program: system_program::ID,
+                rent: sysvar::rent::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::PlaceLimitOrder { params }.data(),
+        };
+        send(&mut self.context, &[fund_ix, initialize_book_ix, place_ix], &[&keeper]).await.unwrap();
+        (maker, keeper_book)
+    }
+
+    fn maker_metas(&self, maker: Pubkey, owner: Pubkey, owner_order_book: Pubkey, destination: Pubkey) -> Vec<AccountMeta> {
+        vec![
+            AccountMeta::new(maker, false),
+            AccountMeta::new(self.escrow(maker), false),
+            AccountMeta::new(destination, false),
+            AccountMeta::new(owner, false),
+            AccountMeta::new(owner_order_book, false),
+        ]
+    }
+
+    fn execute_with_maker_metas(
+        &self,
+        order: Pubkey,
+        fill_amount: u64,
+        keeper_token_account: Pubkey,
+        maker_metas: Vec<AccountMeta>,
+    ) -> Instruction {
+        let mut metas = accounts::ExecuteLimitOrderCtx {
+            keeper: self.keeper.pubkey(),
+            user: self.user(),
+            pool: self.pool,
+            swap: self.swap,
+            pool_authority: self.pool_authority,
+            order,
+            user_order_book: self.user_order_book(),
+            order_book: Some(self.order_book),
+            escrow: self.escrow(order),
+            pool_token_in_account: self.pool_token_a_account,
+            pool_token_out_account: self.pool_token_b_account,
+            destination: self.user_token_b_account,
+            keeper_token_account,
+            token_program: spl_token::id(),
+        }
+        .to_account_metas(None);
+        metas.extend(maker_metas);
+        Instruction {
+            program_id: comprehensive_token_swap::id(),
+            accounts: metas,
+            data: instruction::ExecuteLimitOrder { fill_amount }.data(),
+        }
+    }
+
+    // Fills an A-to-B order with the keeper's own token B.
+    fn fill_limit_order_ix(&self, order: Pubkey, fill_amount: u64, amount_out: u64) -> Instruction {
+        Instruction {
+            program_id: comprehensive_token_swap::id(),
+            accounts: accounts::FillLimitOrderCtx {
+                keeper: self.keeper.pubkey(),
+                user: self.user(),
+                pool: self.pool,
+                swap: self.swap,
+                pool_authority: self.pool_authority,
+                order,
+                user_order_book: self.user_order_book(),
+                order_book: None,
+                escrow: self.escrow(order),
+                destination: self.user_token_b_account,
+                keeper_token_in_account: self.keeper_token_a_account,
+                keeper_token_out_account: self.keeper_token_b_account,
+                token_program: spl_token::id(),
+            }
+            .to_account_metas(None),
+            data: instruction::FillLimitOrder { fill_amount, amount_out }.data(),
+        }
+    }
+
+    // Creates a B/C pool holding `liquidity` of each token and funds the user with token C.
+    async fn setup_route_pool(&mut self, liquidity: u64) -> RoutePool {
+        let payer = self.context.payer.insecure_clone();
+        let program_id = comprehensive_token_swap::id();
+        let token_c_mint = Keypair::new();
+        create_mint(&mut self.context.banks_client, &payer, &token_c_mint, &payer.pubkey()).await.unwrap();
+
+        let mut mints = [self.token_b_mint, token_c_mint.pubkey()];
+        mints.sort();
+        let (pool, initialize_pool_ix) = self.initialize_pool_ix(payer.pubkey(), self.swap, mints);
+        send(&mut self.context, &[initialize_pool_ix], &[]).await.unwrap();
+        let pool_authority = Pubkey::find_program_address(&[VAULT_AUTHORITY_SEED, pool.as_ref()], &program_id).0;
+        let [vault_0, vault_1] =
+            mints.map(|mint| Pubkey::find_program_address(&[VAULT_SEED, pool.as_ref(), mint.as_ref()], &program_id).0);
+        let lp_mint = Pubkey::find_program_address(&[LP_MINT_SEED, pool.as_ref()], &program_id).0;
+
+        let user_token_c_account = Keypair::new();
+        let user_lp_account = Keypair::new();
+        for (account, mint) in [(&user_token_c_account, token_c_mint.pubkey()), (&user_lp_account, lp_mint)] {
+            create_token_account(&mut self.context.banks_client, &payer, account, &mint, &payer.pubkey()).await.unwrap();
+        }
+        let user_token_c_account = user_token_c_account.pubkey();
+        mint_tokens(&mut self.context.banks_client, &payer, &token_c_mint.pubkey(), &user_token_c_account, liquidity * 10)
+            .await
+            .unwrap();
+
+        let user_accounts = if mints[0] == self.token_b_mint {
+            [self.user_token_b_account, user_token_c_account]
+        } else {
+            [user_token_c_account, self.user_token_b_account]
+        };
+        let add_liquidity_ix = Instruction {
+            program_id,
+            accounts: accounts::AddLiquidityCtx {
+                user: self.user(),
+                pool,
+                swap: self.swap,
+                pool_authority,
+                lp_mint,
+                user_lp_account: user_lp_account.pubkey(),
+                user_token_a_account: user_accounts[0],
+                user_token_b_account: user_accounts[1],
+                pool_token_a_account: vault_0,
+                pool_token_b_account: vault_1,
+                token_program: spl_token::id(),
+            }
+            .to_account_metas(None),
+            data: instruction::AddLiquidity { amount_a: liquidity, amount_b: liquidity, min_lp_out: 0 }.data(),
+        };
+        send(&mut self.context, &[add_liquidity_ix], &[]).await.unwrap();
+
+        let (pool_token_b_account, pool_token_c_account) =
+            if mints[0] == self.token_b_mint { (vault_0, vault_1) } else { (vault_1, vault_0) };
+        RoutePool {
+            pool,
+            pool_authority,
+            pool_token_b_account,
+            pool_token_c_account,
+            user_token_c_account,
+        }
+    }
+
+    // Routes through `hops`, each given as (pool, pool authority, input vault, output vault).
+    fn multi_token_swap_ix(
+        &self,
+        source: Pubkey,
+        destination: Pubkey,
+        amount: u64,
+        min_dst_amount: u64,
+        hops: &[(Pubkey, Pubkey, Pubkey, Pubkey)],
+    ) -> Instruction {
+        let mut metas = accounts::MultiTokenSwapCtx {
+            user: self.user(),
+            user_source_account: source,
+            user_destination_account: destination,
+            token_program: spl_token::id(),
+        }
+        .to_account_metas(None);
+        for &(pool, pool_authority, vault_in, vault_out) in hops {
+            metas.push(AccountMeta::new(pool, false));
+            metas.push(AccountMeta::new_readonly(self.swap, false));
+            metas.push(AccountMeta::new_readonly(pool_authority, false));
+            metas.push(AccountMeta::new(vault_in, false));
+            metas.push(AccountMeta::new(vault_out, false));
+        }
+        Instruction {
+            program_id: comprehensive_token_swap::id(),
+            accounts: metas,
+            data: instruction::MultiTokenSwap { amount, min_dst_amount }.data(),
+        }
+    }
+
+    fn cancel_limit_order_ix(&self, order: Pubkey) -> Instruction {
+        self.cancel_order_ix(order, None)
+    }
+
+    fn cancel_order_ix(&self, order: Pubkey, order_book: Option<Pubkey>) -> Instruction {
+        Instruction {
+            program_id: comprehensive_token_swap::id(),
+            accounts: accounts::CancelLimitOrderCtx {
+                user: self.user(),
+                pool: self.pool,
+                pool_authority: self.pool_authority,
+                order,
+                user_order_book: self.user_order_book(),
+                order_book,
+                escrow: self.escrow(order),
+                user_token_account: self.user_token_a_account,
+                token_program: spl_token::id(),
+            }
+            .to_account_metas(None),
+            data: instruction::CancelLimitOrder {}.data(),
+        }
+    }
+
+    // Closed by the keeper, which collects any reward in `caller_token_account`.
+    fn close_expired_order_ix(&self, order: Pubkey, caller_token_account: Option<Pubkey>) -> Instruction {
+        Instruction {
+            program_id: comprehensive_token_swap::id(),
+            accounts: accounts::CloseExpiredOrderCtx {
+                caller: self.keeper.pubkey(),
+                user: self.user(),
+                pool: self.pool,
+                pool_authority: self.pool_authority,
+                order,
+                user_order_book: self.user_order_book(),
+                order_book: None,
+                escrow: self.escrow(order),
+                user_token_account: self.user_token_a_account,
+                caller_token_account,
+                token_program: spl_token::id(),
+            }
+            .to_account_metas(None),
+            data: instruction::CloseExpiredOrder {}.data(),
+        }
+    }
+
+    // An A-to-B DCA order paying out to the user's token B account.
+    fn place_dca_order_ix(&self, dca_order: Pubkey, params: DcaOrderParams) -> Instruction {
+        Instruction {
+            program_id: comprehensive_token_swap::id(),
+            accounts: accounts::PlaceDcaOrderCtx {
+                user: self.user(),
+                pool: self.pool,
+                swap: self.swap,
+                pool_authority: self.pool_authority,
+                user_order_book: self.user_order_book(),
+                dca_order,
+                input_mint: self.token_a_mint,
+                escrow: self.escrow(dca_order),
+                user_token_account: self.user_token_a_account,
+                destination: self.user_token_b_account,
+                token_program: spl_token::id(),
+                system_program: system_program::ID,
+                rent: sysvar::rent::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::PlaceDcaOrder { params }.data(),
+        }
+    }
+
+    fn execute_dca_cycle_ix(&self, dca_order: Pubkey) -> Instruction {
+        Instruction {
+            program_id: comprehensive_token_swap::id(),
+            accounts: accounts::ExecuteDcaCycleCtx {
+                keeper: self.keeper.pubkey(),
+                user: self.user(),
+                pool: self.pool,
+                swap: self.swap,
+                pool_authority: self.pool_authority,
+                dca_order,
+                user_order_book: self.user_order_book(),
+                escrow: self.escrow(dca_order),
+                pool_token_in_account: self.pool_token_a_account,
+                pool_token_out_account: self.pool_token_b_account,
+                destination: self.user_token_b_account,
+                token_program: spl_token::id(),
+            }
+            .to_account_metas(None),
+            data: instruction::ExecuteDcaCycle {}.data(),
+        }
+    }
+
+    fn cancel_dca_order_ix(&self, dca_order: Pubkey) -> Instruction {
+        Instruction {
+            program_id: comprehensive_token_swap::id(),
+            accounts: accounts::CancelDcaOrderCtx {
+                user: self.user(),
+                pool: self.pool,
+                pool_authority: self.pool_authority,
+                dca_order,
+                user_order_book: self.user_order_book(),
+                escrow: self.escrow(dca_order),
+                user_token_account: self.user_token_a_account,
+                token_program: spl_token::id(),
+            }
+            .to_account_metas(None),
+            data: instruction::CancelDcaOrder {}.data(),
+        }
+    }
+
+    fn place_long_term_order_ix(
+        &self,
+        order: Pubkey,
+        direction: SwapDirection,
+        amount_in: u64,
+        intervals: u64,
+    ) -> Instruction {
+        let (pool_token_in_account, _) = self.pool_vaults(direction);
+        Instruction {
+            program_id: comprehensive_token_swap::id(),
+            accounts: accounts::PlaceLongTermOrderCtx {
+                user: self.user(),
+                pool: self.pool,
+                swap: self.swap,
+                user_order_book: self.user_order_book(),
+                long_term_order: order,
+                user_token_account: self.user_token_accounts(direction).0,
+                pool_token_in_account,
+                token_program: spl_token::id(),
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::PlaceLongTermOrder { direction, amount_in, intervals }.data(),
+        }
+    }
+
+    fn withdraw_long_term_proceeds_ix(&self, order: Pubkey, direction: SwapDirection) -> Instruction {
+        Instruction {
+            program_id: comprehensive_token_swap::id(),
+            accounts: accounts::WithdrawLongTermProceedsCtx {
+                user: self.user(),
+                pool: self.pool,
+                swap: self.swap,
+                pool_authority: self.pool_authority,
+                long_term_order: order,
+                user_order_book: self.user_order_book(),
+                pool_token_out_account: self.pool_vaults(direction).1,
+                user_token_out_account: self.user_token_accounts(direction).1,
+                token_program: spl_token::id(),
+            }
+            .to_account_metas(None),
+            data: instruction::WithdrawLongTermProceeds {}.data(),
+        }
+    }
+
+    // Settles an expired order of the user's on the keeper's behalf.
+    fn settle_long_term_order_ix(&self, order: Pubkey, direction: SwapDirection) -> Instruction {
+        Instruction {
+            program_id: comprehensive_token_swap::id(),
+            accounts: accounts::SettleLongTermOrderCtx {
+                caller: self.keeper.pubkey(),
+                user: self.user(),
+                pool: self.pool,
+                swap: self.swap,
+                pool_authority: self.pool_authority,
+                long_term_order: order,
+                user_order_book: self.user_order_book(),
+                pool_token_out_account: self.pool_vaults(direction).1,
+                user_token_out_account: self.user_token_accounts(direction).1,
+                token_program: spl_token::id(),
+            }
+            .to_account_metas(None),
+            data: instruction::SettleLongTermOrder {}.data(),
+        }
+    }
+
+    fn execute_virtual_orders_ix(&self, max_expiries: u8) -> Instruction {
+        Instruction {
+            program_id: comprehensive_token_swap::id(),
+            accounts: accounts::ExecuteVirtualOrdersCtx { pool: self.pool, swap: self.swap }.to_account_metas(None),
+            data: instruction::ExecuteVirtualOrders { max_expiries }.data(),
+        }
+    }
+
+    fn cancel_long_term_order_ix(&self, order: Pubkey, direction: SwapDirection) -> Instruction {
+        let (pool_token_in_account, pool_token_out_account) = self.pool_vaults(direction);
+        let (user_token_in_account, user_token_out_account) = self.user_token_accounts(direction);
+        Instruction {
+            program_id: comprehensive_token_swap::id(),
+            accounts: accounts::CancelLongTermOrderCtx {
+                user: self.user(),
+                pool: self.pool,
+                swap: self.swap,
+                pool_authority: self.pool_authority,
+                long_term_order: order,
+                user_order_book: self.user_order_book(),
+                pool_token_in_account,
+                pool_token_out_account,
+                user_token_in_account,
+                user_token_out_account,
+                token_program: spl_token::id(),
+            }
+            .to_account_metas(None),
+            data: instruction::CancelLongTermOrder {}.data(),
+        }
+    }
+
+    fn set_flash_fee_ix(&self, admin: Pubkey, flash_fee_bps: u16) -> Instruction {
+        Instruction {
+            program_id: comprehensive_token_swap::id(),
+            accounts: accounts::PoolAdminCtx {
+                admin,
+                swap: self.swap,
+                pool: self.pool,
+            }
+            .to_account_metas(None),
+            data: instruction::SetFlashFee { flash_fee_bps }.data(),
+        }
+    }
+
+    fn flash_borrow_ix(&self, amount: u64) -> Instruction {
+        Instruction {
+            program_id: comprehensive_token_swap::id(),
+            accounts: accounts::FlashBorrowCtx {
+                user: self.user(),
+                pool: self.pool,
+                swap: self.swap,
+                pool_authority: self.pool_authority,
+                user_token_a_account: self.user_token_a_account,
+                pool_token_a_account: self.pool_token_a_account,
+                instructions: sysvar::instructions::ID,
+                token_program: spl_token::id(),
+            }
+            .to_account_metas(None),
+            data: instruction::FlashBorrow { amount }.data(),
+        }
+    }
+
+    fn flash_repay_ix(&self, amount: u64) -> Instruction {
+        Instruction {
+            program_id: comprehensive_token_swap::id(),
+            accounts: accounts::FlashRepayCtx {
+                user: self.user(),
+                pool: self.pool,
+                user_token_a_account: self.user_token_a_account,
+                pool_token_a_account: self.pool_token_a_account,
+                token_program: spl_token::id(),
+            }
+            .to_account_metas(None),
+            data: instruction::FlashRepay { amount }.data(),
+        }
+    }
+
+    fn repayment_accounts(&self, borrower: Pubkey) -> Vec<AccountMeta> {
+        vec![
+            AccountMeta::new_readonly(borrower, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new(self.user_token_a_account, false),
+            AccountMeta::new(self.pool_token_a_account, false),
+            AccountMeta::new_readonly(self.user(), true),
+        ]
+    }
+
+    fn flash_swap_ix(&self, amount_a: u64, target_contract: Pubkey, callback_accounts: Vec<AccountMeta>) -> Instruction {
+        let mut metas = accounts::FlashSwapCtx {
+            user: self.user(),
+            pool: self.pool,
+            swap: self.swap,
+            pool_authority: self.pool_authority,
+            user_token_a_account: self.user_token_a_account,
+            pool_token_a_account: self.pool_token_a_account,
+            token_program: spl_token::id(),
+        }
+        .to_account_metas(None);
+        metas.extend(callback_accounts);
+        Instruction {
+            program_id: comprehensive_token_swap::id(),
+            accounts: metas,
+            data: instruction::FlashSwap { amount_a, target_contract }.data(),
+        }
+    }
+}
+
+// An A-to-B limit order with no keeper fee that never expires.
+fn limit_order(amount_in: u64, target_price: u64) -> LimitOrderParams {
+    LimitOrderParams {
+        direction: SwapDirection::AToB,
+        amount_in,
+        target_price,
+        expiration: i64::MAX,
+        partial_fill: false,
+        min_fill_amount: 0,
+        keeper_fee: KeeperFee::Fixed { amount: 0 },
+        order_type: OrderType::Limit,
+        trigger_price: 0,
+    }
+}
+
+fn program_test() -> ProgramTest {
+    ProgramTest::new(
+        "comprehensive_token_swap",
+        comprehensive_token_swap::id(),
+        processor!(process_instruction),
+    )
+}
+
+// Anchor's entrypoint ties the account slice to the accounts' own lifetime, which the
+// builtin processor signature cannot promise, so hand it a leaked copy of the slice.
+fn process_instruction(program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    let accounts = Box::leak(Box::new(accounts.to_vec()));
+    comprehensive_token_swap::entry(program_id, accounts, data)
+}
+
+async fn setup_pool(program_test: ProgramTest, liquidity: u64) -> PoolFixture {
+    let mut context = program_test.start_with_context().await;
+    let payer = context.payer.insecure_clone();
+    let program_id = comprehensive_token_swap::id();
+    let swap = Keypair::new();
+
+    // Pools only accept their mints in canonical order.
+    let mut mints = [Keypair::new(), Keypair::new()];
+    mints.sort_by_key(|mint| mint.pubkey());
+    let [token_a_mint, token_b_mint] = mints;
+    create_mint(&mut context.banks_client, &payer, &token_a_mint, &payer.pubkey()).await.unwrap();
+    create_mint(&mut context.banks_client, &payer, &token_b_mint, &payer.pubkey()).await.unwrap();
+
+    let (pool, _) = Pubkey::find_program_address(
+        &[POOL_SEED, swap.pubkey().as_ref(), token_a_mint.pubkey().as_ref(), token_b_mint.pubkey().as_ref()],
+        &program_id,
+    );
+    let (pool_authority, _) = Pubkey::find_program_address(&[VAULT_AUTHORITY_SEED, pool.as_ref()], &program_id);
+    let (pool_token_a_account, _) =
+        Pubkey::find_program_address(&[VAULT_SEED, pool.as_ref(), token_a_mint.pubkey().as_ref()], &program_id);
+    let (pool_token_b_account, _) =
+        Pubkey::find_program_address(&[VAULT_SEED, pool.as_ref(), token_b_mint.pubkey().as_ref()], &program_id);
+    let (lp_mint, _) = Pubkey::find_program_address(&[LP_MINT_SEED, pool.as_ref()], &program_id);
+
+    let initialize_ix = Instruction {
+        program_id,
+        accounts: accounts::Initialize {
+            swap: swap.pubkey(),
+            user: payer.pubkey(),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::Initialize { fee_rate: 3 }.data(),
+    };
+    let initialize_pool_ix = Instruction {
+        program_id,
+        accounts: accounts::InitializePoolCtx {
+            user: payer.pubkey(),
+            swap: swap.pubkey(),
+            token_a_mint: token_a_mint.pubkey(),
+            token_b_mint: token_b_mint.pubkey(),
+            pool,
+            pool_authority,
+            pool_token_a_account,
+            pool_token_b_account,
+            lp_mint,
+            token_program: spl_token::id(),
+            system_program: system_program::ID,
+            rent: sysvar::rent::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::InitializePool {}.data(),
+    };
+    send(&mut context, &[initialize_ix, initialize_pool_ix], &[&swap]).await.unwrap();
+
+    let user_token_a_account = Keypair::new();
+    let user_token_b_account = Keypair::new();
+    let user_lp_account = Keypair::new();
+    for (account, mint) in [
+        (&user_token_a_account, token_a_mint.pubkey()),
+        (&user_token_b_account, token_b_mint.pubkey()),
+        (&user_lp_account, lp_mint),
+    ] {
+        create_token_account(&mut context.banks_client, &payer, account, &mint, &payer.pubkey()).await.unwrap();
+    }
+    let keeper = Keypair::new();
+    let keeper_token_a_account = Keypair::new();
+    let keeper_token_b_account = Keypair::new();
+    for (account, mint) in [(&keeper_token_a_account, token_a_mint.pubkey()), (&keeper_token_b_account, token_b_mint.pubkey())] {
+        create_token_account(&mut context.banks_client, &payer, account, &mint, &keeper.pubkey()).await.unwrap();
+    }
+    mint_tokens(&mut context.banks_client, &payer, &token_a_mint.pubkey(), &user_token_a_account.pubkey(), liquidity * 10).await.unwrap();
+    mint_tokens(&mut context.banks_client, &payer, &token_b_mint.pubkey(), &user_token_b_account.pubkey(), liquidity * 10).await.unwrap();
+
+    let mut fixture = PoolFixture {
+        context,
+        swap: swap.pubkey(),
+        pool,
+        token_a_mint: token_a_mint.pubkey(),
+        token_b_mint: token_b_mint.pubkey(),
+        pool_authority,
+        pool_token_a_account,
+        pool_token_b_account,
+        lp_mint,
+        user_token_a_account: user_token_a_account.pubkey(),
+        user_token_b_account: user_token_b_account.pubkey(),
+        user_lp_account: user_lp_account.pubkey(),
+        keeper,
+        keeper_token_a_account: keeper_token_a_account.pubkey(),
+        keeper_token_b_account: keeper_token_b_account.pubkey(),
+        order_book: Pubkey::find_program_address(&[ORDER_BOOK_SEED, pool.as_ref()], &program_id).0,
+    };
+    let initialize_book_ix = Instruction {
+        program_id,
+        accounts: accounts::InitializeUserOrderBookCtx {
+            user: fixture.user(),
+            user_order_book: fixture.user_order_book(),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::InitializeUserOrderBook {}.data(),
+    };
+    let initialize_order_book_ix = Instruction {
+        program_id,
+        accounts: accounts::InitializeOrderBookCtx {
+            payer: fixture.user(),
+            pool: fixture.pool,
+            swap: fixture.swap,
+            order_book: fixture.order_book,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::InitializeOrderBook { min_ask_amount: 1_000, min_bid_amount: 1_000 }.data(),
+    };
+    let add_liquidity_ix = fixture.add_liquidity_ix(liquidity, liquidity, 0);
+    send(&mut fixture.context, &[initialize_book_ix, initialize_order_book_ix, add_liquidity_ix], &[]).await.unwrap();
+    fixture
+}
+
+fn assert_program_error(result: std::result::Result<(), BanksClientError>, error: ErrorCode) {
+    match result.unwrap_err().unwrap() {
+        TransactionError::InstructionError(_, InstructionError::Custom(code)) => assert_eq!(code, u32::from(error)),
+        other => panic!("expected {error:?}, got {other:?}"),
+    }
+}
+
+async fn send(
+    context: &mut ProgramTestContext,
+    instructions: &[Instruction],
+    signers: &[&Keypair],
+) -> std::result::Result<(), BanksClientError> {
+    let mut all_signers = vec![&context.payer];
+    all_signers.extend_from_slice(signers);
+    let blockhash = context.banks_client.get_latest_blockhash().await.unwrap();
+    let transaction =
+        Transaction::new_signed_with_payer(instructions, Some(&context.payer.pubkey()), &all_signers, blockhash);
+    context.banks_client.process_transaction(transaction).await
+}
+
+// Waits for the next blockhash, so that re-sending an identical transaction runs it
+// again instead of returning the status cached for the first one.
+async fn refresh_blockhash(context: &mut ProgramTestContext) {
+    let blockhash = context.banks_client.get_latest_blockhash().await.unwrap();
+    context.banks_client.get_new_latest_blockhash(&blockhash).await.unwrap();
+}
+
+// Helper functions for test setup
+async fn create_mint(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    mint: &Keypair,
+    authority: &Pubkey,
+) -> std::result::Result<(), BanksClientError> {
+    let rent = banks_client.get_rent().await.unwrap();
+    let mint_space = spl_token::state::Mint::LEN;
+    let mint_rent = rent.minimum_balance(mint_space);
+
+    let create_mint_ix = system_instruction::create_account(
+        &payer.pubkey(),
+        &mint.pubkey(),
+        mint_rent,
+        mint_space as u64,
+        &spl_token::id(),
+    );
+
+    let initialize_mint_ix = spl_token::instruction::initialize_mint(
+        &spl_token::id(),
+        &mint.pubkey(),
+        authority,
+        None,
+        0,
+    )
+    .unwrap();
+
+    let transaction = Transaction::new_signed_with_payer(
+        &[create_mint_ix, initialize_mint_ix],
+        Some(&payer.pubkey()),
+        &[payer, mint],
+        banks_client.get_latest_blockhash().await.unwrap(),
+    );
+
+    banks_client.process_transaction(transaction).await?;
+    Ok(())
+}
+
+async fn create_token_account(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    token_account: &Keypair,
+    mint: &Pubkey,
+    owner: &Pubkey,
+) -> std::result::Result<(), BanksClientError> {
+    let rent = banks_client.get_rent().await.unwrap();
+    let token_account_space = spl_token::state::Account::LEN;
+    let token_account_rent = rent.minimum_balance(token_account_space);
+
+    let create_token_account_ix = system_instruction::create_account(
+        &payer.pubkey(),
+        &token_account.pubkey(),
+        token_account_rent,
+        token_account_space as u64,
+        &spl_token::id(),
+    );
+
+    let initialize_token_account_ix = spl_token::instruction::initialize_account(
+        &spl_token::id(),
+        &token_account.pubkey(),
+        mint,
+        owner,
+    )
+    .unwrap();
+
+    let transaction = Transaction::new_signed_with_payer(
+        &[create_token_account_ix, initialize_token_account_ix],
+        Some(&payer.pubkey()),
+        &[payer, token_account],
+        banks_client.get_latest_blockhash().await.unwrap(),
+    );
+
+    banks_client.process_transaction(transaction).await?;
+    Ok(())
+}
+
+async fn mint_tokens(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    mint: &Pubkey,
+    token_account: &Pubkey,
+    amount: u64,
+) -> std::result::Result<(), BanksClientError> {
+    let mint_to_ix = spl_token::instruction::mint_to(
+        &spl_token::id(),
+        mint,
+        token_account,
+        &payer.pubkey(),
+        &[],
+        amount,
+    )
+    .unwrap();
+
+    let transaction = Transaction::new_signed_with_payer(
+        &[mint_to_ix],
+        Some(&payer.pubkey()),
+        &[payer],
+        banks_client.get_latest_blockhash().await.unwrap(),
+    );
+
+    banks_client.process_transaction(transaction).await?;
+    Ok(())
+}